@@ -1,10 +1,8 @@
 use anyhow::Result;
-use async_prost::AsyncProstStream;
-use futures::prelude::*;
 use tokio::net::TcpStream;
 use tracing::info;
 
-use kv::{CommandRequest, CommandResponse};
+use kv::{CommandRequest, ProstClientStream};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,18 +12,15 @@ async fn main() -> Result<()> {
     // 连接服务器
     let stream = TcpStream::connect(addr).await?;
 
-    // 使用 AsyncProstStream 来处理 TCP Frame
-    let mut client =
-        AsyncProstStream::<_, CommandResponse, CommandRequest, _>::from(stream).for_async();
+    // 使用 ProstClientStream 来处理 TCP Frame
+    let mut client = ProstClientStream::new(stream);
 
     // 生成一个 HSET 命令
     let cmd = CommandRequest::new_hset("table1", "hello", "world".into());
 
-    // 发送 HSET 命令
-    client.send(cmd).await?;
-    if let Some(Ok(data)) = client.next().await {
-        info!("Got response {:?}", data);
-    }
+    // 发送 HSET 命令并等待响应
+    let data = client.execute(cmd).await?;
+    info!("Got response {:?}", data);
 
     Ok(())
 }
@@ -1,10 +1,8 @@
 use anyhow::Result;
-use async_prost::AsyncProstStream;
-use futures::prelude::*;
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
 
-use kv::{memory::MemTable, CommandRequest, CommandResponse, Service, ServiceInner};
+use kv::{memory::MemTable, ProstServerStream, Service, ServiceInner};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -17,13 +15,10 @@ async fn main() -> Result<()> {
     loop {
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
-        let svc = service.clone();
+        let stream = ProstServerStream::new(stream, service.clone());
         tokio::spawn(async move {
-            let mut stream =
-                AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
-            while let Some(Ok(cmd)) = stream.next().await {
-                let res = svc.execute(cmd);
-                stream.send(res).await.unwrap();
+            if let Err(e) = stream.process().await {
+                warn!("Failed to process client {:?}: {:?}", addr, e);
             }
             info!("Client {:?} disconnected", addr);
         });
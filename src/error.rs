@@ -6,13 +6,25 @@ pub enum KvError {
     #[error("Not found for table: {0}, key: {1}")]
     NotFound(String, String),
 
+    #[error("Table not found: {0}")]
+    TableNotFound(String),
+
+    #[error("Table already exists: {0}")]
+    TableExists(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("Cannot parse command: `{0}`")]
     InvalidCommand(String),
 
-    #[error("Connot convert value {:0} to {1}")]
+    #[error("Connot convert value {0:?} to {1}")]
     ConvertError(Value, &'static str),
 
-    #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {}")]
+    #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
     StorageError(&'static str, String, String, String),
 
     #[error("Failed to encode protobuf message")]
@@ -24,6 +36,49 @@ pub enum KvError {
     #[error["Failed to access sled db"]]
     SledError(#[from] sled::Error),
 
+    #[error("Failed to access rocksdb: {0}")]
+    RocksDbError(String),
+
+    #[error("Invalid frame: {0}")]
+    FrameError(String),
+
+    #[error("I/O error: {0}")]
+    IoError(String),
+
+    #[error("TLS error: {0}")]
+    TlsError(String),
+
+    #[error("Cannot parse {0}: {1}")]
+    CertificateParseError(&'static str, String),
+
+    #[error("Yamux connection error: {0}")]
+    YamuxConnectionError(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
+
+impl From<std::io::Error> for KvError {
+    fn from(e: std::io::Error) -> Self {
+        KvError::IoError(e.to_string())
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl From<rocksdb::Error> for KvError {
+    fn from(e: rocksdb::Error) -> Self {
+        KvError::RocksDbError(e.into_string())
+    }
+}
+
+impl From<tokio_rustls::rustls::Error> for KvError {
+    fn from(e: tokio_rustls::rustls::Error) -> Self {
+        KvError::TlsError(e.to_string())
+    }
+}
+
+impl From<yamux::ConnectionError> for KvError {
+    fn from(e: yamux::ConnectionError) -> Self {
+        KvError::YamuxConnectionError(e.to_string())
+    }
+}
@@ -1,9 +1,11 @@
 mod error;
+mod network;
 mod pb;
 mod service;
 mod storage;
 
 pub use error::*;
+pub use network::*;
 pub use pb::abi::*;
 pub use service::*;
 pub use storage::*;
@@ -0,0 +1,439 @@
+use std::io::{Read, Write};
+
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+use crate::{CommandRequest, CommandResponse, KvError};
+
+/// a frame starts with a big-endian u32 header
+pub const LEN_LEN: usize = 4;
+/// the top two bits of the header carry the compression flag
+const COMPRESSION_SHIFT: u32 = 30;
+/// the remaining 30 bits carry the payload length, so a frame is at most 1 GiB
+pub const MAX_FRAME: usize = (1 << COMPRESSION_SHIFT) - 1;
+/// the largest payload a peer may send by default, compressed or not
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+/// payloads above this size are compressed by default,
+/// ethernet MTU 1500 minus the IP header, the TCP header and our own header
+pub const COMPRESSION_LIMIT: usize = 1436;
+
+/// how the payload of a frame is compressed, encoded in the frame header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Lz4,
+}
+
+impl Compression {
+    fn flag(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+            Compression::Lz4 => 2,
+        }
+    }
+
+    fn from_flag(flag: u32) -> Result<Self, KvError> {
+        match flag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Lz4),
+            v => Err(KvError::FrameError(format!(
+                "unknown compression flag {}",
+                v
+            ))),
+        }
+    }
+}
+
+/// which compression to use for outgoing frames, and from what payload size on.
+/// Incoming frames whose payload is larger than `max_frame`, before or after decompression,
+/// are rejected before any of it is read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameConfig {
+    pub compression: Compression,
+    pub threshold: usize,
+    pub max_frame: usize,
+}
+
+impl FrameConfig {
+    pub fn new(compression: Compression, threshold: usize) -> Self {
+        Self {
+            compression,
+            threshold,
+            max_frame: DEFAULT_MAX_FRAME,
+        }
+    }
+
+    pub fn with_max_frame(mut self, max_frame: usize) -> Self {
+        self.max_frame = max_frame.min(MAX_FRAME);
+        self
+    }
+
+    /// `KvError::FrameError` if a payload of the length may not be sent or received
+    pub(crate) fn check_len(&self, len: usize) -> Result<(), KvError> {
+        if len > self.max_frame {
+            return Err(KvError::FrameError(format!(
+                "frame too large: {} bytes, at most {} allowed",
+                len, self.max_frame
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self::new(Compression::Gzip, COMPRESSION_LIMIT)
+    }
+}
+
+pub trait FrameCoder
+where
+    Self: Message + Sized + Default,
+{
+    /// encode a message into a frame using the default config
+    fn encode_frame(&self, buf: &mut BytesMut) -> Result<(), KvError> {
+        self.encode_frame_with(buf, &FrameConfig::default())
+    }
+
+    /// encode a message into a frame, compressing it when it is larger than the threshold.
+    /// A message the peer would reject as too large, before or after compression,
+    /// fails without leaving anything in the buffer.
+    fn encode_frame_with(&self, buf: &mut BytesMut, config: &FrameConfig) -> Result<(), KvError> {
+        let size = self.encoded_len();
+        if size > MAX_FRAME {
+            return Err(KvError::FrameError(format!("frame too large: {}", size)));
+        }
+        config.check_len(size)?;
+
+        let compression = if size > config.threshold {
+            config.compression
+        } else {
+            Compression::None
+        };
+
+        // the header is written once the length of the (compressed) payload is known
+        let header_pos = buf.len();
+        buf.put_u32(0);
+        let start = buf.len();
+
+        match compression {
+            Compression::None => self.encode(buf)?,
+            Compression::Gzip => {
+                let mut payload = Vec::with_capacity(size);
+                self.encode(&mut payload)?;
+                let mut encoder = GzEncoder::new(buf.writer(), flate2::Compression::default());
+                encoder.write_all(&payload)?;
+                encoder.finish()?;
+            }
+            Compression::Lz4 => {
+                let mut payload = Vec::with_capacity(size);
+                self.encode(&mut payload)?;
+                buf.put_slice(&lz4_flex::compress_prepend_size(&payload));
+            }
+        }
+
+        let len = buf.len() - start;
+        if len > MAX_FRAME {
+            buf.truncate(header_pos);
+            return Err(KvError::FrameError(format!("frame too large: {}", len)));
+        }
+        if let Err(e) = config.check_len(len) {
+            buf.truncate(header_pos);
+            return Err(e);
+        }
+        debug!("Encode a frame: size {}({}), {:?}", size, len, compression);
+
+        let header = (compression.flag() << COMPRESSION_SHIFT) | len as u32;
+        buf[header_pos..start].copy_from_slice(&header.to_be_bytes());
+        Ok(())
+    }
+
+    /// decode a whole frame into a message using the default config
+    fn decode_frame(buf: &mut BytesMut) -> Result<Self, KvError> {
+        Self::decode_frame_with(buf, &FrameConfig::default())
+    }
+
+    /// decode a whole frame into a message, rejecting payloads larger than the config allows.
+    /// The buffer is only consumed once the header is valid and the payload complete.
+    fn decode_frame_with(buf: &mut BytesMut, config: &FrameConfig) -> Result<Self, KvError> {
+        if buf.len() < LEN_LEN {
+            return Err(KvError::FrameError("missing frame header".into()));
+        }
+
+        let header = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let (len, compression) = decode_header(header);
+        let compression = Compression::from_flag(compression)?;
+        config.check_len(len)?;
+        if buf.len() - LEN_LEN < len {
+            return Err(KvError::FrameError(format!(
+                "incomplete frame: expect {} bytes, got {}",
+                len,
+                buf.len() - LEN_LEN
+            )));
+        }
+        buf.advance(LEN_LEN);
+        debug!("Got a frame: len {}, {:?}", len, compression);
+
+        let payload = buf.split_to(len);
+        match compression {
+            Compression::None => Ok(Self::decode(payload)?),
+            Compression::Gzip => {
+                let mut data = Vec::with_capacity(len * 2);
+                GzDecoder::new(&payload[..])
+                    .take(config.max_frame as u64 + 1)
+                    .read_to_end(&mut data)?;
+                config.check_len(data.len())?;
+                Ok(Self::decode(&data[..])?)
+            }
+            Compression::Lz4 => {
+                if payload.len() < LEN_LEN {
+                    return Err(KvError::FrameError("missing lz4 size".into()));
+                }
+                let size = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                config.check_len(size as usize)?;
+                let data = lz4_flex::decompress_size_prepended(&payload)
+                    .map_err(|e| KvError::FrameError(e.to_string()))?;
+                Ok(Self::decode(&data[..])?)
+            }
+        }
+    }
+}
+
+impl FrameCoder for CommandRequest {}
+impl FrameCoder for CommandResponse {}
+
+/// split a header into the payload length and the compression flag
+pub(crate) fn decode_header(header: u32) -> (usize, u32) {
+    let len = header as usize & MAX_FRAME;
+    (len, header >> COMPRESSION_SHIFT)
+}
+
+/// read a whole frame, header included, from the stream into buf using the default config
+pub async fn read_frame<S>(stream: &mut S, buf: &mut BytesMut) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    read_frame_with(stream, buf, &FrameConfig::default()).await
+}
+
+/// read a whole frame, header included, from the stream into buf. A header announcing a payload
+/// larger than the config allows is rejected before anything is allocated for it.
+pub async fn read_frame_with<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    config: &FrameConfig,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    let header = stream.read_u32().await?;
+    let (len, _) = decode_header(header);
+    config.check_len(len)?;
+
+    buf.reserve(LEN_LEN + len);
+    buf.put_u32(header);
+    let start = buf.len();
+    buf.resize(start + len, 0);
+    stream.read_exact(&mut buf[start..]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, Value};
+
+    #[test]
+    fn command_request_encode_decode_should_work() {
+        let mut buf = BytesMut::new();
+
+        let cmd = CommandRequest::new_hdel("t1", "k1");
+        cmd.encode_frame(&mut buf).unwrap();
+
+        assert!(!is_compressed(&buf));
+
+        let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
+        assert_eq!(cmd, cmd1);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn command_response_encode_decode_should_work() {
+        let mut buf = BytesMut::new();
+
+        let values: Vec<Value> = vec![1.into(), "hello".into(), b"data".to_vec().into()];
+        let res: CommandResponse = values.into();
+        res.encode_frame(&mut buf).unwrap();
+
+        assert!(!is_compressed(&buf));
+
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        assert_eq!(res, res1);
+    }
+
+    #[test]
+    fn command_response_gzip_encode_decode_should_work() {
+        let mut buf = BytesMut::new();
+
+        let res = large_response();
+        res.encode_frame(&mut buf).unwrap();
+
+        assert_eq!(compression_of(&buf), Compression::Gzip);
+        assert!(buf.len() < res.encoded_len());
+
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        assert_eq!(res, res1);
+    }
+
+    #[test]
+    fn command_response_lz4_encode_decode_should_work() {
+        let mut buf = BytesMut::new();
+
+        let res = large_response();
+        let config = FrameConfig::new(Compression::Lz4, COMPRESSION_LIMIT);
+        res.encode_frame_with(&mut buf, &config).unwrap();
+
+        assert_eq!(compression_of(&buf), Compression::Lz4);
+        assert!(buf.len() < res.encoded_len());
+
+        let res1 = CommandResponse::decode_frame(&mut buf).unwrap();
+        assert_eq!(res, res1);
+    }
+
+    #[test]
+    fn compression_threshold_should_be_configurable() {
+        let mut buf = BytesMut::new();
+
+        let res = large_response();
+        let config = FrameConfig::new(Compression::Gzip, res.encoded_len());
+        res.encode_frame_with(&mut buf, &config).unwrap();
+        assert!(!is_compressed(&buf));
+        buf.clear();
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let config = FrameConfig::new(Compression::Lz4, 0);
+        cmd.encode_frame_with(&mut buf, &config).unwrap();
+        assert_eq!(compression_of(&buf), Compression::Lz4);
+
+        let cmd1 = CommandRequest::decode_frame(&mut buf).unwrap();
+        assert_eq!(cmd, cmd1);
+    }
+
+    #[test]
+    fn decode_incomplete_frame_should_fail() {
+        let mut buf = BytesMut::new();
+        CommandRequest::new_hget("t1", "k1")
+            .encode_frame(&mut buf)
+            .unwrap();
+        buf.truncate(buf.len() - 1);
+        let len = buf.len();
+
+        let res = CommandRequest::decode_frame(&mut buf);
+        assert!(matches!(res, Err(KvError::FrameError(_))));
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn decode_oversized_frame_should_fail_without_consuming_it() {
+        let mut buf = BytesMut::new();
+        large_response().encode_frame(&mut buf).unwrap();
+        let len = buf.len();
+
+        let config = FrameConfig::default().with_max_frame(16);
+        let res = CommandResponse::decode_frame_with(&mut buf, &config);
+        assert!(matches!(res, Err(KvError::FrameError(_))));
+        assert_eq!(buf.len(), len);
+    }
+
+    #[test]
+    fn encode_oversized_frame_should_fail() {
+        let mut buf = BytesMut::from(&b"rest"[..]);
+        let res = large_response();
+
+        // too large uncompressed, so the peer would reject it after decompression
+        let config = FrameConfig::default().with_max_frame(res.encoded_len() - 1);
+        let r = res.encode_frame_with(&mut buf, &config);
+        assert!(matches!(r, Err(KvError::FrameError(_))));
+        assert_eq!(&buf[..], b"rest");
+
+        // small enough uncompressed, but gzip adds its header and trailer to a tiny payload
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let config = FrameConfig::new(Compression::Gzip, 0).with_max_frame(cmd.encoded_len());
+        let r = cmd.encode_frame_with(&mut buf, &config);
+        assert!(matches!(r, Err(KvError::FrameError(_))));
+        assert_eq!(&buf[..], b"rest");
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_work() {
+        let mut buf = BytesMut::new();
+        let cmd = CommandRequest::new_hdel("t1", "k1");
+        cmd.encode_frame(&mut buf).unwrap();
+        let res = large_response();
+        res.encode_frame(&mut buf).unwrap();
+
+        let mut stream = &buf[..];
+        let mut data = BytesMut::new();
+
+        read_frame(&mut stream, &mut data).await.unwrap();
+        let cmd1 = CommandRequest::decode_frame(&mut data).unwrap();
+        assert_eq!(cmd, cmd1);
+
+        read_frame(&mut stream, &mut data).await.unwrap();
+        let res1 = CommandResponse::decode_frame(&mut data).unwrap();
+        assert_eq!(res, res1);
+    }
+
+    #[tokio::test]
+    async fn read_frame_should_reject_oversized_header() {
+        let header = (MAX_FRAME as u32).to_be_bytes();
+        let mut stream = &header[..];
+        let mut data = BytesMut::new();
+        let res = read_frame(&mut stream, &mut data).await;
+        assert!(matches!(res, Err(KvError::FrameError(_))));
+        assert!(data.capacity() < MAX_FRAME);
+
+        let mut buf = BytesMut::new();
+        large_response().encode_frame(&mut buf).unwrap();
+        let config = FrameConfig::default().with_max_frame(16);
+        let mut stream = &buf[..];
+        let res = read_frame_with(&mut stream, &mut data, &config).await;
+        assert!(matches!(res, Err(KvError::FrameError(_))));
+    }
+
+    #[test]
+    fn decode_should_limit_decompressed_size() {
+        let res = large_response();
+        let config = FrameConfig::new(Compression::Lz4, 0);
+        let mut buf = BytesMut::new();
+        res.encode_frame_with(&mut buf, &config).unwrap();
+        let compressed = buf.len() - LEN_LEN;
+
+        // the compressed payload fits, the decompressed one does not
+        let config = config.with_max_frame(compressed + LEN_LEN);
+        let res = CommandResponse::decode_frame_with(&mut buf, &config);
+        assert!(matches!(res, Err(KvError::FrameError(_))));
+    }
+
+    fn large_response() -> CommandResponse {
+        let pairs: Vec<Kvpair> = (0..100)
+            .map(|i| Kvpair::new(format!("key{}", i), vec![0u8; 64].into()))
+            .collect();
+        pairs.into()
+    }
+
+    fn compression_of(buf: &[u8]) -> Compression {
+        let header = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        Compression::from_flag(decode_header(header).1).unwrap()
+    }
+
+    fn is_compressed(buf: &[u8]) -> bool {
+        compression_of(buf) != Compression::None
+    }
+}
@@ -0,0 +1,666 @@
+pub mod frame;
+mod multiplex;
+mod stream;
+mod stream_result;
+mod tls;
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use futures::{stream::once, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "tower")]
+use tower::{util::BoxService, BoxError, Layer, Service as _, ServiceExt};
+use tracing::{info, warn};
+
+use crate::{
+    command_request::RequestData, memory::MemTable, service::next_session_id, Auth, CommandRequest,
+    CommandResponse, KvError, SentResponse, Service, Storage, StreamingResponse,
+};
+
+pub use frame::{read_frame, read_frame_with, Compression, FrameCoder, FrameConfig};
+pub use multiplex::{YamuxCtrl, YamuxStream};
+pub use stream::ProstStream;
+pub use stream_result::StreamResult;
+pub use tls::{TlsClientConnector, TlsServerAcceptor};
+
+/// failed Auth attempts a physical connection may make before the server closes it
+pub const MAX_AUTH_FAILURES: u32 = 3;
+
+/// what the logical streams multiplexed over one physical connection share,
+/// one of them may cancel the subscriptions of another and they count failed Auth
+/// attempts together
+#[derive(Debug, Clone)]
+pub struct Session {
+    id: u64,
+    /// a successful attempt does not reset them
+    auth_failures: Arc<AtomicU32>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            id: next_session_id(),
+            auth_failures: Default::default(),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// whether the session made `MAX_AUTH_FAILURES` failed Auth attempts,
+    /// its connection has to be closed
+    pub fn auth_exhausted(&self) -> bool {
+        self.auth_failures.load(Ordering::SeqCst) >= MAX_AUTH_FAILURES
+    }
+
+    fn auth_failed(&self) -> u32 {
+        self.auth_failures.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the service executing the requests of a connection, wrapped in tower layers
+#[cfg(feature = "tower")]
+type Stack = BoxService<CommandRequest, StreamingResponse, BoxError>;
+
+/// wraps a service in the layers, the stack is rebuilt whenever the connection authenticates
+#[cfg(feature = "tower")]
+type MakeStack<Store> = Box<dyn Fn(Service<Store>) -> Stack + Send>;
+
+/// server side of a connection, executes every incoming request on the service
+pub struct ProstServerStream<S, Store = MemTable> {
+    inner: ProstStream<S, CommandRequest, CommandResponse>,
+    service: Service<Store>,
+    /// the service acting for the principal of the connection
+    connection: Service<Store>,
+    #[cfg(feature = "tower")]
+    stack: Stack,
+    #[cfg(feature = "tower")]
+    make_stack: MakeStack<Store>,
+    session: Session,
+    /// the user the connection authenticated as
+    principal: Option<String>,
+}
+
+/// client side of a connection, sends a request and waits for its response
+pub struct ProstClientStream<S> {
+    inner: ProstStream<S, CommandResponse, CommandRequest>,
+}
+
+impl<S, Store> ProstServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage,
+{
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        let session = Session::new();
+        let connection = service.for_session(session.id(), None);
+        #[cfg(feature = "tower")]
+        let make_stack: MakeStack<Store> =
+            Box::new(|service| BoxService::new(service.map_err(BoxError::from)));
+        Self {
+            inner: ProstStream::new(stream),
+            #[cfg(feature = "tower")]
+            stack: make_stack(connection.clone()),
+            #[cfg(feature = "tower")]
+            make_stack,
+            connection,
+            service,
+            session,
+            principal: None,
+        }
+    }
+
+    /// execute the requests with the service wrapped in the layer, e.g. a
+    /// `tower::ServiceBuilder` stacking timeouts, concurrency limits and retries.
+    /// The layer wraps the service once per connection, so a limit meant to be shared by
+    /// all connections has to share its state, like `GlobalConcurrencyLimitLayer` does.
+    /// A request failing in the stack is answered with the error. The layer wraps the service
+    /// again when the connection authenticates, so per connection state starts over.
+    ///
+    /// A request which timed out is answered with an error, but its command still runs to
+    /// completion and a write may be applied. Retrying it may apply it twice, so only retry
+    /// commands which read.
+    #[cfg(feature = "tower")]
+    pub fn with_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Service<Store>> + Send + 'static,
+        L::Service: tower::Service<CommandRequest, Response = StreamingResponse> + Send + 'static,
+        <L::Service as tower::Service<CommandRequest>>::Error: Into<BoxError>,
+        <L::Service as tower::Service<CommandRequest>>::Future: Send + 'static,
+    {
+        self.make_stack =
+            Box::new(move |service| BoxService::new(layer.layer(service).map_err(Into::into)));
+        self.stack = (self.make_stack)(self.connection.clone());
+        self
+    }
+
+    pub fn with_frame_config(mut self, config: FrameConfig) -> Self {
+        self.inner = self.inner.with_frame_config(config);
+        self
+    }
+
+    /// serve the connection as part of the session, e.g. the one of the physical connection
+    /// the stream is multiplexed over
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.session = session;
+        self.rebuild_connection();
+        self
+    }
+
+    /// serve requests until the client closes the connection,
+    /// a subscription owns the connection and closes it once it is unsubscribed.
+    /// If the service requires authentication, it rejects every command before a successful
+    /// Auth. The connection is closed once its session made `MAX_AUTH_FAILURES` failed attempts.
+    /// The after send hooks of the service are called once every response frame is flushed.
+    pub async fn process(mut self) -> Result<(), KvError> {
+        while let Some(cmd) = self.inner.recv().await? {
+            let start = Instant::now();
+            let request_id = self.service.next_request_id();
+            let mut is_subscription = false;
+            let mut res = match &cmd.request_data {
+                // the command is not logged, it holds the token
+                Some(RequestData::Auth(auth)) => respond(self.authenticate(auth)),
+                _ => {
+                    info!("Got a new command: {:?}", cmd);
+                    is_subscription = matches!(cmd.request_data, Some(RequestData::Subscribe(_)));
+                    self.execute(cmd).await
+                }
+            };
+            while let Some(data) = res.next().await {
+                let size = self.inner.send(&data).await?;
+                let sent = SentResponse {
+                    request_id,
+                    size,
+                    elapsed: start.elapsed(),
+                };
+                self.service.after_send(&sent).await;
+            }
+
+            if is_subscription {
+                return self.inner.close().await;
+            }
+            if self.session.auth_exhausted() {
+                warn!("Closing a connection after too many failed Auth attempts");
+                return self.inner.close().await;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "tower")]
+    async fn execute(&mut self, cmd: CommandRequest) -> StreamingResponse {
+        match self.stack.ready().await {
+            Ok(stack) => stack.call(cmd).await,
+            Err(e) => Err(e),
+        }
+        .unwrap_or_else(error_response)
+    }
+
+    #[cfg(not(feature = "tower"))]
+    async fn execute(&mut self, cmd: CommandRequest) -> StreamingResponse {
+        self.connection.execute(cmd).await
+    }
+
+    /// authenticate the connection, a failed attempt ends an earlier authentication.
+    /// The commands after it are checked against the acl for the authenticated user.
+    fn authenticate(&mut self, auth: &Auth) -> CommandResponse {
+        // another stream of the session may have used up the attempts meanwhile
+        let res = match self.session.auth_exhausted() {
+            true => Err(KvError::Unauthorized(
+                "too many failed Auth attempts".into(),
+            )),
+            false => self.service.authenticate(auth),
+        };
+        let res = match res {
+            Ok(principal) => {
+                info!("Authenticated user {}", auth.username);
+                self.principal = principal;
+                CommandResponse::ok()
+            }
+            Err(e) => {
+                warn!("Failed to authenticate user {}: {}", auth.username, e);
+                self.principal = None;
+                self.session.auth_failed();
+                e.into()
+            }
+        };
+        self.rebuild_connection();
+        res
+    }
+
+    /// the service acting for the session and the principal of the connection
+    fn rebuild_connection(&mut self) {
+        self.connection = self
+            .service
+            .for_session(self.session.id(), self.principal.as_deref());
+        #[cfg(feature = "tower")]
+        {
+            self.stack = (self.make_stack)(self.connection.clone());
+        }
+    }
+}
+
+/// a single response
+fn respond(res: CommandResponse) -> StreamingResponse {
+    Box::pin(once(async move { Arc::new(res) }))
+}
+
+/// the response to a request which failed in the layers around the service
+#[cfg(feature = "tower")]
+fn error_response(e: BoxError) -> StreamingResponse {
+    let e = match e.downcast::<KvError>() {
+        Ok(e) => *e,
+        Err(e) => KvError::Internal(e.to_string()),
+    };
+    respond(e.into())
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: ProstStream::new(stream),
+        }
+    }
+
+    pub fn with_frame_config(mut self, config: FrameConfig) -> Self {
+        self.inner = self.inner.with_frame_config(config);
+        self
+    }
+
+    /// authenticate the connection, `KvError::Unauthorized` if the server rejects the token
+    pub async fn authenticate(
+        &mut self,
+        username: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Result<(), KvError> {
+        let res = self
+            .execute(CommandRequest::new_auth(username, token))
+            .await?;
+        match res.status {
+            200 => Ok(()),
+            _ => Err(KvError::Unauthorized(res.message)),
+        }
+    }
+
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        self.inner.send(&cmd).await?;
+        match self.inner.recv().await? {
+            Some(res) => Ok(res),
+            None => Err(KvError::IoError("connection closed by server".into())),
+        }
+    }
+
+    /// send a streaming command such as subscribe, the stream is dedicated to its responses
+    pub async fn execute_streaming(mut self, cmd: CommandRequest) -> Result<StreamResult, KvError>
+    where
+        S: 'static,
+    {
+        self.inner.send(&cmd).await?;
+        StreamResult::new(self.inner).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(feature = "tower")]
+    use futures::FutureExt;
+    use prost::Message;
+    use tokio::{
+        io::{duplex, AsyncWriteExt},
+        sync::mpsc,
+    };
+    #[cfg(feature = "tower")]
+    use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+
+    use super::*;
+    use crate::{
+        frame::LEN_LEN,
+        service::{assert_res_error, assert_res_ok},
+        Acl, Permission, ServiceInner, StaticTokens, Value,
+    };
+
+    #[tokio::test]
+    async fn client_server_basic_communication_should_work() -> anyhow::Result<()> {
+        let mut client = start_client_server();
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd.clone()).await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k2")).await?;
+        assert_res_error(res, 404, "Not found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_compression_should_work() -> anyhow::Result<()> {
+        let mut client = start_client_server();
+
+        let v: Value = vec![0u8; 16384].into();
+        let cmd = CommandRequest::new_hset("t2", "k2", v.clone());
+        client.execute(cmd).await?;
+
+        let res = client.execute(CommandRequest::new_hget("t2", "k2")).await?;
+        assert_res_ok(res, &[v], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_stop_when_client_closes() -> anyhow::Result<()> {
+        let (client, server) = duplex(4096);
+        let service = ServiceInner::new(MemTable::new()).into();
+        let handle = tokio::spawn(ProstServerStream::new(server, service).process());
+
+        drop(client);
+        assert_eq!(handle.await?, Ok(()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_fail_on_truncated_frame() -> anyhow::Result<()> {
+        let (mut client, server) = duplex(4096);
+        let service = ServiceInner::new(MemTable::new()).into();
+        let handle = tokio::spawn(ProstServerStream::new(server, service).process());
+
+        client.write_all(&[0, 0, 0, 10, 1, 2]).await?;
+        drop(client);
+        assert!(matches!(handle.await?, Err(KvError::FrameError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_reject_oversized_frame() -> anyhow::Result<()> {
+        let (mut client, server) = duplex(4096);
+        let service = ServiceInner::new(MemTable::new()).into();
+        let config = FrameConfig::default().with_max_frame(1024);
+        let handle = tokio::spawn(
+            ProstServerStream::new(server, service)
+                .with_frame_config(config)
+                .process(),
+        );
+
+        // the header alone is enough, the client never sends the payload
+        client.write_all(&1025u32.to_be_bytes()).await?;
+        assert!(matches!(handle.await?, Err(KvError::FrameError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_fail_cleanly_on_oversized_response() -> anyhow::Result<()> {
+        let (client, server) = duplex(4096);
+        let service = ServiceInner::new(MemTable::new()).into();
+        let config = FrameConfig::default().with_max_frame(1024);
+        let handle = tokio::spawn(
+            ProstServerStream::new(server, service)
+                .with_frame_config(config)
+                .process(),
+        );
+
+        let mut client = ProstClientStream::new(client).with_frame_config(config);
+        for i in 0..4 {
+            let value: Value = vec![0u8; 512].into();
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), value);
+            client.execute(cmd).await?;
+        }
+
+        // every request fits, the response holding all values does not
+        let res = client.execute(CommandRequest::new_hgetall("t1")).await;
+        assert!(res.is_err());
+        assert!(matches!(handle.await?, Err(KvError::FrameError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_fail_when_server_is_gone() -> anyhow::Result<()> {
+        let (client, server) = duplex(4096);
+        let mut client = ProstClientStream::new(client);
+        drop(server);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_pub_sub_should_work() -> anyhow::Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let session = Session::new();
+        let mut publisher = start_client_server_with(service.clone());
+        let subscriber = start_client_server_in(service.clone(), session.clone());
+        let mut unsubscriber = start_client_server_in(service, session);
+
+        let mut stream = subscriber
+            .execute_streaming(CommandRequest::new_subscribe("lobby"))
+            .await?;
+        let id = stream.id;
+        assert!(id > 0);
+
+        let v: Value = "hello".into();
+        let res = publisher
+            .execute(CommandRequest::new_publish("lobby", vec![v.clone()]))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+
+        let res = stream.next().await.unwrap()?;
+        assert_res_ok(res, &[v], &[]);
+
+        // only a connection of the session which subscribed may unsubscribe
+        let res = publisher
+            .execute(CommandRequest::new_unsubscribe("lobby", id))
+            .await?;
+        assert_res_error(res, 404, "Not found");
+        let res = unsubscriber
+            .execute(CommandRequest::new_unsubscribe("lobby", id))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+        assert!(stream.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_call_after_send_hooks() -> anyhow::Result<()> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let service = ServiceInner::new(MemTable::new())
+            .fn_after_send(move |sent| {
+                let _ = tx.send(*sent);
+            })
+            .into();
+        let mut client = start_client_server_with(service);
+
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        let sent = rx.recv().await.unwrap();
+        assert_eq!(sent.request_id, 1);
+        assert_eq!(sent.size, LEN_LEN + res.encoded_len());
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        let sent = rx.recv().await.unwrap();
+        assert_eq!(sent.request_id, 2);
+        assert_eq!(sent.size, LEN_LEN + res.encoded_len());
+        assert!(sent.elapsed > Duration::ZERO);
+        Ok(())
+    }
+
+    #[cfg(feature = "tower")]
+    #[tokio::test]
+    async fn server_should_execute_requests_through_layers() -> anyhow::Result<()> {
+        let service = ServiceInner::new(MemTable::new())
+            .async_received(|cmd| {
+                async move {
+                    if matches!(&cmd.request_data, Some(RequestData::Hget(p)) if p.table == "slow")
+                    {
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                    }
+                }
+                .boxed()
+            })
+            .into();
+        let layers = ServiceBuilder::new()
+            .timeout(Duration::from_millis(50))
+            .layer(GlobalConcurrencyLimitLayer::new(4));
+        let (client, server) = duplex(4096);
+        tokio::spawn(
+            ProstServerStream::new(server, service)
+                .with_layer(layers)
+                .process(),
+        );
+        let mut client = ProstClientStream::new(client);
+
+        let res = client
+            .execute(CommandRequest::new_hget("slow", "k1"))
+            .await?;
+        assert_res_error(res, 500, "request timed out");
+
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_require_auth() -> anyhow::Result<()> {
+        let service = ServiceInner::new(MemTable::new())
+            .with_authenticator(StaticTokens::new().with_user("alice", "secret"))
+            .into();
+        let mut client = start_client_server_with(service);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd.clone()).await?;
+        assert_res_error(res, 401, "authenticate first");
+
+        let res = client.authenticate("alice", "wrong").await;
+        assert!(matches!(res, Err(KvError::Unauthorized(_))));
+        let res = client.execute(cmd.clone()).await?;
+        assert_res_error(res, 401, "authenticate first");
+
+        client.authenticate("alice", "secret").await?;
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        // a failed attempt ends the authentication
+        assert!(client.authenticate("bob", "secret").await.is_err());
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 401, "authenticate first");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_close_connection_after_failed_auths() -> anyhow::Result<()> {
+        let service = ServiceInner::new(MemTable::new())
+            .with_authenticator(StaticTokens::new().with_user("alice", "secret"))
+            .into();
+        let mut client = start_client_server_with(service);
+
+        for _ in 0..MAX_AUTH_FAILURES - 1 {
+            assert!(client.authenticate("alice", "wrong").await.is_err());
+        }
+        // a successful attempt does not reset the failures
+        client.authenticate("alice", "secret").await?;
+        let res = client.authenticate("alice", "guess").await;
+        assert!(matches!(res, Err(KvError::Unauthorized(_))));
+
+        let res = client.authenticate("alice", "secret").await;
+        assert!(matches!(res, Err(KvError::IoError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_check_acl_for_authenticated_user() -> anyhow::Result<()> {
+        let service = ServiceInner::new(MemTable::new())
+            .with_authenticator(
+                StaticTokens::new()
+                    .with_user("alice", "secret")
+                    .with_user("bob", "hunter2"),
+            )
+            .with_acl(Acl::new().with_rule("alice", "sessions", Permission::Read))
+            .into();
+        let mut client = start_client_server_with(service);
+
+        client.authenticate("alice", "secret").await?;
+        let res = client
+            .execute(CommandRequest::new_hget("sessions", "k1"))
+            .await?;
+        assert_res_error(res, 404, "Not found");
+        let cmd = CommandRequest::new_hset("sessions", "k1", "v1".into());
+        let res = client.execute(cmd).await?;
+        assert_res_error(res, 403, "alice has no write permission on table sessions");
+
+        // the permissions follow the user the connection authenticated as last
+        client.authenticate("bob", "hunter2").await?;
+        let res = client
+            .execute(CommandRequest::new_hget("sessions", "k1"))
+            .await?;
+        assert_res_error(res, 403, "bob has no read permission on table sessions");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_without_authenticator_should_accept_any_auth() -> anyhow::Result<()> {
+        let mut client = start_client_server();
+        client.authenticate("anyone", "").await?;
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 404, "Not found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_without_authenticator_should_not_trust_username() -> anyhow::Result<()> {
+        let acl = Acl::new()
+            .with_rule("root", "*", Permission::Admin)
+            .with_admin("root");
+        let service = ServiceInner::new(MemTable::new()).with_acl(acl).into();
+        let mut client = start_client_server_with(service);
+
+        client.authenticate("root", "").await?;
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd).await?;
+        assert_res_error(res, 403, "an anonymous connection has no write permission");
+        let res = client.execute(CommandRequest::new_acl_list("root")).await?;
+        assert_res_error(res, 403, "an anonymous connection may not manage the acl");
+        Ok(())
+    }
+
+    fn start_client_server() -> ProstClientStream<tokio::io::DuplexStream> {
+        start_client_server_with(ServiceInner::new(MemTable::new()).into())
+    }
+
+    fn start_client_server_with(service: Service) -> ProstClientStream<tokio::io::DuplexStream> {
+        start_client_server_in(service, Session::new())
+    }
+
+    fn start_client_server_in(
+        service: Service,
+        session: Session,
+    ) -> ProstClientStream<tokio::io::DuplexStream> {
+        let (client, server) = duplex(4096);
+        tokio::spawn(
+            ProstServerStream::new(server, service)
+                .with_session(session)
+                .process(),
+        );
+        ProstClientStream::new(client)
+    }
+}
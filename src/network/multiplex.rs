@@ -0,0 +1,224 @@
+use std::{future::Future, marker::PhantomData};
+
+use futures::{future, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use yamux::{Config, Connection, ConnectionError, Control, Mode, WindowUpdateMode};
+
+use super::Session;
+use crate::KvError;
+
+/// a logical stream multiplexed over a yamux connection
+pub type YamuxStream = Compat<yamux::Stream>;
+
+/// handle of a yamux connection, clients open new logical streams with it
+pub struct YamuxCtrl<S> {
+    ctrl: Control,
+    _conn: PhantomData<S>,
+}
+
+impl<S> Clone for YamuxCtrl<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ctrl: self.ctrl.clone(),
+            _conn: PhantomData,
+        }
+    }
+}
+
+impl<S> YamuxCtrl<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// client side of a connection, ignores streams opened by the server
+    pub fn new_client(stream: S, config: Option<Config>) -> Self {
+        Self::new(stream, config, Mode::Client, |_| future::ready(Ok(())))
+    }
+
+    /// server side of a connection, `f` is called concurrently for every stream the client opens,
+    /// with the session all streams of the connection share. The connection is closed once
+    /// a stream ends after the session used up its Auth attempts.
+    pub fn new_server<F, Fut>(stream: S, config: Option<Config>, mut f: F) -> Self
+    where
+        F: FnMut(YamuxStream, Session) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ConnectionError>> + Send + 'static,
+    {
+        let session = Session::new();
+        Self::new(stream, config, Mode::Server, move |stream| {
+            let session = session.clone();
+            let fut = f(stream, session.clone());
+            async move {
+                fut.await?;
+                match session.auth_exhausted() {
+                    // failing stops serving the connection, which drops it with all its streams
+                    true => Err(ConnectionError::Closed),
+                    false => Ok(()),
+                }
+            }
+        })
+    }
+
+    fn new<F, Fut>(stream: S, config: Option<Config>, mode: Mode, mut f: F) -> Self
+    where
+        F: FnMut(YamuxStream) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), ConnectionError>> + Send + 'static,
+    {
+        let mut config = config.unwrap_or_default();
+        // only grant the peer more window when the data is consumed, so a slow stream applies back pressure
+        config.set_window_update_mode(WindowUpdateMode::OnRead);
+
+        let conn = Connection::new(stream.compat(), config, mode);
+        let ctrl = conn.control();
+        tokio::spawn(
+            yamux::into_stream(conn).try_for_each_concurrent(None, move |s| f(s.compat())),
+        );
+
+        Self {
+            ctrl,
+            _conn: PhantomData,
+        }
+    }
+
+    /// open a new logical stream on the connection
+    pub async fn open_stream(&mut self) -> Result<YamuxStream, KvError> {
+        let stream = self.ctrl.open_stream().await?;
+        Ok(stream.compat())
+    }
+
+    /// close the connection and all of its streams
+    pub async fn close(&mut self) -> Result<(), KvError> {
+        Ok(self.ctrl.close().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use futures::{future::join_all, StreamExt};
+    use tokio::io::duplex;
+    use tracing::warn;
+
+    use super::*;
+    use crate::{
+        memory::MemTable, network::MAX_AUTH_FAILURES, service::assert_res_ok, CommandRequest,
+        ProstClientStream, ProstServerStream, Service, ServiceInner, StaticTokens, Value,
+    };
+
+    #[tokio::test]
+    async fn yamux_ctrl_client_server_should_work() -> Result<()> {
+        let mut ctrl = start_yamux_server();
+
+        let stream = ctrl.open_stream().await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn yamux_ctrl_should_run_streams_concurrently() -> Result<()> {
+        let ctrl = start_yamux_server();
+
+        let tasks = (0..16).map(|i| {
+            let mut ctrl = ctrl.clone();
+            tokio::spawn(async move {
+                let stream = ctrl.open_stream().await?;
+                let mut client = ProstClientStream::new(stream);
+                let key = format!("k{}", i);
+                client
+                    .execute(CommandRequest::new_hset("t1", &key, (i as i64).into()))
+                    .await?;
+                client.execute(CommandRequest::new_hget("t1", key)).await
+            })
+        });
+
+        for (i, res) in join_all(tasks).await.into_iter().enumerate() {
+            assert_res_ok(res??, &[(i as i64).into()], &[]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_stream_should_survive_a_closed_sibling() -> Result<()> {
+        let mut ctrl = start_yamux_server();
+
+        let first = ctrl.open_stream().await?;
+        drop(first);
+
+        let stream = ctrl.open_stream().await?;
+        let mut client = ProstClientStream::new(stream);
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_streams_should_share_subscriptions() -> Result<()> {
+        let mut ctrl = start_yamux_server();
+
+        let subscriber = ProstClientStream::new(ctrl.open_stream().await?);
+        let mut stream = subscriber
+            .execute_streaming(CommandRequest::new_subscribe("lobby"))
+            .await?;
+
+        let mut client = ProstClientStream::new(ctrl.open_stream().await?);
+        let res = client
+            .execute(CommandRequest::new_unsubscribe("lobby", stream.id))
+            .await?;
+        assert_res_ok(res, &[], &[]);
+        assert!(stream.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn yamux_should_count_failed_auths_of_the_connection() -> Result<()> {
+        let service = ServiceInner::new(MemTable::new())
+            .with_authenticator(StaticTokens::new().with_user("alice", "secret"))
+            .into();
+        let mut ctrl = start_yamux_server_with(service);
+
+        // every stream makes fewer attempts than a single connection may
+        for _ in 0..MAX_AUTH_FAILURES {
+            let mut client = ProstClientStream::new(ctrl.open_stream().await?);
+            assert!(client.authenticate("alice", "wrong").await.is_err());
+        }
+
+        let res = match ctrl.open_stream().await {
+            Ok(stream) => {
+                let mut client = ProstClientStream::new(stream);
+                client.authenticate("alice", "secret").await
+            }
+            Err(e) => Err(e),
+        };
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    fn start_yamux_server() -> YamuxCtrl<tokio::io::DuplexStream> {
+        start_yamux_server_with(ServiceInner::new(MemTable::new()).into())
+    }
+
+    fn start_yamux_server_with(service: Service) -> YamuxCtrl<tokio::io::DuplexStream> {
+        let (client, server) = duplex(4096);
+
+        YamuxCtrl::new_server(server, None, move |stream, session| {
+            let svc = service.clone();
+            async move {
+                let stream = ProstServerStream::new(stream, svc).with_session(session);
+                if let Err(e) = stream.process().await {
+                    warn!("Failed to process stream: {:?}", e);
+                }
+                Ok(())
+            }
+        });
+
+        YamuxCtrl::new_client(client, None)
+    }
+}
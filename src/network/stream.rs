@@ -0,0 +1,91 @@
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    frame::{decode_header, LEN_LEN},
+    FrameCoder, FrameConfig, KvError,
+};
+
+/// a stream of frames, reading `In` messages and writing `Out` messages
+pub struct ProstStream<S, In, Out> {
+    inner: S,
+    rbuf: BytesMut,
+    wbuf: BytesMut,
+    config: FrameConfig,
+    _in: PhantomData<In>,
+    _out: PhantomData<Out>,
+}
+
+impl<S, In, Out> ProstStream<S, In, Out>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    In: FrameCoder,
+    Out: FrameCoder,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            inner: stream,
+            rbuf: BytesMut::new(),
+            wbuf: BytesMut::new(),
+            config: FrameConfig::default(),
+            _in: PhantomData,
+            _out: PhantomData,
+        }
+    }
+
+    pub fn with_frame_config(mut self, config: FrameConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// encode a message into a frame and flush it to the stream, returns the size of the frame
+    pub async fn send(&mut self, msg: &Out) -> Result<usize, KvError> {
+        self.wbuf.clear();
+        msg.encode_frame_with(&mut self.wbuf, &self.config)?;
+        self.inner.write_all(&self.wbuf).await?;
+        self.inner.flush().await?;
+        Ok(self.wbuf.len())
+    }
+
+    /// flush and shut down the write half of the stream
+    pub async fn close(&mut self) -> Result<(), KvError> {
+        self.inner.shutdown().await?;
+        Ok(())
+    }
+
+    /// read the next message, `None` means the peer closed the stream between two frames
+    pub async fn recv(&mut self) -> Result<Option<In>, KvError> {
+        loop {
+            if let Some(len) = self.frame_len()? {
+                if self.rbuf.len() >= len {
+                    let mut frame = self.rbuf.split_to(len);
+                    return In::decode_frame_with(&mut frame, &self.config).map(Some);
+                }
+            }
+
+            if self.inner.read_buf(&mut self.rbuf).await? == 0 {
+                if self.rbuf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(KvError::FrameError(
+                    "stream closed in the middle of a frame".into(),
+                ));
+            }
+        }
+    }
+
+    /// length of the buffered frame, header included, once its header is complete.
+    /// A frame larger than the config allows is rejected before its payload is buffered.
+    fn frame_len(&self) -> Result<Option<usize>, KvError> {
+        if self.rbuf.len() < LEN_LEN {
+            return Ok(None);
+        }
+
+        let header = u32::from_be_bytes([self.rbuf[0], self.rbuf[1], self.rbuf[2], self.rbuf[3]]);
+        let (len, _) = decode_header(header);
+        self.config.check_len(len)?;
+        Ok(Some(LEN_LEN + len))
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{stream, Stream};
+use http::StatusCode;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{CommandRequest, CommandResponse, KvError, ProstStream};
+
+/// responses of a streaming command, `id` is taken from the first response
+pub struct StreamResult {
+    pub id: u32,
+    inner: Pin<Box<dyn Stream<Item = Result<CommandResponse, KvError>> + Send>>,
+}
+
+impl StreamResult {
+    pub(crate) async fn new<S>(
+        mut stream: ProstStream<S, CommandResponse, CommandRequest>,
+    ) -> Result<Self, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let id = match stream.recv().await? {
+            Some(res) if res.status == StatusCode::OK.as_u16() as u32 => match res.values.first() {
+                Some(v) => i64::try_from(v)? as u32,
+                None => return Err(KvError::Internal("Invalid stream".into())),
+            },
+            Some(res) => return Err(KvError::Internal(res.message)),
+            None => return Err(KvError::IoError("connection closed by server".into())),
+        };
+
+        // the stream ends after the first error, the connection cannot be read any further
+        let inner = stream::unfold(Some(stream), |stream| async move {
+            let mut stream = stream?;
+            match stream.recv().await {
+                Ok(Some(res)) => Some((Ok(res), Some(stream))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+
+        Ok(Self {
+            id,
+            inner: Box::pin(inner),
+        })
+    }
+}
+
+impl Stream for StreamResult {
+    type Item = Result<CommandResponse, KvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use futures::StreamExt;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    use super::*;
+    use crate::{FrameCoder, Value};
+
+    #[tokio::test]
+    async fn stream_result_should_end_after_an_error() -> anyhow::Result<()> {
+        let (client, mut server) = duplex(4096);
+        let mut buf = BytesMut::new();
+        let id: Value = 1.into();
+        CommandResponse::from(id).encode_frame(&mut buf)?;
+        server.write_all(&buf).await?;
+
+        let mut stream = StreamResult::new(ProstStream::new(client)).await?;
+        assert_eq!(stream.id, 1);
+
+        // the server goes away in the middle of a frame
+        server.write_all(&[0, 0, 0, 10, 1, 2]).await?;
+        drop(server);
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        Ok(())
+    }
+}
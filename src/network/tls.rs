@@ -0,0 +1,355 @@
+use std::{fs, path::Path, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    client,
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    server, TlsAcceptor, TlsConnector,
+};
+
+use crate::KvError;
+
+/// ALPN protocol name of the kv wire format
+const ALPN_KV: &str = "kv";
+
+/// accepts TLS connections, optionally requiring a client certificate
+#[derive(Clone)]
+pub struct TlsServerAcceptor {
+    inner: Arc<ServerConfig>,
+}
+
+/// opens TLS connections to a server, optionally presenting a client certificate
+#[derive(Clone)]
+pub struct TlsClientConnector {
+    config: Arc<ClientConfig>,
+    domain: Arc<String>,
+}
+
+impl TlsServerAcceptor {
+    /// build an acceptor from PEM encoded cert and key,
+    /// clients must present a certificate signed by `client_ca` when it is given
+    pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let provider = provider();
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_ca {
+            None => builder.with_no_client_auth(),
+            Some(ca) => {
+                let roots = load_roots(ca)?;
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| KvError::TlsError(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let mut config = builder.with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![ALPN_KV.into()];
+
+        Ok(Self {
+            inner: Arc::new(config),
+        })
+    }
+
+    /// same as `new`, reading the PEM files from disk
+    pub fn from_pem_files(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        client_ca: Option<impl AsRef<Path>>,
+    ) -> Result<Self, KvError> {
+        let client_ca = client_ca.map(fs::read_to_string).transpose()?;
+        Self::new(
+            &fs::read_to_string(cert)?,
+            &fs::read_to_string(key)?,
+            client_ca.as_deref(),
+        )
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> Result<server::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let acceptor = TlsAcceptor::from(self.inner.clone());
+        Ok(acceptor.accept(stream).await?)
+    }
+}
+
+impl TlsClientConnector {
+    /// build a connector for `domain`, presenting `identity` (cert, key) when it is given.
+    /// the server certificate is checked against `server_ca`, or the webpki roots without it
+    pub fn new(
+        domain: impl Into<String>,
+        identity: Option<(&str, &str)>,
+        server_ca: Option<&str>,
+    ) -> Result<Self, KvError> {
+        let roots = match server_ca {
+            Some(ca) => load_roots(ca)?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots);
+        let mut config = match identity {
+            Some((cert, key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_KV.into()];
+
+        Ok(Self {
+            config: Arc::new(config),
+            domain: Arc::new(domain.into()),
+        })
+    }
+
+    /// same as `new`, reading the PEM files from disk
+    pub fn from_pem_files(
+        domain: impl Into<String>,
+        identity: Option<(impl AsRef<Path>, impl AsRef<Path>)>,
+        server_ca: Option<impl AsRef<Path>>,
+    ) -> Result<Self, KvError> {
+        let identity = identity
+            .map(|(cert, key)| {
+                Ok::<_, KvError>((fs::read_to_string(cert)?, fs::read_to_string(key)?))
+            })
+            .transpose()?;
+        let server_ca = server_ca.map(fs::read_to_string).transpose()?;
+        Self::new(
+            domain,
+            identity
+                .as_ref()
+                .map(|(cert, key)| (cert.as_str(), key.as_str())),
+            server_ca.as_deref(),
+        )
+    }
+
+    pub async fn connect<S>(&self, stream: S) -> Result<client::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let domain = ServerName::try_from(self.domain.as_str().to_owned())
+            .map_err(|_| KvError::TlsError(format!("invalid domain: {}", self.domain)))?;
+        let connector = TlsConnector::from(self.config.clone());
+        Ok(connector.connect(domain, stream).await?)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(cert: &str) -> Result<Vec<CertificateDer<'static>>, KvError> {
+    let certs = CertificateDer::pem_slice_iter(cert.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| KvError::CertificateParseError("cert", e.to_string()))?;
+    if certs.is_empty() {
+        return Err(KvError::CertificateParseError(
+            "cert",
+            "no certificate found".into(),
+        ));
+    }
+    Ok(certs)
+}
+
+fn load_key(key: &str) -> Result<PrivateKeyDer<'static>, KvError> {
+    PrivateKeyDer::from_pem_slice(key.as_bytes())
+        .map_err(|e| KvError::CertificateParseError("private key", e.to_string()))
+}
+
+fn load_roots(ca: &str) -> Result<RootCertStore, KvError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::{
+        memory::MemTable, service::assert_res_ok, CommandRequest, ProstClientStream,
+        ProstServerStream, ServiceInner, Value,
+    };
+
+    const DOMAIN: &str = "kv.test";
+
+    struct Pem {
+        cert: String,
+        key: String,
+    }
+
+    struct Certs {
+        ca: Pem,
+        server: Pem,
+        client: Pem,
+    }
+
+    #[tokio::test]
+    async fn tls_should_work() -> anyhow::Result<()> {
+        let certs = generate_certs()?;
+        let acceptor = TlsServerAcceptor::new(&certs.server.cert, &certs.server.key, None)?;
+        let connector = TlsClientConnector::new(DOMAIN, None, Some(&certs.ca.cert))?;
+
+        let mut client = start_client_server(acceptor, connector).await?;
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_with_client_cert_should_work() -> anyhow::Result<()> {
+        let certs = generate_certs()?;
+        let acceptor =
+            TlsServerAcceptor::new(&certs.server.cert, &certs.server.key, Some(&certs.ca.cert))?;
+        let identity = (certs.client.cert.as_str(), certs.client.key.as_str());
+        let connector = TlsClientConnector::new(DOMAIN, Some(identity), Some(&certs.ca.cert))?;
+
+        let mut client = start_client_server(acceptor, connector).await?;
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_without_required_client_cert_should_fail() -> anyhow::Result<()> {
+        let certs = generate_certs()?;
+        let acceptor =
+            TlsServerAcceptor::new(&certs.server.cert, &certs.server.key, Some(&certs.ca.cert))?;
+        let connector = TlsClientConnector::new(DOMAIN, None, Some(&certs.ca.cert))?;
+
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(async move { acceptor.accept(server).await });
+
+        // TLS 1.3 clients finish the handshake before the server checks their certificate
+        if let Ok(stream) = connector.connect(client).await {
+            let mut client = ProstClientStream::new(stream);
+            assert!(client
+                .execute(CommandRequest::new_hget("t1", "k1"))
+                .await
+                .is_err());
+        }
+        assert!(server.await?.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_with_wrong_domain_should_fail() -> anyhow::Result<()> {
+        let certs = generate_certs()?;
+        let acceptor = TlsServerAcceptor::new(&certs.server.cert, &certs.server.key, None)?;
+        let connector = TlsClientConnector::new("wrong.test", None, Some(&certs.ca.cert))?;
+
+        let (client, server) = duplex(4096);
+        tokio::spawn(async move { acceptor.accept(server).await });
+
+        assert!(connector.connect(client).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_from_pem_files_should_work() -> anyhow::Result<()> {
+        let certs = generate_certs()?;
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, data: &str| -> std::io::Result<std::path::PathBuf> {
+            let path = dir.path().join(name);
+            fs::write(&path, data)?;
+            Ok(path)
+        };
+        let ca = write("ca.pem", &certs.ca.cert)?;
+        let server_cert = write("server.pem", &certs.server.cert)?;
+        let server_key = write("server.key", &certs.server.key)?;
+        let client_cert = write("client.pem", &certs.client.cert)?;
+        let client_key = write("client.key", &certs.client.key)?;
+
+        let acceptor = TlsServerAcceptor::from_pem_files(&server_cert, &server_key, Some(&ca))?;
+        let connector = TlsClientConnector::from_pem_files(
+            DOMAIN,
+            Some((&client_cert, &client_key)),
+            Some(&ca),
+        )?;
+
+        let mut client = start_client_server(acceptor, connector).await?;
+        let res = client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_pem_should_fail() {
+        let res = TlsServerAcceptor::new("not a cert", "not a key", None);
+        assert!(matches!(res, Err(KvError::CertificateParseError(..))));
+    }
+
+    async fn start_client_server(
+        acceptor: TlsServerAcceptor,
+        connector: TlsClientConnector,
+    ) -> anyhow::Result<ProstClientStream<client::TlsStream<tokio::io::DuplexStream>>> {
+        let (client, server) = duplex(4096);
+        tokio::spawn(async move {
+            let stream = acceptor.accept(server).await.unwrap();
+            let service = ServiceInner::new(MemTable::new()).into();
+            ProstServerStream::new(stream, service).process().await
+        });
+
+        let stream = connector.connect(client).await?;
+        Ok(ProstClientStream::new(stream))
+    }
+
+    fn generate_certs() -> anyhow::Result<Certs> {
+        let ca_key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![])?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "kv test CA");
+        let ca = params.self_signed(&ca_key)?;
+
+        let server = sign(&ca, &ca_key, DOMAIN)?;
+        let client = sign(&ca, &ca_key, "client")?;
+
+        Ok(Certs {
+            ca: Pem {
+                cert: ca.pem(),
+                key: ca_key.serialize_pem(),
+            },
+            server,
+            client,
+        })
+    }
+
+    fn sign(ca: &Certificate, ca_key: &KeyPair, name: &str) -> anyhow::Result<Pem> {
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![name.to_string()])?;
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = params.signed_by(&key, ca, ca_key)?;
+        Ok(Pem {
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+        })
+    }
+}
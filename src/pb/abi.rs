@@ -1,138 +1,425 @@
 /// request from client
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(
-        oneof = "command_request::RequestData",
-        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9"
-    )]
+    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32")]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
-    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
+    #[derive(PartialOrd)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum RequestData {
-        #[prost(message, tag = "1")]
+        #[prost(message, tag="1")]
         Hget(super::Hget),
-        #[prost(message, tag = "2")]
+        #[prost(message, tag="2")]
         Hgetall(super::Hgetall),
-        #[prost(message, tag = "3")]
+        #[prost(message, tag="3")]
         Hmget(super::Hmget),
-        #[prost(message, tag = "4")]
+        #[prost(message, tag="4")]
         Hset(super::Hset),
-        #[prost(message, tag = "5")]
+        #[prost(message, tag="5")]
         Hmset(super::Hmset),
-        #[prost(message, tag = "6")]
+        #[prost(message, tag="6")]
         Hdel(super::Hdel),
-        #[prost(message, tag = "7")]
+        #[prost(message, tag="7")]
         Hmdel(super::Hmdel),
-        #[prost(message, tag = "8")]
+        #[prost(message, tag="8")]
         Hexist(super::Hexist),
-        #[prost(message, tag = "9")]
+        #[prost(message, tag="9")]
         Hmexists(super::Hmexists),
+        #[prost(message, tag="10")]
+        Subscribe(super::Subscribe),
+        #[prost(message, tag="11")]
+        Unsubscribe(super::Unsubscribe),
+        #[prost(message, tag="12")]
+        Publish(super::Publish),
+        #[prost(message, tag="13")]
+        Expire(super::Expire),
+        #[prost(message, tag="14")]
+        Ttl(super::Ttl),
+        #[prost(message, tag="15")]
+        Persist(super::Persist),
+        #[prost(message, tag="16")]
+        Hsetex(super::Hsetex),
+        #[prost(message, tag="17")]
+        Hincrby(super::Hincrby),
+        #[prost(message, tag="18")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        #[prost(message, tag="19")]
+        Hsetnx(super::Hsetnx),
+        #[prost(message, tag="20")]
+        Hcas(super::Hcas),
+        #[prost(message, tag="21")]
+        Txn(super::Txn),
+        #[prost(message, tag="22")]
+        Hscan(super::Hscan),
+        #[prost(message, tag="23")]
+        Hrange(super::Hrange),
+        #[prost(message, tag="24")]
+        Hprefix(super::Hprefix),
+        #[prost(message, tag="25")]
+        ListTables(super::ListTables),
+        #[prost(message, tag="26")]
+        DropTable(super::DropTable),
+        #[prost(message, tag="27")]
+        RenameTable(super::RenameTable),
+        #[prost(message, tag="28")]
+        Hlen(super::Hlen),
+        #[prost(message, tag="29")]
+        Auth(super::Auth),
+        #[prost(message, tag="30")]
+        AclGrant(super::AclGrant),
+        #[prost(message, tag="31")]
+        AclRevoke(super::AclRevoke),
+        #[prost(message, tag="32")]
+        AclList(super::AclList),
     }
 }
 /// response by server
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandResponse {
     /// status codes,reuse http status codes,e.g. 2xx / 3xx / 4xx
-    #[prost(uint32, tag = "1")]
+    #[prost(uint32, tag="1")]
     pub status: u32,
     /// if the status code is not 2xx message will give a specific err message
-    #[prost(string, tag = "2")]
+    #[prost(string, tag="2")]
     pub message: ::prost::alloc::string::String,
-    #[prost(message, repeated, tag = "3")]
+    #[prost(message, repeated, tag="3")]
     pub values: ::prost::alloc::vec::Vec<Value>,
-    #[prost(message, repeated, tag = "4")]
+    #[prost(message, repeated, tag="4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// responses of the commands in a Txn, in order
+    #[prost(message, repeated, tag="5")]
+    pub responses: ::prost::alloc::vec::Vec<CommandResponse>,
 }
 /// get the value of the key in the table
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hget {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag = "2")]
+    #[prost(string, tag="2")]
     pub key: ::prost::alloc::string::String,
 }
 /// get all kv pairs of in the table
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hgetall {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag = "2")]
+    #[prost(string, repeated, tag="2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
+    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
 pub mod value {
-    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
+    #[derive(PartialOrd)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum Value {
-        #[prost(string, tag = "1")]
+        #[prost(string, tag="1")]
         String(::prost::alloc::string::String),
-        #[prost(bytes, tag = "2")]
+        #[prost(bytes, tag="2")]
         Binary(::prost::bytes::Bytes),
-        #[prost(int64, tag = "3")]
+        #[prost(int64, tag="3")]
         Integer(i64),
-        #[prost(double, tag = "4")]
+        #[prost(double, tag="4")]
         Float(f64),
-        #[prost(bool, tag = "5")]
+        #[prost(bool, tag="5")]
         Bool(bool),
     }
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub key: ::prost::alloc::string::String,
-    #[prost(message, optional, tag = "2")]
+    #[prost(message, optional, tag="2")]
     pub value: ::core::option::Option<Value>,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hset {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, optional, tag = "2")]
+    #[prost(message, optional, tag="2")]
     pub pair: ::core::option::Option<Kvpair>,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, repeated, tag = "2")]
+    #[prost(message, repeated, tag="2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hdel {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag = "2")]
+    #[prost(string, tag="2")]
     pub key: ::prost::alloc::string::String,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmdel {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag = "2")]
+    #[prost(string, repeated, tag="2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hexist {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag = "2")]
+    #[prost(string, tag="2")]
     pub key: ::prost::alloc::string::String,
 }
-#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmexists {
-    #[prost(string, tag = "1")]
+    #[prost(string, tag="1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag = "2")]
+    #[prost(string, repeated, tag="2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// subscribe to a topic, the first response carries the subscription id
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscribe {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+}
+/// cancel the subscription with the id in the topic
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Unsubscribe {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(uint32, tag="2")]
+    pub id: u32,
+}
+/// publish data to every subscriber of the topic
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Publish {
+    #[prost(string, tag="1")]
+    pub topic: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag="2")]
+    pub data: ::prost::alloc::vec::Vec<Value>,
+}
+/// let the key expire after ttl milliseconds
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Expire {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag="3")]
+    pub ttl: u64,
+}
+/// get the remaining time to live of the key in milliseconds, -1 if it never expires
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Ttl {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// remove the time to live of the key, so it never expires
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Persist {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// set a kv pair which expires after ttl milliseconds
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetex {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub pair: ::core::option::Option<Kvpair>,
+    #[prost(uint64, tag="3")]
+    pub ttl: u64,
+}
+/// add delta to the integer value of the key, a missing key starts from 0
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrby {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag="3")]
+    pub delta: i64,
+}
+/// add delta to the float value of the key, a missing key starts from 0
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag="3")]
+    pub delta: f64,
+}
+/// set the kv pair only if the key does not exist
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// replace the value of the key only if it equals expected,
+/// a missing expected means the key must not exist, a missing new deletes the key
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hcas {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag="3")]
+    pub expected: ::core::option::Option<Value>,
+    #[prost(message, optional, tag="4")]
+    pub new: ::core::option::Option<Value>,
+}
+/// run the commands atomically, either all of them apply or none does
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Txn {
+    #[prost(message, repeated, tag="1")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// examine at most count keys of the table, in key order, starting after the cursor, and get the
+/// kv pairs among them matching the pattern, which may be none. The response carries the pairs and
+/// the cursor of the next call in values, the cursor is an opaque token, an empty cursor starts
+/// a scan and is returned once the scan is finished.
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub cursor: ::prost::alloc::string::String,
+    #[prost(uint32, tag="3")]
+    pub count: u32,
+    /// only return keys matching the glob pattern, `*` matches any sequence and `?` any character
+    #[prost(string, tag="4")]
+    pub match_pattern: ::prost::alloc::string::String,
+}
+/// get the kv pairs with start <= key < end in key order, an empty end is unbounded,
+/// reverse returns them from the last key, a limit of 0 returns all of them
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hrange {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub end: ::prost::alloc::string::String,
+    #[prost(bool, tag="4")]
+    pub reverse: bool,
+    #[prost(uint32, tag="5")]
+    pub limit: u32,
+}
+/// get the kv pairs whose key starts with prefix in key order
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hprefix {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub prefix: ::prost::alloc::string::String,
+}
+/// get the names of all tables in order
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTables {
+}
+/// remove a table with all of its keys, returns false if it does not exist
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// rename a table, fails if from does not exist or to already exists
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenameTable {
+    #[prost(string, tag="1")]
+    pub from: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub to: ::prost::alloc::string::String,
+}
+/// get the number of keys in a table
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hlen {
+    #[prost(string, tag="1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// authenticate the connection, a server with an authenticator rejects every other command
+/// until it succeeds
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Auth {
+    #[prost(string, tag="1")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub token: ::prost::alloc::string::String,
+}
+/// grant a principal a permission on the tables matching a glob pattern, replacing the
+/// permission it had on the pattern. The permission is `read` to get and scan keys, `write` to
+/// also set, delete and expire them or `admin` to also drop and rename the table.
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AclGrant {
+    #[prost(string, tag="1")]
+    pub principal: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub pattern: ::prost::alloc::string::String,
+    #[prost(string, tag="3")]
+    pub permission: ::prost::alloc::string::String,
+}
+/// revoke the permission of a principal on a pattern, returns false if it had none
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AclRevoke {
+    #[prost(string, tag="1")]
+    pub principal: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub pattern: ::prost::alloc::string::String,
+}
+/// list the patterns of a principal with the permission on each of them, in pattern order
+#[derive(PartialOrd)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AclList {
+    #[prost(string, tag="1")]
+    pub principal: ::prost::alloc::string::String,
+}
@@ -1,6 +1,8 @@
+// generated by build.rs, left as prost writes it so a build leaves it unchanged
+#[rustfmt::skip]
 pub mod abi;
 
-use crate::KvError;
+use crate::{KvError, Permission};
 use abi::{command_request::RequestData, *};
 use http::StatusCode;
 use prost::Message;
@@ -41,6 +43,80 @@ impl CommandRequest {
         }
     }
 
+    pub fn new_hscan(
+        table: impl Into<String>,
+        cursor: impl Into<String>,
+        count: u32,
+        match_pattern: impl Into<String>,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                cursor: cursor.into(),
+                count,
+                match_pattern: match_pattern.into(),
+            })),
+        }
+    }
+
+    pub fn new_hrange(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        reverse: bool,
+        limit: u32,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hrange(Hrange {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                reverse,
+                limit,
+            })),
+        }
+    }
+
+    pub fn new_hprefix(table: impl Into<String>, prefix: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hprefix(Hprefix {
+                table: table.into(),
+                prefix: prefix.into(),
+            })),
+        }
+    }
+
+    pub fn new_list_tables() -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::ListTables(ListTables {})),
+        }
+    }
+
+    pub fn new_drop_table(table: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::DropTable(DropTable {
+                table: table.into(),
+            })),
+        }
+    }
+
+    pub fn new_rename_table(from: impl Into<String>, to: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::RenameTable(RenameTable {
+                from: from.into(),
+                to: to.into(),
+            })),
+        }
+    }
+
+    pub fn new_hlen(table: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hlen(Hlen {
+                table: table.into(),
+            })),
+        }
+    }
+
     pub fn new_hmget(table: impl Into<String>, keys: Vec<String>) -> CommandRequest {
         CommandRequest {
             request_data: Some(RequestData::Hmget(Hmget {
@@ -85,6 +161,190 @@ impl CommandRequest {
             })),
         }
     }
+
+    pub fn new_hsetex(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        ttl: u64,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hsetex(Hsetex {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ttl,
+            })),
+        }
+    }
+
+    pub fn new_expire(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        ttl: u64,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Expire(Expire {
+                table: table.into(),
+                key: key.into(),
+                ttl,
+            })),
+        }
+    }
+
+    pub fn new_ttl(table: impl Into<String>, key: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Ttl(Ttl {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_persist(table: impl Into<String>, key: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Persist(Persist {
+                table: table.into(),
+                key: key.into(),
+            })),
+        }
+    }
+
+    pub fn new_hincrby(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: i64,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hincrby(Hincrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+        }
+    }
+
+    pub fn new_hincrbyfloat(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: f64,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+        }
+    }
+
+    pub fn new_hsetnx(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        CommandRequest {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+        }
+    }
+
+    pub fn new_hcas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Hcas(Hcas {
+                table: table.into(),
+                key: key.into(),
+                expected,
+                new,
+            })),
+        }
+    }
+
+    pub fn new_auth(username: impl Into<String>, token: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Auth(Auth {
+                username: username.into(),
+                token: token.into(),
+            })),
+        }
+    }
+
+    pub fn new_acl_grant(
+        principal: impl Into<String>,
+        pattern: impl Into<String>,
+        permission: Permission,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::AclGrant(AclGrant {
+                principal: principal.into(),
+                pattern: pattern.into(),
+                permission: permission.as_str().into(),
+            })),
+        }
+    }
+
+    pub fn new_acl_revoke(
+        principal: impl Into<String>,
+        pattern: impl Into<String>,
+    ) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::AclRevoke(AclRevoke {
+                principal: principal.into(),
+                pattern: pattern.into(),
+            })),
+        }
+    }
+
+    pub fn new_acl_list(principal: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::AclList(AclList {
+                principal: principal.into(),
+            })),
+        }
+    }
+
+    pub fn new_txn(commands: Vec<CommandRequest>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Txn(Txn { commands })),
+        }
+    }
+
+    pub fn new_subscribe(topic: impl Into<String>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Subscribe(Subscribe {
+                topic: topic.into(),
+            })),
+        }
+    }
+
+    pub fn new_unsubscribe(topic: impl Into<String>, id: u32) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Unsubscribe(Unsubscribe {
+                topic: topic.into(),
+                id,
+            })),
+        }
+    }
+
+    pub fn new_publish(topic: impl Into<String>, data: Vec<Value>) -> CommandRequest {
+        CommandRequest {
+            request_data: Some(RequestData::Publish(Publish {
+                topic: topic.into(),
+                data,
+            })),
+        }
+    }
+}
+
+impl CommandResponse {
+    pub fn ok() -> Self {
+        CommandResponse {
+            status: StatusCode::OK.as_u16() as _,
+            ..Default::default()
+        }
+    }
 }
 
 impl Kvpair {
@@ -120,6 +380,14 @@ impl From<f64> for Value {
     }
 }
 
+impl From<Vec<u8>> for Value {
+    fn from(buf: Vec<u8>) -> Self {
+        Self {
+            value: Some(value::Value::Binary(buf.into())),
+        }
+    }
+}
+
 impl From<bool> for Value {
     fn from(b: bool) -> Self {
         Self {
@@ -128,6 +396,28 @@ impl From<bool> for Value {
     }
 }
 
+impl TryFrom<&Value> for i64 {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Integer(i)) => Ok(i),
+            _ => Err(KvError::ConvertError(v.clone(), "Integer")),
+        }
+    }
+}
+
+impl TryFrom<&Value> for f64 {
+    type Error = KvError;
+
+    fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Float(f)) => Ok(f),
+            _ => Err(KvError::ConvertError(v.clone(), "Float")),
+        }
+    }
+}
+
 impl TryFrom<&[u8]> for Value {
     type Error = KvError;
 
@@ -157,6 +447,16 @@ impl From<Value> for CommandResponse {
     }
 }
 
+impl From<Vec<Value>> for CommandResponse {
+    fn from(v: Vec<Value>) -> CommandResponse {
+        CommandResponse {
+            status: StatusCode::OK.as_u16() as _,
+            values: v,
+            ..Default::default()
+        }
+    }
+}
+
 impl From<Vec<Kvpair>> for CommandResponse {
     fn from(v: Vec<Kvpair>) -> CommandResponse {
         CommandResponse {
@@ -177,6 +477,16 @@ impl From<Kvpair> for CommandResponse {
     }
 }
 
+impl From<Vec<CommandResponse>> for CommandResponse {
+    fn from(responses: Vec<CommandResponse>) -> Self {
+        CommandResponse {
+            status: StatusCode::OK.as_u16() as _,
+            responses,
+            ..Default::default()
+        }
+    }
+}
+
 impl From<KvError> for CommandResponse {
     fn from(e: KvError) -> Self {
         let mut r = CommandResponse {
@@ -186,7 +496,12 @@ impl From<KvError> for CommandResponse {
         };
 
         match e {
-            KvError::NotFound(_, _) => r.status = StatusCode::NOT_FOUND.as_u16() as _,
+            KvError::NotFound(_, _) | KvError::TableNotFound(_) => {
+                r.status = StatusCode::NOT_FOUND.as_u16() as _
+            }
+            KvError::TableExists(_) => r.status = StatusCode::CONFLICT.as_u16() as _,
+            KvError::Unauthorized(_) => r.status = StatusCode::UNAUTHORIZED.as_u16() as _,
+            KvError::PermissionDenied(_) => r.status = StatusCode::FORBIDDEN.as_u16() as _,
             KvError::InvalidCommand(_) => r.status = StatusCode::BAD_REQUEST.as_u16() as _,
             _ => {}
         }
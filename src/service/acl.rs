@@ -0,0 +1,327 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{PoisonError, RwLock},
+};
+
+use super::command_service::glob_match;
+use crate::{command_request::RequestData, CommandRequest, KvError};
+
+/// what a principal may do with a table, every permission includes the ones before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Permission {
+    /// get and scan keys
+    Read,
+    /// set, delete and expire keys
+    Write,
+    /// drop and rename the table
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(KvError::InvalidCommand(format!(
+                "unknown permission: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// which tables every principal may access and how, as glob patterns of tables with a
+/// permission each. A principal may run a command on a table if one of its patterns matches the
+/// table with at least the permission the command needs, a principal without any rule has no
+/// access at all. Only the acl admins may manage the acl, whatever their rules on tables are.
+/// Topics are no tables, the acl does not restrict publishing and subscribing.
+#[derive(Debug, Default)]
+pub struct Acl {
+    /// principal -> pattern -> permission
+    rules: RwLock<HashMap<String, BTreeMap<String, Permission>>>,
+    /// the principals which may manage the acl
+    admins: HashSet<String>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// panics if the pattern is empty
+    pub fn with_rule(
+        self,
+        principal: impl Into<String>,
+        pattern: impl Into<String>,
+        permission: Permission,
+    ) -> Self {
+        self.grant(principal, pattern, permission).unwrap();
+        self
+    }
+
+    /// let the principal manage the acl, the acl commands cannot change who may
+    pub fn with_admin(mut self, principal: impl Into<String>) -> Self {
+        self.admins.insert(principal.into());
+        self
+    }
+
+    /// grant the principal the permission on the tables matching the pattern,
+    /// replacing the permission it had on the pattern. An empty pattern is refused,
+    /// it would only match a table without a name.
+    pub fn grant(
+        &self,
+        principal: impl Into<String>,
+        pattern: impl Into<String>,
+        permission: Permission,
+    ) -> Result<(), KvError> {
+        let pattern = pattern.into();
+        if pattern.is_empty() {
+            return Err(KvError::InvalidCommand("Acl pattern is empty".into()));
+        }
+        self.rules
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(principal.into())
+            .or_default()
+            .insert(pattern, permission);
+        Ok(())
+    }
+
+    /// revoke the permission of the principal on the pattern, returns false if it had none
+    pub fn revoke(&self, principal: &str, pattern: &str) -> bool {
+        let mut rules = self.rules.write().unwrap_or_else(PoisonError::into_inner);
+        let patterns = match rules.get_mut(principal) {
+            Some(patterns) => patterns,
+            None => return false,
+        };
+        let revoked = patterns.remove(pattern).is_some();
+        if patterns.is_empty() {
+            rules.remove(principal);
+        }
+        revoked
+    }
+
+    /// the patterns of the principal with the permission on each of them, in pattern order
+    pub fn rules(&self, principal: &str) -> Vec<(String, Permission)> {
+        let rules = self.rules.read().unwrap_or_else(PoisonError::into_inner);
+        rules
+            .get(principal)
+            .map(|patterns| patterns.iter().map(|(p, v)| (p.clone(), *v)).collect())
+            .unwrap_or_default()
+    }
+
+    /// the highest permission of the principal on the table, `None` if it has no access
+    pub fn permission(&self, principal: &str, table: &str) -> Option<Permission> {
+        let rules = self.rules.read().unwrap_or_else(PoisonError::into_inner);
+        rules
+            .get(principal)?
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, table))
+            .map(|(_, permission)| *permission)
+            .max()
+    }
+
+    /// `KvError::PermissionDenied` unless the principal has at least the permission on the
+    /// table, a connection which did not authenticate has no principal and no access
+    pub fn check(
+        &self,
+        principal: Option<&str>,
+        table: &str,
+        needed: Permission,
+    ) -> Result<(), KvError> {
+        let granted = principal.and_then(|principal| self.permission(principal, table));
+        match granted {
+            Some(granted) if granted >= needed => Ok(()),
+            _ => Err(KvError::PermissionDenied(format!(
+                "{} has no {} permission on table {}",
+                principal.unwrap_or("an anonymous connection"),
+                needed,
+                table
+            ))),
+        }
+    }
+
+    /// `KvError::PermissionDenied` unless the principal is an acl admin
+    pub fn check_admin(&self, principal: Option<&str>) -> Result<(), KvError> {
+        match principal {
+            Some(principal) if self.admins.contains(principal) => Ok(()),
+            _ => Err(KvError::PermissionDenied(format!(
+                "{} may not manage the acl",
+                principal.unwrap_or("an anonymous connection")
+            ))),
+        }
+    }
+}
+
+/// the tables the command accesses and the permission it needs on each of them.
+/// ListTables lists only the tables the principal may read. Pub/sub commands access topics
+/// and acl commands the acl, so they need no permission on any table.
+pub(crate) fn required_permissions(cmd: &CommandRequest) -> Vec<(&str, Permission)> {
+    use Permission::*;
+
+    let required = match &cmd.request_data {
+        Some(RequestData::Hget(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hgetall(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hmget(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hexist(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hmexists(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Ttl(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hscan(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hrange(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hprefix(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hlen(p)) => (p.table.as_str(), Read),
+        Some(RequestData::Hset(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hmset(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hdel(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hmdel(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hsetex(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Expire(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Persist(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hincrby(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hincrbyfloat(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hsetnx(p)) => (p.table.as_str(), Write),
+        Some(RequestData::Hcas(p)) => (p.table.as_str(), Write),
+        Some(RequestData::DropTable(p)) => (p.table.as_str(), Admin),
+        Some(RequestData::RenameTable(p)) => {
+            return vec![(p.from.as_str(), Admin), (p.to.as_str(), Admin)]
+        }
+        Some(RequestData::Txn(p)) => {
+            return p.commands.iter().flat_map(required_permissions).collect()
+        }
+        Some(
+            RequestData::AclGrant(_)
+            | RequestData::AclRevoke(_)
+            | RequestData::AclList(_)
+            | RequestData::ListTables(_)
+            | RequestData::Subscribe(_)
+            | RequestData::Unsubscribe(_)
+            | RequestData::Publish(_)
+            | RequestData::Auth(_),
+        )
+        | None => return vec![],
+    };
+    vec![required]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acl_should_check_permissions() {
+        let acl = Acl::new()
+            .with_rule("reader", "sessions", Permission::Read)
+            .with_rule("writer", "config*", Permission::Write)
+            .with_rule("writer", "config_locked", Permission::Read)
+            .with_rule("root", "*", Permission::Admin)
+            .with_admin("security");
+
+        assert!(acl
+            .check(Some("reader"), "sessions", Permission::Read)
+            .is_ok());
+        assert!(acl
+            .check(Some("reader"), "sessions", Permission::Write)
+            .is_err());
+        assert!(acl
+            .check(Some("reader"), "config", Permission::Read)
+            .is_err());
+        // the highest permission of all matching patterns counts
+        assert!(acl
+            .check(Some("writer"), "config_locked", Permission::Write)
+            .is_ok());
+        assert!(acl
+            .check(Some("writer"), "config", Permission::Admin)
+            .is_err());
+        // managing the acl is no permission on a table, not even on a table named `*`
+        assert!(acl.check(Some("root"), "*", Permission::Admin).is_ok());
+        assert!(matches!(
+            acl.check_admin(Some("root")),
+            Err(KvError::PermissionDenied(_))
+        ));
+        assert!(acl.check_admin(Some("security")).is_ok());
+        assert!(acl
+            .check(Some("security"), "sessions", Permission::Read)
+            .is_err());
+        assert!(acl.check_admin(None).is_err());
+        assert!(matches!(
+            acl.check(None, "sessions", Permission::Read),
+            Err(KvError::PermissionDenied(_))
+        ));
+        assert!(acl
+            .check(Some("nobody"), "sessions", Permission::Read)
+            .is_err());
+    }
+
+    #[test]
+    fn acl_should_grant_and_revoke() {
+        let acl = Acl::new();
+        acl.grant("alice", "t*", Permission::Read).unwrap();
+        acl.grant("alice", "t*", Permission::Write).unwrap();
+        acl.grant("alice", "a", Permission::Read).unwrap();
+        assert!(matches!(
+            acl.grant("alice", "", Permission::Read),
+            Err(KvError::InvalidCommand(_))
+        ));
+        assert_eq!(
+            acl.rules("alice"),
+            vec![
+                ("a".into(), Permission::Read),
+                ("t*".into(), Permission::Write)
+            ]
+        );
+
+        assert!(acl.revoke("alice", "t*"));
+        assert!(!acl.revoke("alice", "t*"));
+        assert!(!acl.revoke("bob", "a"));
+        assert_eq!(acl.permission("alice", "t1"), None);
+    }
+
+    #[test]
+    fn required_permissions_should_cover_txn_and_rename() {
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hget("t1", "k1"),
+            CommandRequest::new_hset("t2", "k1", "v1".into()),
+        ]);
+        assert_eq!(
+            required_permissions(&cmd),
+            vec![("t1", Permission::Read), ("t2", Permission::Write)]
+        );
+
+        let cmd = CommandRequest::new_rename_table("t1", "t2");
+        assert_eq!(
+            required_permissions(&cmd),
+            vec![("t1", Permission::Admin), ("t2", Permission::Admin)]
+        );
+        let cmd = CommandRequest::new_publish("lobby", vec![]);
+        assert!(required_permissions(&cmd).is_empty());
+    }
+
+    #[test]
+    fn permission_should_parse() {
+        for permission in [Permission::Read, Permission::Write, Permission::Admin] {
+            assert_eq!(permission.as_str().parse(), Ok(permission));
+        }
+        assert!("root".parse::<Permission>().is_err());
+    }
+}
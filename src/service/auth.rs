@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::KvError;
+
+/// checks the credentials a connection authenticates with. It is called on the runtime
+/// driving the connection, so it should not block.
+pub trait Authenticator: Send + Sync + 'static {
+    /// Ok if the token is valid for the user, `KvError::Unauthorized` otherwise
+    fn authenticate(&self, username: &str, token: &str) -> Result<(), KvError>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&str, &str) -> Result<(), KvError> + Send + Sync + 'static,
+{
+    fn authenticate(&self, username: &str, token: &str) -> Result<(), KvError> {
+        self(username, token)
+    }
+}
+
+/// a fixed set of users, each with its token
+#[derive(Debug, Default, Clone)]
+pub struct StaticTokens {
+    tokens: HashMap<String, String>,
+}
+
+impl StaticTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user(mut self, username: impl Into<String>, token: impl Into<String>) -> Self {
+        self.tokens.insert(username.into(), token.into());
+        self
+    }
+}
+
+impl Authenticator for StaticTokens {
+    fn authenticate(&self, username: &str, token: &str) -> Result<(), KvError> {
+        match self.tokens.get(username) {
+            Some(expected) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => Ok(()),
+            // an unknown user and a wrong token fail alike
+            _ => Err(KvError::Unauthorized("invalid username or token".into())),
+        }
+    }
+}
+
+/// compare without returning early, so the time taken does not tell how much of a token
+/// was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_tokens_should_check_credentials() {
+        let auth = StaticTokens::new()
+            .with_user("alice", "secret")
+            .with_user("bob", "hunter2");
+        assert_eq!(auth.authenticate("alice", "secret"), Ok(()));
+        assert_eq!(auth.authenticate("bob", "hunter2"), Ok(()));
+        assert!(matches!(
+            auth.authenticate("alice", "hunter2"),
+            Err(KvError::Unauthorized(_))
+        ));
+        assert!(auth.authenticate("alice", "secret1").is_err());
+        assert!(auth.authenticate("carol", "secret").is_err());
+    }
+
+    #[test]
+    fn closure_should_work_as_authenticator() {
+        let auth = |username: &str, _: &str| match username {
+            "admin" => Ok(()),
+            _ => Err(KvError::Unauthorized(format!(
+                "{} is not allowed",
+                username
+            ))),
+        };
+        assert_eq!(auth.authenticate("admin", ""), Ok(()));
+        assert!(auth.authenticate("guest", "").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use crate::*;
 
 impl CommandService for Hset {
@@ -13,17 +15,23 @@ impl CommandService for Hset {
     }
 }
 
-impl CommandService for Hmset {
+impl CommandService for Hsetex {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        let mut pairs: Vec<Kvpair> = Vec::new();
-        for pair in self.pairs {
-            match store.set(&self.table, &pair.key, pair.value.unwrap_or_default()) {
-                Ok(Some(v)) => pairs.push(Kvpair::new(pair.key, v)),
-                Ok(None) => pairs.push(Kvpair::new(pair.key, Value::default())),
-                Err(e) => return e.into(),
-            }
+        let ttl = Duration::from_millis(self.ttl);
+        match self.pair {
+            Some(v) => match store.set_ex(&self.table, &v.key, v.value.unwrap_or_default(), ttl) {
+                Ok(Some(v)) => v.into(),
+                Ok(None) => Value::default().into(),
+                Err(e) => e.into(),
+            },
+            None => Value::default().into(),
         }
-        pairs.into()
+    }
+}
+
+impl CommandService for Hmset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        execute_atomically(&self, store)
     }
 }
 
@@ -46,6 +54,167 @@ impl CommandService for Hgetall {
     }
 }
 
+impl CommandService for Hrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let limit = self.limit as usize;
+        match store.range(&self.table, &self.start, &self.end, self.reverse, limit) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hprefix {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.prefix(&self.table, &self.prefix) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for ListTables {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_tables() {
+            Ok(v) => v
+                .iter()
+                .map(|t| t.as_str().into())
+                .collect::<Vec<Value>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for DropTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.drop_table(&self.table) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for RenameTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.rename_table(&self.from, &self.to) {
+            Ok(()) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hlen {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.len(&self.table) {
+            Ok(v) => Value::from(v as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// how many keys a Hscan without count examines
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = match self.count {
+            0 => DEFAULT_SCAN_COUNT,
+            n => n as usize,
+        };
+        // the smallest key after the last key examined by the previous call
+        let start = match decode_cursor(&self.cursor) {
+            Ok(Some(last)) => format!("{}\0", last),
+            Ok(None) => String::new(),
+            Err(e) => return e.into(),
+        };
+
+        // the cursor is the last key examined by the previous call, so the scan neither repeats
+        // nor skips keys no matter what changes in between. Like the COUNT of a Redis SCAN,
+        // count caps the keys examined rather than the ones returned, so with a pattern a call
+        // may return fewer pairs than count, or none, along with the cursor to go on from.
+        let mut pairs = match store.range(&self.table, &start, "", false, count + 1) {
+            Ok(pairs) => pairs,
+            Err(e) => return e.into(),
+        };
+        let cursor = match pairs.len() > count {
+            true => {
+                pairs.truncate(count);
+                encode_cursor(&pairs[count - 1].key)
+            }
+            false => String::new(),
+        };
+        let page: Vec<Kvpair> = pairs
+            .into_iter()
+            .filter(|pair| glob_match(&self.match_pattern, &pair.key))
+            .collect();
+
+        let mut res: CommandResponse = page.into();
+        res.values.push(cursor.as_str().into());
+        res
+    }
+}
+
+/// the cursor of the page after the key, the key hex encoded so clients treat it as opaque
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// the last key of the previous page, `None` for the first page
+fn decode_cursor(cursor: &str) -> Result<Option<String>, KvError> {
+    if cursor.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || KvError::InvalidCommand(format!("invalid Hscan cursor: {}", cursor));
+    let digit = |b: &u8| (*b as char).to_digit(16);
+    let bytes = cursor
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some((digit(hi)? * 16 + digit(lo)?) as u8),
+            _ => None,
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    String::from_utf8(bytes).map(Some).map_err(|_| invalid())
+}
+
+/// match the key against a glob pattern, `*` matches any sequence and `?` any character,
+/// an empty pattern matches every key
+pub(crate) fn glob_match(pattern: &str, key: &str) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+    // where the last `*` is in the pattern and how much of the key it matched
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more character and retry
+                Some((sp, sk)) => {
+                    star = Some((sp, sk + 1));
+                    p = sp + 1;
+                    k = sk + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 impl CommandService for Hmget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         let mut pairs: Vec<Kvpair> = Vec::new();
@@ -71,15 +240,7 @@ impl CommandService for Hdel {
 
 impl CommandService for Hmdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
-        let mut pairs: Vec<Kvpair> = Vec::new();
-        for key in self.keys {
-            match store.del(&self.table, &key) {
-                Ok(Some(v)) => pairs.push(Kvpair::new(key, v)),
-                Ok(None) => pairs.push(Kvpair::new(key, Value::default())),
-                Err(e) => return e.into(),
-            }
-        }
-        pairs.into()
+        execute_atomically(&self, store)
     }
 }
 
@@ -106,12 +267,111 @@ impl CommandService for Hmexists {
     }
 }
 
+impl CommandService for Expire {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.expire(&self.table, &self.key, Duration::from_millis(self.ttl)) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Ttl {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.ttl(&self.table, &self.key) {
+            Ok(Some(ttl)) => Value::from(ttl.as_millis() as i64).into(),
+            Ok(None) => Value::from(-1).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Persist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.persist(&self.table, &self.key) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrby {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.incr(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrbyfloat {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.incr_float(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match self.pair {
+            Some(v) => {
+                let value = v.value.unwrap_or_default();
+                swap_response(store.compare_and_swap(&self.table, &v.key, None, Some(value)))
+            }
+            None => KvError::InvalidCommand("Hsetnx has no kv pair".into()).into(),
+        }
+    }
+}
+
+impl CommandService for Hcas {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let res = store.compare_and_swap(&self.table, &self.key, self.expected, self.new);
+        swap_response(res)
+    }
+}
+
+/// `[true]` if the swap succeeded, otherwise `[false, current value]`
+fn swap_response(res: Result<Result<(), CompareAndSwapError>, KvError>) -> CommandResponse {
+    match res {
+        Ok(Ok(())) => Value::from(true).into(),
+        Ok(Err(e)) => vec![false.into(), e.current.unwrap_or_default()].into(),
+        Err(e) => e.into(),
+    }
+}
+
+impl CommandService for Txn {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let res = store.transaction(|txn| {
+            self.commands
+                .iter()
+                .map(|cmd| dispatch_txn(cmd, txn))
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match res {
+            Ok(responses) => responses.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// run the command in its own transaction, so either all of its writes apply or none does
+fn execute_atomically(cmd: &impl TxnService, store: &impl Storage) -> CommandResponse {
+    match store.transaction(|txn| cmd.execute_txn(txn)) {
+        Ok(res) => res,
+        Err(e) => e.into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::thread;
+
     use super::*;
-    use crate::command_request::RequestData;
     use crate::memory::MemTable;
     use crate::service::*;
+    use crate::sleddb::SledDb;
 
     #[test]
     fn hset_should_work() {
@@ -300,17 +560,337 @@ mod tests {
         )
     }
 
-    fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
-        match cmd.request_data.unwrap() {
-            RequestData::Hset(cmd) => cmd.execute(store),
-            RequestData::Hget(cmd) => cmd.execute(store),
-            RequestData::Hdel(cmd) => cmd.execute(store),
-            RequestData::Hgetall(cmd) => cmd.execute(store),
-            RequestData::Hmget(cmd) => cmd.execute(store),
-            RequestData::Hmset(cmd) => cmd.execute(store),
-            RequestData::Hmdel(cmd) => cmd.execute(store),
-            RequestData::Hexist(cmd) => cmd.execute(store),
-            RequestData::Hmexists(cmd) => cmd.execute(store),
+    #[test]
+    fn hsetex_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hsetex("t1", "k1", "v1".into(), 50);
+        let res = dispatch(cmd.clone(), &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        thread::sleep(Duration::from_millis(100));
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn expire_and_ttl_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_expire("t1", "k1", 1000), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+        let res = dispatch(CommandRequest::new_ttl("t1", "k1"), &store);
+        assert_res_ok(res, &[(-1).into()], &[]);
+
+        let res = dispatch(CommandRequest::new_expire("t1", "k1", 60_000), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_ttl("t1", "k1"), &store);
+        let ttl: i64 = (&res.values[0]).try_into().unwrap();
+        assert!(ttl > 50_000 && ttl <= 60_000);
+
+        let res = dispatch(CommandRequest::new_ttl("t1", "k2"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn persist_should_work() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hsetex("t1", "k1", "v1".into(), 50),
+            &store,
+        );
+
+        let res = dispatch(CommandRequest::new_persist("t1", "k1"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_persist("t1", "k1"), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        thread::sleep(Duration::from_millis(100));
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(res, &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn hincrby_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincrby("t1", "k1", 5), &store);
+        assert_res_ok(res, &[5.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hincrby("t1", "k1", -8), &store);
+        assert_res_ok(res, &[(-3).into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k2", "v2".into()), &store);
+        let res = dispatch(CommandRequest::new_hincrby("t1", "k2", 1), &store);
+        assert_res_error(res, 500, "Integer");
+    }
+
+    #[test]
+    fn hincrbyfloat_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincrbyfloat("t1", "k1", 0.5), &store);
+        assert_res_ok(res, &[0.5.into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+        let res = dispatch(CommandRequest::new_hincrbyfloat("t1", "k2", 0.5), &store);
+        assert_res_ok(res, &[2.5.into()], &[]);
+
+        dispatch(CommandRequest::new_hset("t1", "k3", true.into()), &store);
+        let res = dispatch(CommandRequest::new_hincrbyfloat("t1", "k3", 1.0), &store);
+        assert_res_error(res, 500, "Float");
+    }
+
+    #[test]
+    fn hsetnx_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "k1", "v1".into()), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hsetnx("t1", "k1", "v2".into()), &store);
+        assert_res_ok(res, &[false.into(), "v1".into()], &[]);
+    }
+
+    #[test]
+    fn hcas_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+
+        let cmd = CommandRequest::new_hcas("t1", "k1", Some(2.into()), Some(3.into()));
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into(), 1.into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "k1", Some(1.into()), Some(3.into()));
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "k1", Some(3.into()), None);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let cmd = CommandRequest::new_hcas("t1", "k1", Some(3.into()), None);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[false.into(), Value::default()], &[]);
+    }
+
+    #[test]
+    fn hscan_should_page_through_the_table() {
+        let store = MemTable::new();
+        for i in 0..25 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{:02}", i), i.into());
+            dispatch(cmd, &store);
+        }
+
+        let mut cursor = String::new();
+        let mut keys = Vec::new();
+        let mut pages = 0;
+        loop {
+            let res = dispatch(CommandRequest::new_hscan("t1", cursor, 10, ""), &store);
+            assert_eq!(res.status, 200);
+            assert!(res.pairs.len() <= 10);
+            keys.extend(res.pairs.into_iter().map(|pair| pair.key));
+            pages += 1;
+
+            cursor = match &res.values[0].value {
+                Some(value::Value::String(s)) => s.clone(),
+                v => panic!("cursor should be a string, got {:?}", v),
+            };
+            if cursor.is_empty() {
+                break;
+            }
         }
+
+        assert_eq!(pages, 3);
+        let expected: Vec<String> = (0..25).map(|i| format!("k{:02}", i)).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn hscan_should_not_repeat_keys_after_changes() {
+        let store = MemTable::new();
+        for key in ["a", "c", "e", "g"] {
+            dispatch(CommandRequest::new_hset("t1", key, 1.into()), &store);
+        }
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 2, ""), &store);
+        assert_res_ok(
+            res,
+            &[encode_cursor("c").as_str().into()],
+            &[Kvpair::new("a", 1.into()), Kvpair::new("c", 1.into())],
+        );
+
+        // keys before the cursor are not visited again, keys after it are
+        dispatch(CommandRequest::new_hset("t1", "b", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "f", 1.into()), &store);
+        dispatch(CommandRequest::new_hdel("t1", "e"), &store);
+
+        // the last page is known to be the last one, so it returns an empty cursor
+        let cursor = encode_cursor("c");
+        let res = dispatch(CommandRequest::new_hscan("t1", cursor, 2, ""), &store);
+        assert_res_ok(
+            res,
+            &["".into()],
+            &[Kvpair::new("f", 1.into()), Kvpair::new("g", 1.into())],
+        );
+    }
+
+    #[test]
+    fn hscan_should_filter_by_pattern() {
+        let store = MemTable::new();
+        for key in ["user:1", "user:2", "user:10", "session:1"] {
+            dispatch(CommandRequest::new_hset("t1", key, 1.into()), &store);
+        }
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 0, "user:?"), &store);
+        let pairs = &[
+            Kvpair::new("user:1", 1.into()),
+            Kvpair::new("user:2", 1.into()),
+        ];
+        assert_res_ok(res, &["".into()], pairs);
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 0, "*:1*"), &store);
+        let pairs = &[
+            Kvpair::new("session:1", 1.into()),
+            Kvpair::new("user:1", 1.into()),
+            Kvpair::new("user:10", 1.into()),
+        ];
+        assert_res_ok(res, &["".into()], pairs);
+    }
+
+    #[test]
+    fn hscan_should_work_with_sleddb() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledDb::new(dir);
+        for i in (0..5).rev() {
+            dispatch(
+                CommandRequest::new_hset("t1", format!("k{}", i), i.into()),
+                &store,
+            );
+        }
+        // a table whose name shares the prefix is not part of the scan
+        dispatch(CommandRequest::new_hset("t10", "k0", 0.into()), &store);
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 3, ""), &store);
+        let pairs = &[
+            Kvpair::new("k0", 0.into()),
+            Kvpair::new("k1", 1.into()),
+            Kvpair::new("k2", 2.into()),
+        ];
+        assert_res_ok(res, &[encode_cursor("k2").as_str().into()], pairs);
+    }
+
+    #[test]
+    fn hscan_should_examine_at_most_count_keys() {
+        let store = MemTable::new();
+        for i in 0..1000 {
+            let key = format!("k{:03}", i);
+            dispatch(CommandRequest::new_hset("t1", key, i.into()), &store);
+        }
+
+        // count limits the keys examined, not the keys returned
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 150, "k?00"), &store);
+        let pairs = &[
+            Kvpair::new("k000", 0.into()),
+            Kvpair::new("k100", 100.into()),
+        ];
+        assert_res_ok(res, &[encode_cursor("k149").as_str().into()], pairs);
+
+        // a call without any match still returns the cursor to go on from
+        let cursor = encode_cursor("k100");
+        let res = dispatch(CommandRequest::new_hscan("t1", cursor, 50, "k?00"), &store);
+        assert_res_ok(res, &[encode_cursor("k150").as_str().into()], &[]);
+
+        let cursor = encode_cursor("k800");
+        let res = dispatch(CommandRequest::new_hscan("t1", cursor, 200, "k?00"), &store);
+        assert_res_ok(res, &["".into()], &[Kvpair::new("k900", 900.into())]);
+    }
+
+    #[test]
+    fn hscan_should_reject_invalid_cursor() {
+        let store = MemTable::new();
+        for cursor in ["k1", "6", "zz", "ff"] {
+            let res = dispatch(CommandRequest::new_hscan("t1", cursor, 2, ""), &store);
+            assert_res_error(res, 400, "invalid Hscan cursor");
+        }
+    }
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match("", "anything"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*b*", "abc"));
+        assert!(!glob_match("a?c", "abbc"));
+        assert!(!glob_match("a*d", "abc"));
+        assert!(!glob_match("abc", "ab"));
+    }
+
+    #[test]
+    fn hrange_should_work() {
+        let store = MemTable::new();
+        for ts in ["1000", "1010", "1020", "1030"] {
+            dispatch(CommandRequest::new_hset("metrics", ts, ts.into()), &store);
+        }
+
+        let res = dispatch(
+            CommandRequest::new_hrange("metrics", "1010", "1030", false, 0),
+            &store,
+        );
+        assert_eq!(res.status, 200);
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["1010", "1020"]);
+
+        // the latest entry
+        let res = dispatch(
+            CommandRequest::new_hrange("metrics", "", "", true, 1),
+            &store,
+        );
+        assert_res_ok(res, &[], &[Kvpair::new("1030", "1030".into())]);
+    }
+
+    #[test]
+    fn hprefix_should_work() {
+        let store = MemTable::new();
+        for key in ["/a/b", "/a/c", "/b/a"] {
+            dispatch(CommandRequest::new_hset("fs", key, 1.into()), &store);
+        }
+
+        let res = dispatch(CommandRequest::new_hprefix("fs", "/a/"), &store);
+        let pairs = &[Kvpair::new("/a/b", 1.into()), Kvpair::new("/a/c", 1.into())];
+        assert_res_ok(res, &[], pairs);
+    }
+
+    #[test]
+    fn table_commands_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t2", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t1", "k2", 2.into()), &store);
+
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+        let res = dispatch(CommandRequest::new_hlen("t1"), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_rename_table("t1", "t2"), &store);
+        assert_res_error(res, 409, "already exists");
+        let res = dispatch(CommandRequest::new_rename_table("t3", "t4"), &store);
+        assert_res_error(res, 404, "not found");
+        let res = dispatch(CommandRequest::new_rename_table("t1", "t3"), &store);
+        assert_res_ok(res, &[], &[]);
+        let res = dispatch(CommandRequest::new_hget("t3", "k2"), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_drop_table("t3"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_drop_table("t3"), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+        let res = dispatch(CommandRequest::new_hlen("t3"), &store);
+        assert_res_ok(res, &[0.into()], &[]);
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t2".into()], &[]);
     }
 }
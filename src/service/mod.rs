@@ -1,201 +1,1181 @@
+mod acl;
+mod auth;
 mod command_service;
+mod topic;
+mod topic_service;
+#[cfg(feature = "tower")]
+mod tower_service;
+mod txn_service;
 
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use tracing::debug;
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 
 use crate::{
-    command_request::RequestData, memory::MemTable, CommandRequest, CommandResponse, KvError,
-    Storage,
+    command_request::RequestData, memory::MemTable, value, AclGrant, AclList, AclRevoke, Auth,
+    BlockingStorage, CommandRequest, CommandResponse, KvError, Kvpair, Storage, Transaction, Value,
 };
 
+pub use acl::{Acl, Permission};
+pub use auth::{Authenticator, StaticTokens};
+pub use topic::Broadcaster;
+pub use topic_service::{StreamingResponse, TopicService};
+pub use txn_service::TxnService;
+
 pub trait CommandService {
     fn execute(self, store: &impl Storage) -> CommandResponse;
 }
 
-pub trait Notify<Arg> {
-    fn notify(&self, arg: &Arg);
-}
+type AsyncFn<Arg, R> = Box<dyn for<'a> Fn(&'a Arg) -> BoxFuture<'a, R> + Send + Sync>;
+type AsyncFnMut<Arg> = Box<dyn for<'a> Fn(&'a mut Arg) -> BoxFuture<'a, ()> + Send + Sync>;
 
-pub trait NotifyMut<Arg> {
-    fn notify(&self, arg: &mut Arg);
+/// a hook called with an event, an async hook returns a future of its result
+enum Hook<Arg, R> {
+    Sync(Box<dyn Fn(&Arg) -> R + Send + Sync>),
+    Async(AsyncFn<Arg, R>),
 }
 
-impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
-    fn notify(&self, arg: &Arg) {
-        for f in self {
-            f(arg);
+impl<Arg, R> Hook<Arg, R> {
+    async fn call(&self, arg: &Arg) -> R {
+        match self {
+            Hook::Sync(f) => f(arg),
+            Hook::Async(f) => f(arg).await,
         }
     }
 }
 
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
-    fn notify(&self, arg: &mut Arg) {
-        for f in self {
-            f(arg);
+/// a hook which may change the event it is called with
+enum HookMut<Arg> {
+    Sync(Box<dyn Fn(&mut Arg) + Send + Sync>),
+    Async(AsyncFnMut<Arg>),
+}
+
+impl<Arg> HookMut<Arg> {
+    async fn call(&self, arg: &mut Arg) {
+        match self {
+            HookMut::Sync(f) => f(arg),
+            HookMut::Async(f) => f(arg).await,
         }
     }
 }
 
 pub struct Service<Store = MemTable> {
     inner: Arc<ServiceInner<Store>>,
+    broadcaster: Arc<Broadcaster>,
+    caller: Caller,
 }
 
 impl<Store> Clone for Service<Store> {
     fn clone(&self) -> Self {
         Service {
             inner: Arc::clone(&self.inner),
+            broadcaster: Arc::clone(&self.broadcaster),
+            caller: self.caller.clone(),
         }
     }
 }
 
+/// the next id of a session, unique in the process
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) fn next_session_id() -> u64 {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// who the commands executed by a service handle come from, the acl only applies to connections
+#[derive(Debug, Clone)]
+enum Caller {
+    /// the application embedding the service, it may run any command
+    Server,
+    /// a client connection with its session and the principal it authenticated as
+    Connection {
+        session: u64,
+        principal: Option<Arc<str>>,
+    },
+}
+
+/// a response frame the server sent, passed to the after send hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SentResponse {
+    /// the id the service gave the request, a subscription sends every frame with its id
+    pub request_id: u64,
+    /// size of the frame in bytes, header included
+    pub size: usize,
+    /// time from receiving the request until the frame was flushed
+    pub elapsed: Duration,
+}
+
 pub struct ServiceInner<Store> {
-    store: Store,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_before_send: Vec<fn(&mut CommandResponse)>,
-    on_after_send: Vec<fn()>,
+    store: BlockingStorage<Store>,
+    /// called in order, the first one returning a response answers the request
+    on_received: Vec<Hook<CommandRequest, Option<CommandResponse>>>,
+    on_executed: Vec<Hook<CommandResponse, ()>>,
+    on_before_send: Vec<HookMut<CommandResponse>>,
+    on_after_send: Vec<Hook<SentResponse, ()>>,
+    /// the id of the last request received
+    request_id: AtomicU64,
+    /// connections have to authenticate with it before sending any other command
+    authenticator: Option<Box<dyn Authenticator>>,
+    /// what the principals of connections may do with every table
+    acl: Option<Acl>,
 }
 
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     fn from(inner: ServiceInner<Store>) -> Self {
         Self {
             inner: Arc::new(inner),
+            broadcaster: Default::default(),
+            caller: Caller::Server,
         }
     }
 }
 
 impl<Store: Storage> Service<Store> {
-    pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+    /// execute the command, storage commands and publish/unsubscribe yield a single response,
+    /// a subscription yields a response for every publish until it is unsubscribed.
+    /// Storage commands run on the blocking thread pool, so a slow store never blocks the runtime.
+    /// A received hook returning a response answers the command without executing it.
+    /// A connection which has to authenticate and did not gets `KvError::Unauthorized`
+    /// for every command, before any hook sees it.
+    pub async fn execute(&self, cmd: CommandRequest) -> StreamingResponse {
         debug!("Got request: {:?}", cmd);
-        self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
-        debug!("Executed response: {:?}", res);
-        self.inner.on_executed.notify(&res);
-        self.inner.on_before_send.notify(&mut res);
+        if let Err(e) = self.check_authenticated() {
+            info!("Rejected a command of an unauthenticated connection");
+            return Box::pin(stream::once(async { Arc::new(e.into()) }));
+        }
+        let res = match self.inner.received(&cmd).await {
+            Some(res) => {
+                debug!("Request answered by a hook");
+                Box::pin(stream::once(async { Arc::new(res) }))
+            }
+            None => self.dispatch(cmd).await,
+        };
+
+        let inner = Arc::clone(&self.inner);
+        Box::pin(res.then(move |mut res| {
+            let inner = Arc::clone(&inner);
+            async move {
+                debug!("Executed response: {:?}", res);
+                for hook in &inner.on_executed {
+                    hook.call(&res).await;
+                }
+                if !inner.on_before_send.is_empty() {
+                    let res = Arc::make_mut(&mut res);
+                    for hook in &inner.on_before_send {
+                        hook.call(res).await;
+                    }
+                }
+                res
+            }
+        }))
+    }
+
+    /// a new request id, unique among all requests received by the clones of the service
+    pub fn next_request_id(&self) -> u64 {
+        self.inner.request_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// whether connections have to authenticate before sending commands
+    pub fn requires_auth(&self) -> bool {
+        self.inner.authenticator.is_some()
+    }
+
+    /// check the credentials of an Auth command, the principal they authenticate.
+    /// Without an authenticator any are valid but authenticate no principal,
+    /// nothing vouches for the username.
+    pub fn authenticate(&self, auth: &Auth) -> Result<Option<String>, KvError> {
+        match &self.inner.authenticator {
+            Some(authenticator) => authenticator
+                .authenticate(&auth.username, &auth.token)
+                .map(|()| Some(auth.username.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// a handle executing the commands of a connection, checked against the acl for the
+    /// principal the connection authenticated as, `None` before it authenticated.
+    /// The principal is trusted as it is, only pass one which `authenticate` accepted.
+    pub fn for_connection(&self, principal: Option<&str>) -> Self {
+        self.for_session(next_session_id(), principal)
+    }
+
+    /// a handle for one of the connections of a session, like the streams multiplexed over
+    /// one physical connection. The connections of a session may cancel the subscriptions
+    /// of each other, but not those of any other session.
+    pub fn for_session(&self, session: u64, principal: Option<&str>) -> Self {
+        Service {
+            caller: Caller::Connection {
+                session,
+                principal: principal.map(Arc::from),
+            },
+            ..self.clone()
+        }
+    }
+
+    pub fn acl(&self) -> Option<&Acl> {
+        self.inner.acl.as_ref()
+    }
+
+    /// call the after send hooks, a server calls it once it flushed a response frame
+    pub async fn after_send(&self, sent: &SentResponse) {
+        for hook in &self.inner.on_after_send {
+            hook.call(sent).await;
+        }
+    }
+
+    async fn dispatch(&self, cmd: CommandRequest) -> StreamingResponse {
+        if let Err(e) = self.check_access(&cmd) {
+            return Box::pin(stream::once(async { Arc::new(e.into()) }));
+        }
+
+        let res = match cmd.request_data {
+            Some(
+                RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_),
+            ) => return dispatch_stream(cmd, Arc::clone(&self.broadcaster), self.session()),
+            Some(RequestData::AclGrant(param)) => self.acl_grant(param),
+            Some(RequestData::AclRevoke(param)) => self.acl_revoke(param),
+            Some(RequestData::AclList(param)) => self.acl_list(param),
+            Some(RequestData::ListTables(_)) => {
+                let res = self.run(cmd).await;
+                self.readable_tables(res)
+            }
+            _ => self.run(cmd).await,
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+
+    /// execute a storage command on the blocking thread pool
+    async fn run(&self, cmd: CommandRequest) -> CommandResponse {
+        self.inner
+            .store
+            .run(move |store| dispatch(cmd, store))
+            .await
+            .unwrap_or_else(|e| e.into())
+    }
+
+    /// the principal of the connection and the acl it is checked against,
+    /// `None` if the caller is not restricted
+    fn restriction(&self) -> Option<(Option<&str>, &Acl)> {
+        match (&self.caller, &self.inner.acl) {
+            (Caller::Connection { principal, .. }, Some(acl)) => Some((principal.as_deref(), acl)),
+            _ => None,
+        }
+    }
+
+    /// the session of the caller, `None` for the server
+    fn session(&self) -> Option<u64> {
+        match &self.caller {
+            Caller::Server => None,
+            Caller::Connection { session, .. } => Some(*session),
+        }
+    }
+
+    /// `KvError::Unauthorized` if the caller is a connection which has to authenticate first
+    fn check_authenticated(&self) -> Result<(), KvError> {
+        match &self.caller {
+            Caller::Connection {
+                principal: None, ..
+            } if self.requires_auth() => Err(KvError::Unauthorized("authenticate first".into())),
+            _ => Ok(()),
+        }
+    }
+
+    /// `KvError::PermissionDenied` unless the caller may access every table the command does,
+    /// or manage the acl for an acl command
+    fn check_access(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        let (principal, acl) = match self.restriction() {
+            Some(restriction) => restriction,
+            None => return Ok(()),
+        };
+        if let Some(
+            RequestData::AclGrant(_) | RequestData::AclRevoke(_) | RequestData::AclList(_),
+        ) = &cmd.request_data
+        {
+            return acl.check_admin(principal);
+        }
+        acl::required_permissions(cmd)
+            .into_iter()
+            .try_for_each(|(table, needed)| acl.check(principal, table, needed))
+    }
+
+    /// drop the tables the caller may not read from a ListTables response
+    fn readable_tables(&self, mut res: CommandResponse) -> CommandResponse {
+        if let Some((principal, acl)) = self.restriction() {
+            res.values.retain(|v| match (&v.value, principal) {
+                (Some(value::Value::String(table)), Some(principal)) => {
+                    acl.permission(principal, table).is_some()
+                }
+                _ => false,
+            });
+        }
         res
     }
+
+    fn acl_or_err(&self) -> Result<&Acl, KvError> {
+        self.inner
+            .acl
+            .as_ref()
+            .ok_or_else(|| KvError::InvalidCommand("Service has no acl".into()))
+    }
+
+    fn acl_grant(&self, param: AclGrant) -> CommandResponse {
+        let res = self.acl_or_err().and_then(|acl| {
+            let permission = param.permission.parse()?;
+            acl.grant(param.principal, param.pattern, permission)
+        });
+        match res {
+            Ok(()) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        }
+    }
+
+    fn acl_revoke(&self, param: AclRevoke) -> CommandResponse {
+        match self.acl_or_err() {
+            Ok(acl) => Value::from(acl.revoke(&param.principal, &param.pattern)).into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// the patterns of the principal as pairs of pattern and permission
+    fn acl_list(&self, param: AclList) -> CommandResponse {
+        match self.acl_or_err() {
+            Ok(acl) => acl
+                .rules(&param.principal)
+                .into_iter()
+                .map(|(pattern, permission)| Kvpair::new(pattern, permission.as_str().into()))
+                .collect::<Vec<_>>()
+                .into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// periodically remove the expired keys from the store,
+    /// the sweeper stops once every clone of the service is dropped
+    pub fn start_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => break,
+                };
+                match inner.store.run(|store| store.purge_expired()).await {
+                    Ok(Ok(n)) => debug!("Sweeper purged {} expired keys", n),
+                    Ok(Err(e)) | Err(e) => warn!("Sweeper failed to purge expired keys: {:?}", e),
+                }
+            }
+        })
+    }
 }
 
 impl<Store: Storage> ServiceInner<Store> {
     pub fn new(store: Store) -> ServiceInner<Store> {
         ServiceInner {
-            store,
+            store: BlockingStorage::new(store),
             on_received: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
+            request_id: AtomicU64::new(0),
+            authenticator: None,
+            acl: None,
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    /// restrict what the principals of connections may do with every table,
+    /// a connection which did not authenticate may not access any table.
+    /// Only an authenticator gives connections a principal, without one they access nothing.
+    /// Publishing and subscribing to topics is not restricted.
+    pub fn with_acl(mut self, acl: Acl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// require connections to authenticate with an Auth command checked by the authenticator
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator) -> Self {
+        self.authenticator = Some(Box::new(authenticator));
+        self
+    }
+
+    pub fn fn_received(mut self, f: impl Fn(&CommandRequest) + Send + Sync + 'static) -> Self {
+        self.on_received.push(Hook::Sync(Box::new(move |cmd| {
+            f(cmd);
+            None
+        })));
+        self
+    }
+
+    pub fn async_received<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a CommandRequest) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_received.push(Hook::Async(Box::new(move |cmd| {
+            f(cmd).map(|_| None).boxed()
+        })));
+        self
+    }
+
+    /// a received hook which may answer the request itself, a response returned by it is
+    /// sent instead of executing the request, the hooks registered after it are not called
+    pub fn fn_intercept(
+        mut self,
+        f: impl Fn(&CommandRequest) -> Option<CommandResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_received.push(Hook::Sync(Box::new(f)));
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    pub fn async_intercept<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a CommandRequest) -> BoxFuture<'a, Option<CommandResponse>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_received.push(Hook::Async(Box::new(f)));
         self
     }
 
-    pub fn fn_before_send(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_before_send.push(f);
+    pub fn fn_executed(mut self, f: impl Fn(&CommandResponse) + Send + Sync + 'static) -> Self {
+        self.on_executed.push(Hook::Sync(Box::new(f)));
         self
     }
 
-    pub fn fn_after_send(mut self, f: fn()) -> Self {
-        self.on_after_send.push(f);
+    pub fn async_executed<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a CommandResponse) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_executed.push(Hook::Async(Box::new(f)));
         self
     }
+
+    pub fn fn_before_send(
+        mut self,
+        f: impl Fn(&mut CommandResponse) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_before_send.push(HookMut::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn async_before_send<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a mut CommandResponse) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_before_send.push(HookMut::Async(Box::new(f)));
+        self
+    }
+
+    pub fn fn_after_send(mut self, f: impl Fn(&SentResponse) + Send + Sync + 'static) -> Self {
+        self.on_after_send.push(Hook::Sync(Box::new(f)));
+        self
+    }
+
+    pub fn async_after_send<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a SentResponse) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.on_after_send.push(Hook::Async(Box::new(f)));
+        self
+    }
+
+    /// run the received hooks until one of them answers the request
+    async fn received(&self, cmd: &CommandRequest) -> Option<CommandResponse> {
+        for hook in &self.on_received {
+            if let Some(res) = hook.call(cmd).await {
+                return Some(res);
+            }
+        }
+        None
+    }
 }
 
+/// execute a storage command on the store. Nothing is checked, the authentication and the acl
+/// are enforced by `Service::execute` before it dispatches a command.
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::Hscan(param)) => param.execute(store),
+        Some(RequestData::Hrange(param)) => param.execute(store),
+        Some(RequestData::Hprefix(param)) => param.execute(store),
+        Some(RequestData::ListTables(param)) => param.execute(store),
+        Some(RequestData::DropTable(param)) => param.execute(store),
+        Some(RequestData::RenameTable(param)) => param.execute(store),
+        Some(RequestData::Hlen(param)) => param.execute(store),
+        Some(RequestData::Hmget(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
+        Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
+        Some(RequestData::Hmdel(param)) => param.execute(store),
+        Some(RequestData::Hexist(param)) => param.execute(store),
+        Some(RequestData::Hmexists(param)) => param.execute(store),
+        Some(RequestData::Hsetex(param)) => param.execute(store),
+        Some(RequestData::Expire(param)) => param.execute(store),
+        Some(RequestData::Ttl(param)) => param.execute(store),
+        Some(RequestData::Persist(param)) => param.execute(store),
+        Some(RequestData::Hincrby(param)) => param.execute(store),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute(store),
+        Some(RequestData::Hsetnx(param)) => param.execute(store),
+        Some(RequestData::Hcas(param)) => param.execute(store),
+        Some(RequestData::Txn(param)) => param.execute(store),
+        Some(RequestData::Subscribe(_) | RequestData::Unsubscribe(_) | RequestData::Publish(_)) => {
+            KvError::InvalidCommand("Pub/sub commands must be dispatched as a stream".into()).into()
+        }
+        Some(RequestData::Auth(_)) => {
+            KvError::InvalidCommand("Auth is handled by the connection".into()).into()
+        }
+        Some(RequestData::AclGrant(_) | RequestData::AclRevoke(_) | RequestData::AclList(_)) => {
+            KvError::InvalidCommand("Acl commands are handled by the service".into()).into()
+        }
         None => KvError::InvalidCommand("Request has no data".into()).into(),
-        _ => KvError::Internal("Not implemented".into()).into(),
     }
 }
 
+/// execute a command inside a transaction, an error aborts the transaction
+pub fn dispatch_txn(
+    cmd: &CommandRequest,
+    txn: &dyn Transaction,
+) -> Result<CommandResponse, KvError> {
+    match &cmd.request_data {
+        Some(RequestData::Hget(param)) => param.execute_txn(txn),
+        Some(RequestData::Hmget(param)) => param.execute_txn(txn),
+        Some(RequestData::Hset(param)) => param.execute_txn(txn),
+        Some(RequestData::Hmset(param)) => param.execute_txn(txn),
+        Some(RequestData::Hdel(param)) => param.execute_txn(txn),
+        Some(RequestData::Hmdel(param)) => param.execute_txn(txn),
+        Some(RequestData::Hexist(param)) => param.execute_txn(txn),
+        Some(RequestData::Hmexists(param)) => param.execute_txn(txn),
+        Some(RequestData::Hincrby(param)) => param.execute_txn(txn),
+        Some(RequestData::Hincrbyfloat(param)) => param.execute_txn(txn),
+        Some(RequestData::Hsetnx(param)) => param.execute_txn(txn),
+        Some(RequestData::Hcas(param)) => param.execute_txn(txn),
+        Some(_) => Err(KvError::InvalidCommand(
+            "Command cannot run in a transaction".into(),
+        )),
+        None => Err(KvError::InvalidCommand("Request has no data".into())),
+    }
+}
+
+/// execute a pub/sub command of the session, `None` for the server itself
+pub fn dispatch_stream(
+    cmd: CommandRequest,
+    topic: Arc<Broadcaster>,
+    session: Option<u64>,
+) -> StreamingResponse {
+    let res = match cmd.request_data {
+        Some(RequestData::Subscribe(param)) => return param.execute(topic, session),
+        Some(RequestData::Unsubscribe(param)) => return param.execute(topic, session),
+        Some(RequestData::Publish(param)) => return param.execute(topic, session),
+        Some(_) => {
+            KvError::InvalidCommand("Only pub/sub commands can be dispatched as a stream".into())
+        }
+        None => KvError::InvalidCommand("Request has no data".into()),
+    };
+    Box::pin(stream::once(async { Arc::new(res.into()) }))
+}
+
+#[cfg(test)]
+pub(crate) fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(res.pairs, pairs)
+}
+
+#[cfg(test)]
+pub(crate) fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
+}
+
 #[cfg(test)]
 mod tests {
-    use std::thread;
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+    };
+
+    use anyhow::Result;
+    use http::StatusCode;
+    use tokio::net::{TcpListener, TcpStream};
+    use tracing::info;
 
-    use crate::CommandRequest;
+    use crate::{CommandRequest, ProstClientStream, ProstServerStream};
 
     use super::*;
 
-    #[test]
-    fn service_should_work() {
+    #[tokio::test]
+    async fn service_should_work() {
         let service: Service = ServiceInner::new(MemTable::default()).into();
 
         let cloned = service.clone();
 
-        let handle = thread::spawn(move || {
-            let res = cloned.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
-            assert_res_ok(res, &[Value::default()], &[]);
+        let handle = tokio::spawn(async move {
+            let mut res = cloned
+                .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+                .await;
+            let data = res.next().await.unwrap();
+            assert_res_ok(data.as_ref().clone(), &[Value::default()], &[]);
         });
-        handle.join().unwrap();
+        handle.await.unwrap();
 
-        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        let mut res = service.execute(CommandRequest::new_hget("t1", "k1")).await;
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &["v1".into()], &[]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn blocked_store_should_not_block_runtime() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+
+        // a transaction holds the lock of the key for a while
+        let cloned = service.clone();
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let writer = thread::spawn(move || {
+            cloned.inner.store.get_ref().transaction(|txn| {
+                txn.set("t1", "k1", "v1".into())?;
+                let _ = locked_tx.send(());
+                // a blocked runtime could never release it, so give up eventually
+                Ok(release_rx.recv_timeout(Duration::from_secs(10)).is_ok())
+            })
+        });
+        locked_rx.recv().unwrap();
+
+        let get = tokio::spawn(async move {
+            let mut res = service.execute(CommandRequest::new_hget("t1", "k1")).await;
+            res.next().await.unwrap()
+        });
+        // the only runtime thread keeps running while the get waits for the lock
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        release_tx.send(()).unwrap();
+
+        let data = get.await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &["v1".into()], &[]);
+        assert!(writer.join().unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn sweeper_should_purge_expired_keys() -> Result<()> {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let handle = service.start_sweeper(Duration::from_millis(10));
+
+        let cmd = CommandRequest::new_hsetex("t1", "k1", "v1".into(), 20);
+        service.execute(cmd).await.next().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(service.inner.store.get_ref().purge_expired(), Ok(0));
+
+        drop(service);
+        tokio::time::timeout(Duration::from_secs(1), handle).await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ttl_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hsetex("t1", "k1", "v1".into(), 60_000))
+            .await?;
+
+        let res = client.execute(CommandRequest::new_ttl("t1", "k1")).await?;
+        let ttl: i64 = (&res.values[0]).try_into()?;
+        assert!(ttl > 0 && ttl <= 60_000);
+
+        let res = client
+            .execute(CommandRequest::new_persist("t1", "k1"))
+            .await?;
+        assert_res_ok(res, &[true.into()], &[]);
+
+        let res = client.execute(CommandRequest::new_ttl("t1", "k1")).await?;
+        assert_res_ok(res, &[(-1).into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hincrby_over_network_should_be_atomic() -> Result<()> {
+        let addr = start_server().await?;
+        let tasks = (0..8).map(|_| {
+            tokio::spawn(async move {
+                let mut client = connect(addr).await?;
+                for _ in 0..10 {
+                    client
+                        .execute(CommandRequest::new_hincrby("t1", "counter", 1))
+                        .await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+        });
+        for res in futures::future::join_all(tasks).await {
+            res??;
+        }
+
+        let mut client = connect(addr).await?;
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "counter"))
+            .await?;
+        assert_res_ok(res, &[80.into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn txn_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hmset("t1", vec![Kvpair::new("k2", "v2".into())]),
+        ]);
+        let res = client.execute(cmd).await?;
+        assert_eq!(res.responses.len(), 2);
+        assert_res_ok(res.responses[0].clone(), &[Value::default()], &[]);
+
+        let res = client.execute(CommandRequest::new_hgetall("t1")).await?;
+        let pairs = &[
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hget_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
         assert_res_ok(res, &["v1".into()], &[]);
+        Ok(())
     }
-}
 
-#[cfg(test)]
-use crate::{Kvpair, Value};
+    #[tokio::test]
+    async fn hgetall_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k2", "v2".into()))
+            .await?;
 
-#[cfg(test)]
-use tracing::info;
+        let res = client.execute(CommandRequest::new_hgetall("t1")).await?;
+        let pairs = &[
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
+    }
 
-#[cfg(test)]
-use http::StatusCode;
+    #[tokio::test]
+    async fn hmget_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
 
-#[cfg(test)]
-fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(res.pairs, pairs)
-}
+        let cmd = CommandRequest::new_hmget("t1", vec!["k1".into(), "k2".into()]);
+        let res = client.execute(cmd).await?;
+        let pairs = &[
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", Value::default()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
+    }
 
-#[cfg(test)]
-fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
-}
+    #[tokio::test]
+    async fn hset_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+
+        let res = client.execute(cmd.clone()).await?;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client.execute(cmd).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hmset_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", 11.into()))
+            .await?;
+
+        let cmd = CommandRequest::new_hmset(
+            "t1",
+            vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())],
+        );
+        let res = client.execute(cmd).await?;
+        let pairs = &[
+            Kvpair::new("k1", 11.into()),
+            Kvpair::new("k2", Value::default()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hdel_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+
+        let res = client.execute(CommandRequest::new_hdel("t1", "k1")).await?;
+        assert_res_ok(res, &["v1".into()], &[]);
 
-#[test]
-fn event_registration_should_work() {
-    fn b(cmd: &CommandRequest) {
-        info!("Got: {:?}", cmd);
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await?;
+        assert_res_error(res, 404, "Not found");
+        Ok(())
     }
-    fn c(res: &CommandResponse) {
-        info!("{:?}", res);
+
+    #[tokio::test]
+    async fn hmdel_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+
+        let cmd = CommandRequest::new_hmdel("t1", vec!["k1".into(), "k2".into()]);
+        let res = client.execute(cmd).await?;
+        let pairs = &[
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", Value::default()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
     }
-    fn d(res: &mut CommandResponse) {
-        res.status = StatusCode::CREATED.as_u16() as _
+
+    #[tokio::test]
+    async fn hexist_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+
+        let res = client
+            .execute(CommandRequest::new_hexist("t1", "k1"))
+            .await?;
+        assert_res_ok(res, &[], &[Kvpair::new("k1", true.into())]);
+        Ok(())
     }
-    fn e() {
-        info!("Data is sent")
+
+    #[tokio::test]
+    async fn hmexists_over_network_should_work() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
+        client
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await?;
+
+        let cmd = CommandRequest::new_hmexist("t1", vec!["k1".into(), "k2".into()]);
+        let res = client.execute(cmd).await?;
+        let pairs = &[
+            Kvpair::new("k1", true.into()),
+            Kvpair::new("k2", false.into()),
+        ];
+        assert_res_ok(res, &[], pairs);
+        Ok(())
     }
 
-    let service: Service = ServiceInner::new(MemTable::default())
-        .fn_received(|_: &CommandRequest| {})
-        .fn_received(b)
-        .fn_executed(c)
-        .fn_before_send(d)
-        .fn_after_send(e)
-        .into();
+    #[tokio::test]
+    async fn empty_request_over_network_should_return_400() -> Result<()> {
+        let mut client = connect(start_server().await?).await?;
 
-    let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
-    assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, vec![Value::default()]);
+        let res = client.execute(CommandRequest::default()).await?;
+        assert_res_error(res, 400, "Request has no data");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hooks_should_keep_state() {
+        let received = Arc::new(AtomicUsize::new(0));
+        let executed = Arc::new(AtomicUsize::new(0));
+        let service: Service = {
+            let (received, executed) = (Arc::clone(&received), Arc::clone(&executed));
+            ServiceInner::new(MemTable::default())
+                .fn_received(move |_| {
+                    received.fetch_add(1, Ordering::SeqCst);
+                })
+                .fn_executed(move |_| {
+                    executed.fetch_add(1, Ordering::SeqCst);
+                })
+                .into()
+        };
+
+        for cmd in [
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_hget("t1", "k1"),
+        ] {
+            service.execute(cmd).await.next().await.unwrap();
+        }
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        assert_eq!(executed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn intercept_should_short_circuit_execution() {
+        let received = Arc::new(AtomicUsize::new(0));
+        let service: Service = {
+            let received = Arc::clone(&received);
+            ServiceInner::new(MemTable::default())
+                .fn_intercept(|cmd| match &cmd.request_data {
+                    Some(RequestData::Hset(param)) if param.table == "readonly" => {
+                        Some(KvError::InvalidCommand("readonly is read only".into()).into())
+                    }
+                    _ => None,
+                })
+                .fn_received(move |_| {
+                    received.fetch_add(1, Ordering::SeqCst);
+                })
+                .fn_before_send(|res| res.message.push_str(" (checked)"))
+                .into()
+        };
+
+        let cmd = CommandRequest::new_hset("readonly", "k1", "v1".into());
+        let res = service.execute(cmd).await.next().await.unwrap();
+        assert_res_error(res.as_ref().clone(), 400, "read only` (checked)");
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+        let tables = service.inner.store.get_ref().list_tables().unwrap();
+        assert!(tables.is_empty());
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        service.execute(cmd).await.next().await.unwrap();
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn async_hooks_should_work() {
+        let executed = Arc::new(AtomicUsize::new(0));
+        let service: Service = {
+            let executed = Arc::clone(&executed);
+            ServiceInner::new(MemTable::default())
+                .async_received(|_| tokio::task::yield_now().boxed())
+                .async_intercept(|cmd| {
+                    async move {
+                        tokio::task::yield_now().await;
+                        cmd.request_data.is_none().then(|| CommandResponse {
+                            status: 204,
+                            ..Default::default()
+                        })
+                    }
+                    .boxed()
+                })
+                .async_executed(move |_| {
+                    let executed = Arc::clone(&executed);
+                    async move {
+                        executed.fetch_add(1, Ordering::SeqCst);
+                    }
+                    .boxed()
+                })
+                .async_before_send(|res| {
+                    async move {
+                        res.message = format!("status {}", res.status);
+                    }
+                    .boxed()
+                })
+                .into()
+        };
+
+        let res = service
+            .execute(CommandRequest::default())
+            .await
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(res.status, 204);
+        assert_eq!(res.message, "status 204");
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let res = service.execute(cmd).await.next().await.unwrap();
+        assert_eq!(res.message, "status 200");
+        assert_eq!(executed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn service_should_require_connections_to_authenticate() {
+        let executed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&executed);
+        let service: Service = ServiceInner::new(MemTable::default())
+            .with_authenticator(StaticTokens::new().with_user("alice", "secret"))
+            .fn_received(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .into();
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let anonymous = service.for_connection(None);
+        let res = execute(&anonymous, cmd.clone()).await;
+        assert_res_error(res, 401, "authenticate first");
+        assert_eq!(executed.load(Ordering::SeqCst), 0);
+
+        let alice = service.for_connection(Some("alice"));
+        assert_res_ok(execute(&alice, cmd).await, &[Value::default()], &[]);
+        // the server itself does not authenticate
+        let cmd = CommandRequest::new_hset("t1", "k2", "v2".into());
+        assert_res_ok(execute(&service, cmd).await, &[Value::default()], &[]);
+    }
+
+    #[tokio::test]
+    async fn acl_should_restrict_connections() {
+        let acl = Acl::new()
+            .with_rule("reader", "sessions", Permission::Read)
+            .with_rule("writer", "config*", Permission::Write);
+        let service: Service = ServiceInner::new(MemTable::default()).with_acl(acl).into();
+
+        // the server itself is not restricted
+        let cmd = CommandRequest::new_hset("sessions", "k1", "v1".into());
+        assert_res_ok(execute(&service, cmd).await, &[Value::default()], &[]);
+        let cmd = CommandRequest::new_hset("other", "k1", "v1".into());
+        assert_res_ok(execute(&service, cmd).await, &[Value::default()], &[]);
+
+        let reader = service.for_connection(Some("reader"));
+        let res = execute(&reader, CommandRequest::new_hget("sessions", "k1")).await;
+        assert_res_ok(res, &["v1".into()], &[]);
+        let cmd = CommandRequest::new_hset("sessions", "k1", "v2".into());
+        let res = execute(&reader, cmd).await;
+        assert_res_error(res, 403, "reader has no write permission on table sessions");
+
+        let writer = service.for_connection(Some("writer"));
+        let cmd = CommandRequest::new_hset("config", "k1", "v1".into());
+        assert_res_ok(execute(&writer, cmd).await, &[Value::default()], &[]);
+        let res = execute(&writer, CommandRequest::new_drop_table("config")).await;
+        assert_res_error(res, 403, "writer has no admin permission on table config");
+        // a transaction is denied as a whole if any of its commands is
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("config", "k2", "v2".into()),
+            CommandRequest::new_hset("sessions", "k2", "v2".into()),
+        ]);
+        let res = execute(&writer, cmd).await;
+        assert_res_error(res, 403, "on table sessions");
+        let res = execute(&writer, CommandRequest::new_hget("config", "k2")).await;
+        assert_res_error(res, 404, "Not found");
+
+        let anonymous = service.for_connection(None);
+        let res = execute(&anonymous, CommandRequest::new_hget("sessions", "k1")).await;
+        assert_res_error(res, 403, "anonymous");
+        let res = execute(&anonymous, CommandRequest::new_list_tables()).await;
+        assert_res_ok(res, &[], &[]);
+
+        let res = execute(&reader, CommandRequest::new_list_tables()).await;
+        assert_res_ok(res, &["sessions".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn acl_commands_should_need_admin() {
+        let acl = Acl::new()
+            .with_rule("alice", "*", Permission::Admin)
+            .with_admin("root");
+        let service: Service = ServiceInner::new(MemTable::default()).with_acl(acl).into();
+        let root = service.for_connection(Some("root"));
+        let alice = service.for_connection(Some("alice"));
+
+        // admin on every table, the one named `*` included, does not manage the acl
+        let res = execute(&alice, CommandRequest::new_acl_list("alice")).await;
+        assert_res_error(res, 403, "alice may not manage the acl");
+        let res = execute(&root, CommandRequest::new_acl_revoke("alice", "*")).await;
+        assert_res_ok(res, &[true.into()], &[]);
+        let cmd = CommandRequest::new_acl_grant("alice", "", Permission::Read);
+        assert_res_error(execute(&root, cmd).await, 400, "Acl pattern is empty");
+
+        let cmd = CommandRequest::new_acl_grant("alice", "t*", Permission::Write);
+        assert_res_ok(execute(&root, cmd).await, &[], &[]);
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        assert_res_ok(execute(&alice, cmd).await, &[Value::default()], &[]);
+        let res = execute(&root, CommandRequest::new_acl_list("alice")).await;
+        assert_res_ok(res, &[], &[Kvpair::new("t*", "write".into())]);
+
+        let res = execute(&root, CommandRequest::new_acl_revoke("alice", "t*")).await;
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = execute(&alice, CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_error(res, 403, "alice has no read permission on table t1");
+
+        let mut cmd = CommandRequest::new_acl_grant("alice", "t*", Permission::Read);
+        if let Some(RequestData::AclGrant(grant)) = &mut cmd.request_data {
+            grant.permission = "root".into();
+        }
+        assert_res_error(execute(&root, cmd).await, 400, "unknown permission");
+
+        // without an acl there is nothing to manage
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let res = execute(&service, CommandRequest::new_acl_list("alice")).await;
+        assert_res_error(res, 400, "Service has no acl");
+    }
+
+    async fn execute(service: &Service, cmd: CommandRequest) -> CommandResponse {
+        let res = service.execute(cmd).await.next().await.unwrap();
+        res.as_ref().clone()
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let stream = ProstServerStream::new(stream, service.clone());
+                tokio::spawn(stream.process());
+            }
+        });
+
+        Ok(addr)
+    }
+
+    async fn connect(addr: SocketAddr) -> Result<ProstClientStream<TcpStream>> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(ProstClientStream::new(stream))
+    }
+
+    #[tokio::test]
+    async fn event_registration_should_work() {
+        fn b(cmd: &CommandRequest) {
+            info!("Got: {:?}", cmd);
+        }
+        fn c(res: &CommandResponse) {
+            info!("{:?}", res);
+        }
+        fn d(res: &mut CommandResponse) {
+            res.status = StatusCode::CREATED.as_u16() as _
+        }
+        fn e(sent: &SentResponse) {
+            info!("Data is sent: {:?}", sent)
+        }
+
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received(|_: &CommandRequest| {})
+            .fn_received(b)
+            .fn_executed(c)
+            .fn_before_send(d)
+            .fn_after_send(e)
+            .into();
+
+        let mut res = service
+            .execute(CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await;
+        let res = res.next().await.unwrap();
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(res.message, "");
+        assert_eq!(res.values, vec![Value::default()]);
+    }
 }
@@ -0,0 +1,209 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use dashmap::{DashMap, DashSet};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, info, warn};
+
+use crate::{CommandResponse, KvError};
+
+/// how many messages can be queued for a subscriber, one falling further behind is dropped
+const BROADCAST_CAPACITY: usize = 128;
+
+/// the next subscription id
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn get_next_subscription_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// tracks the subscribers of every topic and delivers published data to them
+#[derive(Default)]
+pub struct Broadcaster {
+    /// topic name -> subscription ids
+    topics: DashMap<String, DashSet<u32>>,
+    /// subscription id -> the subscription
+    subscriptions: DashMap<u32, Subscription>,
+}
+
+struct Subscription {
+    /// the session which subscribed, `None` for the server itself
+    session: Option<u64>,
+    tx: mpsc::Sender<Arc<CommandResponse>>,
+}
+
+impl Broadcaster {
+    /// subscribe the session to the topic, returns the subscription id and the channel
+    /// the published data arrives in
+    pub fn subscribe(
+        &self,
+        name: String,
+        session: Option<u64>,
+    ) -> (u32, mpsc::Receiver<Arc<CommandResponse>>) {
+        let id = get_next_subscription_id();
+        let (tx, rx) = mpsc::channel(BROADCAST_CAPACITY);
+
+        self.subscriptions.insert(id, Subscription { session, tx });
+        self.topics.entry(name.clone()).or_default().insert(id);
+        debug!("Subscription {} is added to topic {}", id, name);
+
+        (id, rx)
+    }
+
+    /// remove the subscription, which closes its channel. A session may only remove its own
+    /// subscriptions, the server (`None`) any of them.
+    pub fn unsubscribe(&self, name: String, id: u32, session: Option<u64>) -> Result<u32, KvError> {
+        let owned = match (self.subscriptions.get(&id), session) {
+            (Some(sub), Some(session)) => sub.session == Some(session),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let removed = owned
+            && self
+                .topics
+                .get(&name)
+                .and_then(|topic| topic.remove(&id))
+                .is_some();
+        if !removed {
+            return Err(KvError::NotFound(name, id.to_string()));
+        }
+
+        self.topics.remove_if(&name, |_, ids| ids.is_empty());
+        self.subscriptions.remove(&id);
+        debug!("Subscription {} is removed from topic {}", id, name);
+        Ok(id)
+    }
+
+    /// send the data to every subscriber of the topic without waiting for any of them.
+    /// A subscriber whose channel is closed or still full of earlier data is removed,
+    /// which ends its subscription once it read what is queued.
+    pub fn publish(&self, name: String, value: Arc<CommandResponse>) {
+        let ids: Vec<u32> = match self.topics.get(&name) {
+            Some(topic) => topic.iter().map(|id| *id).collect(),
+            None => return,
+        };
+        info!("Publish to topic {} with {} subscribers", name, ids.len());
+
+        for id in ids {
+            let res = match self.subscriptions.get(&id) {
+                Some(sub) => sub.tx.try_send(value.clone()),
+                None => continue,
+            };
+            match res {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("Subscription {} is lagging behind, dropping it", id);
+                    self.remove_subscription(&name, id);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    debug!("Subscription {} is closed, dropping it", id);
+                    self.remove_subscription(&name, id);
+                }
+            }
+        }
+    }
+
+    fn remove_subscription(&self, name: &str, id: u32) {
+        if let Some(topic) = self.topics.get(name) {
+            topic.remove(&id);
+        }
+        self.topics.remove_if(name, |_, ids| ids.is_empty());
+        self.subscriptions.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{service::assert_res_ok, Value};
+
+    #[tokio::test]
+    async fn pub_sub_should_work() {
+        let b = Broadcaster::default();
+        let lobby = "lobby".to_string();
+
+        let (id1, mut stream1) = b.subscribe(lobby.clone(), None);
+        let (id2, mut stream2) = b.subscribe(lobby.clone(), None);
+        assert_ne!(id1, id2);
+
+        let v: Value = "hello".into();
+        b.publish(lobby.clone(), Arc::new(v.clone().into()));
+
+        let res1 = stream1.recv().await.unwrap();
+        let res2 = stream2.recv().await.unwrap();
+        assert_eq!(res1, res2);
+        assert_res_ok(res1.as_ref().clone(), std::slice::from_ref(&v), &[]);
+
+        assert_eq!(b.unsubscribe(lobby.clone(), id1, None), Ok(id1));
+        b.publish(lobby.clone(), Arc::new(v.clone().into()));
+
+        // the channel of an unsubscribed stream is closed
+        assert!(stream1.recv().await.is_none());
+        let res2 = stream2.recv().await.unwrap();
+        assert_res_ok(res2.as_ref().clone(), &[v], &[]);
+    }
+
+    #[test]
+    fn unsubscribe_unknown_subscription_should_fail() {
+        let b = Broadcaster::default();
+        let (id, _stream) = b.subscribe("lobby".into(), None);
+
+        assert!(b.unsubscribe("other".into(), id, None).is_err());
+        assert!(b.unsubscribe("lobby".into(), id + 1000, None).is_err());
+        assert_eq!(b.unsubscribe("lobby".into(), id, None), Ok(id));
+        assert!(b.unsubscribe("lobby".into(), id, None).is_err());
+    }
+
+    #[test]
+    fn unsubscribe_should_only_remove_subscriptions_of_the_session() {
+        let b = Broadcaster::default();
+        let (id, _stream) = b.subscribe("lobby".into(), Some(1));
+
+        assert!(matches!(
+            b.unsubscribe("lobby".into(), id, Some(2)),
+            Err(KvError::NotFound(..))
+        ));
+        assert_eq!(b.unsubscribe("lobby".into(), id, Some(1)), Ok(id));
+
+        // the server may remove any subscription
+        let (id, _stream) = b.subscribe("lobby".into(), Some(1));
+        assert_eq!(b.unsubscribe("lobby".into(), id, None), Ok(id));
+    }
+
+    #[test]
+    fn publish_should_drop_closed_subscribers() {
+        let b = Broadcaster::default();
+        let (_, stream) = b.subscribe("lobby".into(), None);
+        drop(stream);
+
+        let v: Value = "hello".into();
+        b.publish("lobby".into(), Arc::new(v.into()));
+        assert!(b.topics.is_empty());
+        assert!(b.subscriptions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn publish_should_drop_lagging_subscribers() {
+        let b = Broadcaster::default();
+        let (_, mut slow) = b.subscribe("lobby".into(), None);
+        let (_, mut fast) = b.subscribe("lobby".into(), None);
+
+        for i in 0..=BROADCAST_CAPACITY {
+            let v: Value = (i as i64).into();
+            b.publish("lobby".into(), Arc::new(v.into()));
+            fast.recv().await.unwrap();
+        }
+        // the slow subscriber gets what was queued before it fell behind, then it ends
+        for _ in 0..BROADCAST_CAPACITY {
+            slow.recv().await.unwrap();
+        }
+        assert!(slow.recv().await.is_none());
+
+        let v: Value = "hello".into();
+        b.publish("lobby".into(), Arc::new(v.clone().into()));
+        let res = fast.recv().await.unwrap();
+        assert_res_ok(res.as_ref().clone(), &[v], &[]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{pin::Pin, sync::Arc};
+
+use futures::{stream, Stream, StreamExt};
+
+use crate::{Broadcaster, CommandResponse, Publish, Subscribe, Unsubscribe, Value};
+
+/// a stream of responses, a subscription yields one response for every publish
+pub type StreamingResponse = Pin<Box<dyn Stream<Item = Arc<CommandResponse>> + Send>>;
+
+/// a pub/sub command of the session, `None` for the server itself
+pub trait TopicService {
+    fn execute(self, topic: Arc<Broadcaster>, session: Option<u64>) -> StreamingResponse;
+}
+
+impl TopicService for Subscribe {
+    /// the first response carries the subscription id
+    fn execute(self, topic: Arc<Broadcaster>, session: Option<u64>) -> StreamingResponse {
+        let (id, rx) = topic.subscribe(self.topic, session);
+        let id: Value = (id as i64).into();
+        let first = stream::once(async move { Arc::new(id.into()) });
+        Box::pin(first.chain(stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|res| (res, rx))
+        })))
+    }
+}
+
+impl TopicService for Unsubscribe {
+    fn execute(self, topic: Arc<Broadcaster>, session: Option<u64>) -> StreamingResponse {
+        let res = match topic.unsubscribe(self.topic, self.id, session) {
+            Ok(_) => CommandResponse::ok(),
+            Err(e) => e.into(),
+        };
+        Box::pin(stream::once(async { Arc::new(res) }))
+    }
+}
+
+impl TopicService for Publish {
+    fn execute(self, topic: Arc<Broadcaster>, _session: Option<u64>) -> StreamingResponse {
+        topic.publish(self.topic, Arc::new(self.data.into()));
+        Box::pin(stream::once(async { Arc::new(CommandResponse::ok()) }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::memory::MemTable;
+    use crate::{
+        service::{assert_res_error, assert_res_ok},
+        CommandRequest, Service, ServiceInner, Value,
+    };
+
+    #[tokio::test]
+    async fn dispatch_publish_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut res = service
+            .execute(CommandRequest::new_publish("lobby", vec!["hello".into()]))
+            .await;
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[], &[]);
+        assert!(res.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatch_subscribe_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut res = service
+            .execute(CommandRequest::new_subscribe("lobby"))
+            .await;
+        let id = get_id(&mut res).await;
+        assert!(id > 0);
+
+        let v: Value = 42.into();
+        let mut publish = service
+            .execute(CommandRequest::new_publish("lobby", vec![v.clone()]))
+            .await;
+        publish.next().await.unwrap();
+
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[v], &[]);
+    }
+
+    #[tokio::test]
+    async fn dispatch_unsubscribe_should_work() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut res = service
+            .execute(CommandRequest::new_subscribe("lobby"))
+            .await;
+        let id = get_id(&mut res).await;
+
+        let mut unsub = service
+            .execute(CommandRequest::new_unsubscribe("lobby", id))
+            .await;
+        let data = unsub.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[], &[]);
+
+        // the subscription stream ends once it is unsubscribed
+        assert!(res.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatch_unsubscribe_should_only_cancel_subscriptions_of_the_session() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let subscriber = service.for_session(1, None);
+        let mut res = subscriber
+            .execute(CommandRequest::new_subscribe("lobby"))
+            .await;
+        let id = get_id(&mut res).await;
+
+        let cmd = CommandRequest::new_unsubscribe("lobby", id);
+        let mut unsub = service.for_connection(None).execute(cmd.clone()).await;
+        let data = unsub.next().await.unwrap();
+        assert_res_error(data.as_ref().clone(), 404, "Not found");
+
+        let mut unsub = service.for_session(1, None).execute(cmd).await;
+        let data = unsub.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[], &[]);
+        assert!(res.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dispatch_unsubscribe_random_id_should_error() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let mut res = service
+            .execute(CommandRequest::new_unsubscribe("lobby", 9527))
+            .await;
+        let data = res.next().await.unwrap();
+        assert_res_error(data.as_ref().clone(), 404, "Not found");
+    }
+
+    async fn get_id(res: &mut StreamingResponse) -> u32 {
+        let id: i64 = (&res.next().await.unwrap().values[0]).try_into().unwrap();
+        id as u32
+    }
+}
@@ -0,0 +1,55 @@
+use std::task::{Context, Poll};
+
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::{CommandRequest, KvError, Service, Storage, StreamingResponse};
+
+/// lets tower layers such as timeouts and concurrency limits wrap the service,
+/// it is always ready and every call executes the command.
+///
+/// A command runs on the blocking pool of the store, a timeout only stops waiting for it.
+/// The command still completes, so a write which timed out may well be applied. Retrying it
+/// applies it again, a retry policy should only retry commands which read.
+impl<Store: Storage> tower::Service<CommandRequest> for Service<Store> {
+    type Response = StreamingResponse;
+    type Error = KvError;
+    type Future = BoxFuture<'static, Result<StreamingResponse, KvError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), KvError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, cmd: CommandRequest) -> Self::Future {
+        let service = self.clone();
+        async move { Ok(service.execute(cmd).await) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use tower::{Service as _, ServiceBuilder, ServiceExt};
+
+    use crate::{
+        memory::MemTable,
+        service::{assert_res_ok, ServiceInner},
+        CommandRequest, Service, Value,
+    };
+
+    #[tokio::test]
+    async fn service_should_work_as_tower_service() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let mut res = service.clone().oneshot(cmd).await.unwrap();
+        let data = res.next().await.unwrap();
+        assert_res_ok(data.as_ref().clone(), &[Value::default()], &[]);
+
+        let mut stack = ServiceBuilder::new().concurrency_limit(1).service(service);
+        for _ in 0..2 {
+            let cmd = CommandRequest::new_hget("t1", "k1");
+            let mut res = stack.ready().await.unwrap().call(cmd).await.unwrap();
+            let data = res.next().await.unwrap();
+            assert_res_ok(data.as_ref().clone(), &["v1".into()], &[]);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    storage::{incr_float_value, incr_value},
+    *,
+};
+
+/// a command which can run inside a transaction, an error aborts the whole transaction
+pub trait TxnService {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError>;
+}
+
+impl TxnService for Hget {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        match txn.get(&self.table, &self.key)? {
+            Some(v) => Ok(v.into()),
+            None => Err(KvError::NotFound(self.table.clone(), self.key.clone())),
+        }
+    }
+}
+
+impl TxnService for Hmget {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            let v = txn.get(&self.table, key)?;
+            pairs.push(Kvpair::new(key, v.unwrap_or_default()));
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TxnService for Hset {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        match &self.pair {
+            Some(v) => {
+                let value = v.value.clone().unwrap_or_default();
+                let old = txn.set(&self.table, &v.key, value)?;
+                Ok(old.unwrap_or_default().into())
+            }
+            None => Ok(Value::default().into()),
+        }
+    }
+}
+
+impl TxnService for Hmset {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::with_capacity(self.pairs.len());
+        for pair in &self.pairs {
+            let value = pair.value.clone().unwrap_or_default();
+            let old = txn.set(&self.table, &pair.key, value)?;
+            pairs.push(Kvpair::new(&pair.key, old.unwrap_or_default()));
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TxnService for Hdel {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let old = txn.del(&self.table, &self.key)?;
+        Ok(old.unwrap_or_default().into())
+    }
+}
+
+impl TxnService for Hmdel {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            let old = txn.del(&self.table, key)?;
+            pairs.push(Kvpair::new(key, old.unwrap_or_default()));
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TxnService for Hexist {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let exists = txn.contains(&self.table, &self.key)?;
+        Ok(Kvpair::new(&self.key, exists.into()).into())
+    }
+}
+
+impl TxnService for Hmexists {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let mut pairs = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            let exists = txn.contains(&self.table, key)?;
+            pairs.push(Kvpair::new(key, exists.into()));
+        }
+        Ok(pairs.into())
+    }
+}
+
+impl TxnService for Hincrby {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let old = txn.get(&self.table, &self.key)?;
+        let new = incr_value(old.as_ref(), self.delta)?;
+        txn.set(&self.table, &self.key, new.into())?;
+        Ok(Value::from(new).into())
+    }
+}
+
+impl TxnService for Hincrbyfloat {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let old = txn.get(&self.table, &self.key)?;
+        let new = incr_float_value(old.as_ref(), self.delta)?;
+        txn.set(&self.table, &self.key, new.into())?;
+        Ok(Value::from(new).into())
+    }
+}
+
+impl TxnService for Hsetnx {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let pair = match &self.pair {
+            Some(pair) => pair,
+            None => return Err(KvError::InvalidCommand("Hsetnx has no kv pair".into())),
+        };
+        match txn.get(&self.table, &pair.key)? {
+            Some(current) => Ok(vec![false.into(), current].into()),
+            None => {
+                let value = pair.value.clone().unwrap_or_default();
+                txn.set(&self.table, &pair.key, value)?;
+                Ok(Value::from(true).into())
+            }
+        }
+    }
+}
+
+impl TxnService for Hcas {
+    fn execute_txn(&self, txn: &dyn Transaction) -> Result<CommandResponse, KvError> {
+        let current = txn.get(&self.table, &self.key)?;
+        if current != self.expected {
+            return Ok(vec![false.into(), current.unwrap_or_default()].into());
+        }
+
+        match &self.new {
+            Some(v) => txn.set(&self.table, &self.key, v.clone())?,
+            None => txn.del(&self.table, &self.key)?,
+        };
+        Ok(Value::from(true).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        memory::MemTable,
+        service::{assert_res_error, assert_res_ok, dispatch},
+    };
+
+    #[test]
+    fn txn_should_apply_all_commands() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "balance", 10.into()), &store);
+
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hcas("t1", "balance", Some(10.into()), Some(7.into())),
+            CommandRequest::new_hincrby("t1", "spent", 3),
+            CommandRequest::new_hget("t1", "balance"),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.responses.len(), 3);
+        assert_res_ok(res.responses[0].clone(), &[true.into()], &[]);
+        assert_res_ok(res.responses[1].clone(), &[3.into()], &[]);
+        assert_res_ok(res.responses[2].clone(), &[7.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_hget("t1", "spent"), &store);
+        assert_res_ok(res, &[3.into()], &[]);
+    }
+
+    #[test]
+    fn txn_should_apply_nothing_on_error() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", "v1".into()), &store);
+
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k2", "v2".into()),
+            CommandRequest::new_hdel("t1", "k1"),
+            CommandRequest::new_hincrby("t1", "k2", 1),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 500, "Integer");
+
+        let res = dispatch(CommandRequest::new_hget("t1", "k1"), &store);
+        assert_res_ok(res, &["v1".into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("t1", "k2"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn txn_should_reject_unsupported_commands() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_txn(vec![
+            CommandRequest::new_hset("t1", "k1", "v1".into()),
+            CommandRequest::new_txn(vec![]),
+        ]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "transaction");
+
+        let res = dispatch(CommandRequest::new_hexist("t1", "k1"), &store);
+        assert_res_ok(res, &[], &[Kvpair::new("k1", false.into())]);
+    }
+}
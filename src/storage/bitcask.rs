@@ -0,0 +1,1571 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, TryLockError, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_skiplist::SkipMap;
+use dashmap::DashMap;
+use tracing::{info, warn};
+
+use crate::{
+    storage::{
+        expire_at, incr_float_value, incr_value,
+        lock_set::{run_transaction, LockSet, LockingStore, TxnWrites},
+        now_millis,
+        record::{crc32, read_log, Cursor, RECORD_HEADER_LEN},
+    },
+    CompareAndSwapError, KvError, Kvpair, Storage, Transaction, Value,
+};
+
+/// the active data file is closed and a new one started once it grows beyond this size
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// kind, seq, expire_at and the lengths of table, key and value in front of every entry
+const ENTRY_HEADER_LEN: usize = 1 + 8 + 8 + 4 + 4 + 4;
+
+/// lists the data files replaced by a merge, it exists from the moment the output of the
+/// merge is complete until the replaced files are deleted
+const MERGE_MANIFEST: &str = "MERGE";
+
+/// locked by the store which has the directory open
+const LOCK_FILE: &str = "LOCK";
+
+/// keys of a table -> where their latest value is in the log
+type Keydir = SkipMap<String, Location>;
+
+/// a storage keeping every write in append only data files and the location of the
+/// latest value of every key in memory, so a read is a single seek.
+///
+/// A data file is a sequence of records, every record is
+/// `crc32 | payload length | payload` with the payload holding one or more entries of
+/// `kind | seq | expire_at | table length | key length | value length | table | key | value`.
+/// Writes spanning several keys, transactions and renames, are a single record, so after a
+/// crash either all of them are in the log or none is. A torn record at the tail of the file
+/// which was active is truncated on open, any other corrupt record fails it. A merge copies the
+/// live values into new files, each of them with a hint file listing its keys, so opening the
+/// store does not have to read the values. Only one store can have the directory open.
+pub struct Bitcask {
+    inner: Arc<Inner>,
+    /// the locked LOCK file, so no other store opens the directory meanwhile
+    _lock: File,
+}
+
+struct Inner {
+    dir: PathBuf,
+    max_file_size: u64,
+    /// table -> keydir of the table
+    tables: DashMap<String, Arc<Keydir>>,
+    /// the file new records are appended to, the lock serialises all writes to the log
+    /// and to the tables
+    active: Mutex<ActiveFile>,
+    /// read handles of every data file, a merge removes the files it replaced while
+    /// holding the write lock, so a scan holding the read lock sees all of its values
+    files: RwLock<HashMap<u64, Arc<Mutex<File>>>>,
+    next_file_id: AtomicU64,
+    next_seq: AtomicU64,
+    /// single key operations lock their key, transactions lock every key they touch
+    locks: LockSet,
+    /// size of all data files and how much of it holds the latest value of a key,
+    /// the rest is reclaimed by a merge
+    total_bytes: AtomicU64,
+    live_bytes: AtomicU64,
+    /// held while merging, so only one merge runs at a time
+    merge_lock: Mutex<()>,
+    /// the store itself, to hand to a background merge
+    me: Weak<Inner>,
+    /// set once the store is dropped, no merge starts afterwards
+    closed: AtomicBool,
+}
+
+struct ActiveFile {
+    id: u64,
+    file: File,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Put = 0,
+    Delete = 1,
+    DropTable = 2,
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = ();
+
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Kind::Put),
+            1 => Ok(Kind::Delete),
+            2 => Ok(Kind::DropTable),
+            _ => Err(()),
+        }
+    }
+}
+
+/// a change to write to the log
+struct Entry<'a> {
+    kind: Kind,
+    table: &'a str,
+    key: &'a str,
+    value: &'a [u8],
+    expire_at: Option<u64>,
+}
+
+impl<'a> Entry<'a> {
+    fn put(table: &'a str, key: &'a str, value: &'a [u8], expire_at: Option<u64>) -> Self {
+        Self {
+            kind: Kind::Put,
+            table,
+            key,
+            value,
+            expire_at,
+        }
+    }
+
+    fn delete(table: &'a str, key: &'a str) -> Self {
+        Self {
+            kind: Kind::Delete,
+            table,
+            key,
+            value: &[],
+            expire_at: None,
+        }
+    }
+
+    fn drop_table(table: &'a str) -> Self {
+        Self {
+            kind: Kind::DropTable,
+            table,
+            key: "",
+            value: &[],
+            expire_at: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        ENTRY_HEADER_LEN + self.table.len() + self.key.len() + self.value.len()
+    }
+}
+
+/// where the value of an entry is in the log
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Location {
+    file_id: u64,
+    /// offset of the value in the file
+    pos: u64,
+    len: u32,
+    seq: u64,
+    expire_at: Option<u64>,
+    /// size of the whole entry, it becomes garbage once the key is written again
+    entry_len: u32,
+}
+
+impl Location {
+    fn is_live(&self, now: u64) -> bool {
+        !matches!(self.expire_at, Some(t) if t <= now)
+    }
+}
+
+/// an entry read back from a data or hint file
+struct LoadedEntry {
+    kind: Kind,
+    table: String,
+    key: String,
+    location: Location,
+}
+
+impl Bitcask {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::try_new(path).unwrap()
+    }
+
+    pub fn with_max_file_size(path: impl AsRef<Path>, max_file_size: u64) -> Self {
+        Self::open(path, max_file_size).unwrap()
+    }
+
+    /// open the store with the default maximum size of a data file
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        Self::open(path, DEFAULT_MAX_FILE_SIZE)
+    }
+
+    /// open the store, starting a new data file whenever the active one grows beyond
+    /// max_file_size. It fails if the directory cannot be read, holds a corrupt record
+    /// before the tail of the active file or is opened by another store.
+    pub fn open(path: impl AsRef<Path>, max_file_size: u64) -> Result<Self, KvError> {
+        let dir = path.as_ref();
+        fs::create_dir_all(dir)?;
+        let lock = lock_dir(dir)?;
+        Ok(Self {
+            inner: Inner::open(dir, max_file_size)?,
+            _lock: lock,
+        })
+    }
+
+    /// copy the live values of all data files but the active one into new files and
+    /// delete the old ones. Runs in the background by itself once more than half of
+    /// the log is garbage.
+    pub fn merge(&self) -> Result<(), KvError> {
+        self.inner.merge()
+    }
+}
+
+impl Drop for Bitcask {
+    /// a background merge may still hold the store, the directory is unlocked once it is done
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        let _merge = self
+            .inner
+            .merge_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+impl fmt::Debug for Bitcask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitcask")
+            .field("dir", &self.inner.dir)
+            .field("tables", &self.inner.tables.len())
+            .finish()
+    }
+}
+
+impl Inner {
+    /// load the store from dir, the caller holds the lock of the directory
+    fn open(dir: &Path, max_file_size: u64) -> Result<Arc<Self>, KvError> {
+        finish_merge(dir)?;
+
+        // the latest put or delete of every key and the latest drop of every table
+        let mut latest: HashMap<(String, String), (Kind, Location)> = HashMap::new();
+        let mut drops: HashMap<String, u64> = HashMap::new();
+        let mut files = HashMap::new();
+        let mut total_bytes = 0;
+        let mut max_seq = 0;
+        let ids = data_file_ids(dir)?;
+        // merged files get their name once complete, so only the file which was active when
+        // the store stopped, the newest one without a hint, can end in a torn write
+        let active = ids
+            .iter()
+            .rev()
+            .find(|&&id| !hint_path(dir, id).exists())
+            .copied();
+        for &id in &ids {
+            let entries = match load_hint(dir, id)? {
+                Some(entries) => entries,
+                None => load_data_file(dir, id, Some(id) == active)?,
+            };
+            for e in entries {
+                let seq = e.location.seq;
+                max_seq = max_seq.max(seq);
+                if e.kind == Kind::DropTable {
+                    let dropped = drops.entry(e.table).or_default();
+                    *dropped = (*dropped).max(seq);
+                    continue;
+                }
+                match latest.get(&(e.table.clone(), e.key.clone())) {
+                    Some((_, loc)) if loc.seq >= seq => {}
+                    _ => {
+                        latest.insert((e.table, e.key), (e.kind, e.location));
+                    }
+                }
+            }
+            // every open starts a new active file, the empty ones left behind are removed
+            let size = fs::metadata(data_path(dir, id))?.len();
+            if size == 0 {
+                remove_data_files(dir, &[id])?;
+                continue;
+            }
+            total_bytes += size;
+            files.insert(id, Arc::new(Mutex::new(File::open(data_path(dir, id))?)));
+        }
+
+        let tables: DashMap<String, Arc<Keydir>> = DashMap::new();
+        let mut live_bytes = 0;
+        for ((table, key), (kind, loc)) in latest {
+            let dropped = matches!(drops.get(&table), Some(&seq) if seq > loc.seq);
+            if kind == Kind::Put && !dropped {
+                live_bytes += loc.entry_len as u64;
+                tables.entry(table).or_default().insert(key, loc);
+            }
+        }
+
+        let id = ids.last().map_or(0, |id| id + 1);
+        let active = ActiveFile::create(dir, id)?;
+        files.insert(id, Arc::new(Mutex::new(File::open(data_path(dir, id))?)));
+
+        Ok(Arc::new_cyclic(|me| Self {
+            dir: dir.to_path_buf(),
+            max_file_size,
+            tables,
+            active: Mutex::new(active),
+            files: RwLock::new(files),
+            next_file_id: AtomicU64::new(id + 1),
+            next_seq: AtomicU64::new(max_seq + 1),
+            locks: LockSet::default(),
+            total_bytes: AtomicU64::new(total_bytes),
+            live_bytes: AtomicU64::new(live_bytes),
+            merge_lock: Mutex::new(()),
+            me: me.clone(),
+            closed: AtomicBool::new(false),
+        }))
+    }
+
+    fn active(&self) -> MutexGuard<'_, ActiveFile> {
+        self.active.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// the keydir of the table if it exists, reads never create a table
+    fn get_table(&self, name: &str) -> Option<Arc<Keydir>> {
+        self.tables.get(name).map(|t| Arc::clone(&t))
+    }
+
+    /// where the live value of the key is
+    fn location(&self, table: &str, key: &str) -> Option<Location> {
+        let loc = *self.get_table(table)?.get(key)?.value();
+        loc.is_live(now_millis()).then_some(loc)
+    }
+
+    /// the live value of the key, the caller holds the lock of the key
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        // a merge may move the value, its old file stays readable while the files are locked
+        let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+        match self.location(table, key) {
+            Some(loc) => Ok(Some(read_value(&files, &loc)?.as_slice().try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// append the entries to the log as a single record and apply them to the tables
+    fn write(&self, entries: &[Entry]) -> Result<(), KvError> {
+        let mut active = self.active();
+        self.write_locked(&mut active, entries)
+    }
+
+    fn write_locked(&self, active: &mut ActiveFile, entries: &[Entry]) -> Result<(), KvError> {
+        let seqs: Vec<_> = entries
+            .iter()
+            .map(|_| self.next_seq.fetch_add(1, Ordering::SeqCst))
+            .collect();
+        let (record, positions) = encode_record(entries, &seqs);
+
+        let rotated = active.size > 0 && active.size + record.len() as u64 > self.max_file_size;
+        if rotated {
+            self.rotate(active)?;
+        }
+        if let Err(e) = active.file.write_all(&record) {
+            // do not leave a partial record in front of the next one
+            let _ = active.file.set_len(active.size);
+            return Err(e.into());
+        }
+
+        for ((entry, seq), pos) in entries.iter().zip(seqs).zip(positions) {
+            let location = Location {
+                file_id: active.id,
+                pos: active.size + pos as u64,
+                len: entry.value.len() as u32,
+                seq,
+                expire_at: entry.expire_at,
+                entry_len: entry.len() as u32,
+            };
+            self.apply(entry, location);
+        }
+        active.size += record.len() as u64;
+        self.total_bytes
+            .fetch_add(record.len() as u64, Ordering::SeqCst);
+
+        if rotated && self.should_merge() {
+            self.merge_in_background();
+        }
+        Ok(())
+    }
+
+    /// apply a written entry to the tables
+    fn apply(&self, entry: &Entry, location: Location) {
+        match entry.kind {
+            Kind::Put => {
+                let table = self.tables.entry(entry.table.into()).or_default().clone();
+                if let Some(old) = table.get(entry.key) {
+                    self.release(old.value());
+                }
+                table.insert(entry.key.into(), location);
+                self.live_bytes
+                    .fetch_add(location.entry_len as u64, Ordering::SeqCst);
+            }
+            Kind::Delete => {
+                if let Some(table) = self.get_table(entry.table) {
+                    if let Some(old) = table.remove(entry.key) {
+                        self.release(old.value());
+                    }
+                }
+            }
+            Kind::DropTable => {
+                if let Some((_, table)) = self.tables.remove(entry.table) {
+                    table.iter().for_each(|e| self.release(e.value()));
+                }
+            }
+        }
+        // the entries superseding a value are garbage themselves once it is merged away
+    }
+
+    /// the entry at loc no longer holds the latest value of its key
+    fn release(&self, loc: &Location) {
+        self.live_bytes
+            .fetch_sub(loc.entry_len as u64, Ordering::SeqCst);
+    }
+
+    /// close the active file and start a new one
+    fn rotate(&self, active: &mut ActiveFile) -> Result<(), KvError> {
+        active.file.sync_data()?;
+        let id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+        let file = ActiveFile::create(&self.dir, id)?;
+        let reader = File::open(data_path(&self.dir, id))?;
+        self.files
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, Arc::new(Mutex::new(reader)));
+        *active = file;
+        Ok(())
+    }
+
+    /// more than half of the log is garbage, and there is at least a whole file of it
+    fn should_merge(&self) -> bool {
+        let total = self.total_bytes.load(Ordering::SeqCst);
+        let garbage = total.saturating_sub(self.live_bytes.load(Ordering::SeqCst));
+        garbage >= self.max_file_size && garbage * 2 > total
+    }
+
+    fn merge_in_background(&self) {
+        let inner = match self.me.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        thread::spawn(move || {
+            // a merge which is already running takes care of the garbage
+            let _merge = match inner.merge_lock.try_lock() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => return,
+            };
+            // files closed during the last merge may already be mostly garbage again
+            while !inner.closed.load(Ordering::SeqCst) && inner.should_merge() {
+                if let Err(e) = inner.merge_files() {
+                    warn!("merge of {:?} failed: {}", inner.dir, e);
+                    break;
+                }
+            }
+        });
+    }
+
+    fn merge(&self) -> Result<(), KvError> {
+        let _merge = self
+            .merge_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.merge_files()
+    }
+
+    fn merge_files(&self) -> Result<(), KvError> {
+        // every file older than a fresh active file is immutable
+        let sealed: HashSet<u64> = {
+            let mut active = self.active();
+            self.rotate(&mut active)?;
+            let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+            files
+                .keys()
+                .filter(|&&id| id < active.id)
+                .copied()
+                .collect()
+        };
+        if sealed.is_empty() {
+            return Ok(());
+        }
+
+        // copy the live values into new files, keeping their seq
+        let now = now_millis();
+        let mut output: Option<MergeOutput> = None;
+        let mut moved = Vec::new();
+        let mut expired = Vec::new();
+        let tables: Vec<_> = self
+            .tables
+            .iter()
+            .map(|t| (t.key().clone(), Arc::clone(t.value())))
+            .collect();
+        for (table, keydir) in tables {
+            for e in keydir.iter() {
+                let loc = *e.value();
+                if !sealed.contains(&loc.file_id) {
+                    continue;
+                }
+                if !loc.is_live(now) {
+                    expired.push((table.clone(), e.key().clone(), loc.seq));
+                    continue;
+                }
+
+                let value = {
+                    let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+                    read_value(&files, &loc)?
+                };
+                let entry = Entry::put(&table, e.key(), &value, loc.expire_at);
+                let out = match &mut output {
+                    Some(out) if out.size + entry.len() as u64 <= self.max_file_size => out,
+                    _ => {
+                        if let Some(out) = output.take() {
+                            self.finish_output(out)?;
+                        }
+                        let id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+                        output.insert(MergeOutput::create(&self.dir, id)?)
+                    }
+                };
+                let new_loc = out.append(&entry, loc)?;
+                moved.push((table.clone(), e.key().clone(), loc.seq, new_loc));
+            }
+        }
+        if let Some(out) = output.take() {
+            self.finish_output(out)?;
+        }
+
+        // point the tables at the copies, unless the key was written meanwhile
+        {
+            let _active = self.active();
+            for (table, key, seq, loc) in moved {
+                if let Some(keydir) = self.get_table(&table) {
+                    if matches!(keydir.get(&key), Some(e) if e.value().seq == seq) {
+                        keydir.insert(key, loc);
+                    }
+                }
+            }
+            for (table, key, seq) in expired {
+                if let Some(keydir) = self.get_table(&table) {
+                    if let Some(e) = keydir.get(&key).filter(|e| e.value().seq == seq) {
+                        self.release(e.value());
+                        e.remove();
+                    }
+                }
+            }
+        }
+
+        // once the manifest is written the old files are gone for good,
+        // even if the process dies before deleting them
+        let mut ids: Vec<_> = sealed.into_iter().collect();
+        ids.sort_unstable();
+        let manifest: String = ids.iter().map(|id| format!("{}\n", id)).collect();
+        write_atomically(&self.dir.join(MERGE_MANIFEST), manifest.as_bytes())?;
+        let mut files = self.files.write().unwrap_or_else(PoisonError::into_inner);
+        for id in &ids {
+            files.remove(id);
+        }
+        drop(files);
+        let freed = remove_data_files(&self.dir, &ids)?;
+        fs::remove_file(self.dir.join(MERGE_MANIFEST))?;
+        sync_dir(&self.dir)?;
+        self.total_bytes.fetch_sub(freed, Ordering::SeqCst);
+
+        info!("merged {} data files of {:?}", ids.len(), self.dir);
+        Ok(())
+    }
+
+    /// write the hint of a merged file, give the file its name and make its values readable
+    fn finish_output(&self, out: MergeOutput) -> Result<(), KvError> {
+        let mut file = out.file;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        write_atomically(&hint_path(&self.dir, out.id), &out.hint)?;
+        fs::rename(
+            merging_path(&self.dir, out.id),
+            data_path(&self.dir, out.id),
+        )?;
+        sync_dir(&self.dir)?;
+        let reader = File::open(data_path(&self.dir, out.id))?;
+        self.files
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(out.id, Arc::new(Mutex::new(reader)));
+        self.total_bytes.fetch_add(out.size, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// the live pairs of the table in the range in key order, up to limit of them if it
+    /// is not 0, only the keys for which f returns true before the first it returns false
+    fn scan(
+        &self,
+        table: &str,
+        range: (Bound<&str>, Bound<&str>),
+        reverse: bool,
+        limit: usize,
+        f: impl Fn(&str) -> bool,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let keydir = match self.get_table(table) {
+            Some(keydir) => keydir,
+            None => return Ok(Vec::new()),
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let entries = keydir.range::<str, _>(range);
+        let entries: Box<dyn Iterator<Item = _>> = match reverse {
+            true => Box::new(entries.rev()),
+            false => Box::new(entries),
+        };
+
+        let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+        let now = now_millis();
+        let mut pairs = Vec::new();
+        for e in entries.take_while(|e| f(e.key())) {
+            if pairs.len() == limit {
+                break;
+            }
+            let loc = *e.value();
+            if !loc.is_live(now) {
+                continue;
+            }
+            let value = read_value(&files, &loc)?;
+            pairs.push(Kvpair::new(e.key(), value.as_slice().try_into()?));
+        }
+        Ok(pairs)
+    }
+
+    fn insert(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = self.locks.write(table, key);
+        let old = self.lookup(table, key)?;
+        let data: Vec<u8> = value.try_into()?;
+        self.write(&[Entry::put(table, key, &data, expire_at)])?;
+        Ok(old)
+    }
+
+    /// rewrite the live value of the key with a new expiration, false if it does not exist
+    fn set_expire_at(
+        &self,
+        table: &str,
+        key: &str,
+        expire_at: Option<u64>,
+    ) -> Result<bool, KvError> {
+        let value = {
+            let files = self.files.read().unwrap_or_else(PoisonError::into_inner);
+            match self.location(table, key) {
+                Some(loc) => read_value(&files, &loc)?,
+                None => return Ok(false),
+            }
+        };
+        self.write(&[Entry::put(table, key, &value, expire_at)])?;
+        Ok(true)
+    }
+
+    /// replace the value of the key with f(old value) while holding the lock of the key,
+    /// the key keeps its time to live
+    fn update<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError>
+    where
+        T: Into<Value> + Copy,
+    {
+        let _guard = self.locks.write(table, key);
+        let expire_at = self.location(table, key).and_then(|loc| loc.expire_at);
+        let old = self.lookup(table, key)?;
+        let new = f(old.as_ref())?;
+        let data: Vec<u8> = new.into().try_into()?;
+        self.write(&[Entry::put(table, key, &data, expire_at)])?;
+        Ok(new)
+    }
+
+    /// write a delete of the key if it is in the table, expired or not
+    fn remove(&self, table: &str, key: &str) -> Result<(), KvError> {
+        match self.get_table(table) {
+            Some(keydir) if keydir.contains_key(key) => self.write(&[Entry::delete(table, key)]),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ActiveFile {
+    fn create(dir: &Path, id: u64) -> Result<Self, KvError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(data_path(dir, id))?;
+        let size = file.metadata()?.len();
+        Ok(Self { id, file, size })
+    }
+}
+
+/// a data file written by a merge with the hint listing its entries
+struct MergeOutput {
+    id: u64,
+    file: io::BufWriter<File>,
+    size: u64,
+    hint: Vec<u8>,
+}
+
+impl MergeOutput {
+    fn create(dir: &Path, id: u64) -> Result<Self, KvError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(merging_path(dir, id))?;
+        Ok(Self {
+            id,
+            file: io::BufWriter::new(file),
+            size: 0,
+            hint: Vec::new(),
+        })
+    }
+
+    /// append the entry as its own record with the seq of the value it copies
+    fn append(&mut self, entry: &Entry, old: Location) -> Result<Location, KvError> {
+        let (record, positions) = encode_record(std::slice::from_ref(entry), &[old.seq]);
+        self.file.write_all(&record)?;
+        let loc = Location {
+            file_id: self.id,
+            pos: self.size + positions[0] as u64,
+            ..old
+        };
+        self.size += record.len() as u64;
+        encode_hint(&mut self.hint, entry.table, entry.key, &loc);
+        Ok(loc)
+    }
+}
+
+fn data_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:010}.data", id))
+}
+
+fn hint_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:010}.hint", id))
+}
+
+/// where a merge writes a data file until it is complete
+fn merging_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:010}.merging", id))
+}
+
+/// lock the directory for this process, it fails if another store has it open
+fn lock_dir(dir: &Path) -> Result<File, KvError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => Err(KvError::Internal(format!(
+            "{:?} is used by another store",
+            dir
+        ))),
+        Err(fs::TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// make the renames and removals of files in the directory durable
+fn sync_dir(dir: &Path) -> Result<(), KvError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// ids of the data files in the directory in order
+fn data_file_ids(dir: &Path) -> Result<Vec<u64>, KvError> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let id = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".data"))
+            .and_then(|id| id.parse().ok());
+        if let Some(id) = id {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// delete the data files and their hints, returns the size of the data files
+fn remove_data_files(dir: &Path, ids: &[u64]) -> Result<u64, KvError> {
+    let mut freed = 0;
+    for &id in ids {
+        for path in [data_path(dir, id), hint_path(dir, id)] {
+            match fs::metadata(&path) {
+                Ok(meta) => {
+                    if path.extension().is_some_and(|ext| ext == "data") {
+                        freed += meta.len();
+                    }
+                    fs::remove_file(&path)?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    Ok(freed)
+}
+
+/// delete the files replaced by a merge that was interrupted before deleting them,
+/// and the unfinished output of a merge that was interrupted before completing
+fn finish_merge(dir: &Path) -> Result<(), KvError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let unfinished = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("merging") => true,
+            Some("hint") => !path.with_extension("data").exists(),
+            _ => false,
+        };
+        if unfinished {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let path = dir.join(MERGE_MANIFEST);
+    let manifest = match fs::read_to_string(&path) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let ids: Vec<u64> = manifest.lines().filter_map(|l| l.parse().ok()).collect();
+    remove_data_files(dir, &ids)?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// write the file under a temporary name and rename it, so it is either complete or missing
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), KvError> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// encode the entries as a single record, returns it with the offset of every value in it
+fn encode_record(entries: &[Entry], seqs: &[u64]) -> (Vec<u8>, Vec<usize>) {
+    let len: usize = entries.iter().map(Entry::len).sum();
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + len);
+    record.extend_from_slice(&[0; RECORD_HEADER_LEN]);
+    let mut positions = Vec::with_capacity(entries.len());
+    for (entry, seq) in entries.iter().zip(seqs) {
+        record.push(entry.kind as u8);
+        record.extend_from_slice(&seq.to_be_bytes());
+        record.extend_from_slice(&entry.expire_at.unwrap_or(0).to_be_bytes());
+        record.extend_from_slice(&(entry.table.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(entry.key.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(entry.value.len() as u32).to_be_bytes());
+        record.extend_from_slice(entry.table.as_bytes());
+        record.extend_from_slice(entry.key.as_bytes());
+        positions.push(record.len());
+        record.extend_from_slice(entry.value);
+    }
+
+    let crc = crc32(&record[RECORD_HEADER_LEN..]);
+    record[..4].copy_from_slice(&crc.to_be_bytes());
+    record[4..RECORD_HEADER_LEN].copy_from_slice(&(len as u32).to_be_bytes());
+    (record, positions)
+}
+
+/// the entries of a record, `None` if the payload is malformed
+fn decode_payload(payload: &[u8], file_id: u64, offset: u64) -> Option<Vec<LoadedEntry>> {
+    let mut buf = Cursor::new(payload);
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        let kind = Kind::try_from(buf.u8()?).ok()?;
+        let seq = buf.u64()?;
+        let expire_at = Some(buf.u64()?).filter(|&t| t != 0);
+        let (table_len, key_len, value_len) = (buf.u32()?, buf.u32()?, buf.u32()?);
+        let table = buf.string(table_len as usize)?;
+        let key = buf.string(key_len as usize)?;
+        let pos = offset + buf.pos() as u64;
+        buf.take(value_len as usize)?;
+        let entry_len = ENTRY_HEADER_LEN + table.len() + key.len() + value_len as usize;
+        entries.push(LoadedEntry {
+            kind,
+            table,
+            key,
+            location: Location {
+                file_id,
+                pos,
+                len: value_len,
+                seq,
+                expire_at,
+                entry_len: entry_len as u32,
+            },
+        });
+    }
+    Some(entries)
+}
+
+/// read the entries of a data file, the torn tail of the file which was active is truncated
+fn load_data_file(dir: &Path, id: u64, active: bool) -> Result<Vec<LoadedEntry>, KvError> {
+    let records = read_log(&data_path(dir, id), active, |payload, pos| {
+        decode_payload(payload, id, pos as u64)
+    })?;
+    Ok(records.into_iter().flatten().collect())
+}
+
+/// append the entry of a merged file to its hint,
+/// `seq | expire_at | pos | value length | entry length | table length | key length | table | key`
+fn encode_hint(hint: &mut Vec<u8>, table: &str, key: &str, loc: &Location) {
+    hint.extend_from_slice(&loc.seq.to_be_bytes());
+    hint.extend_from_slice(&loc.expire_at.unwrap_or(0).to_be_bytes());
+    hint.extend_from_slice(&loc.pos.to_be_bytes());
+    hint.extend_from_slice(&loc.len.to_be_bytes());
+    hint.extend_from_slice(&loc.entry_len.to_be_bytes());
+    hint.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    hint.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    hint.extend_from_slice(table.as_bytes());
+    hint.extend_from_slice(key.as_bytes());
+}
+
+/// the entries of a merged data file from its hint, `None` if it has no valid hint
+fn load_hint(dir: &Path, id: u64) -> Result<Option<Vec<LoadedEntry>>, KvError> {
+    let data = match fs::read(hint_path(dir, id)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut buf = Cursor::new(&data);
+    let mut entries = Vec::new();
+    while !buf.is_empty() {
+        let entry = (|| {
+            let (seq, expire_at, pos) = (buf.u64()?, buf.u64()?, buf.u64()?);
+            let (len, entry_len) = (buf.u32()?, buf.u32()?);
+            let (table_len, key_len) = (buf.u32()?, buf.u32()?);
+            let table = buf.string(table_len as usize)?;
+            let key = buf.string(key_len as usize)?;
+            Some(LoadedEntry {
+                kind: Kind::Put,
+                table,
+                key,
+                location: Location {
+                    file_id: id,
+                    pos,
+                    len,
+                    seq,
+                    expire_at: Some(expire_at).filter(|&t| t != 0),
+                    entry_len,
+                },
+            })
+        })();
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => {
+                warn!("ignoring the corrupt hint of data file {}", id);
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(entries))
+}
+
+/// the value at loc, the caller holds the read lock of the files
+fn read_value(files: &HashMap<u64, Arc<Mutex<File>>>, loc: &Location) -> Result<Vec<u8>, KvError> {
+    let file = files
+        .get(&loc.file_id)
+        .ok_or_else(|| KvError::Internal(format!("data file {} is missing", loc.file_id)))?;
+    let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut buf = vec![0; loc.len as usize];
+    file.seek(SeekFrom::Start(loc.pos))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+impl Storage for Bitcask {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.inner.locks.read(table, key);
+        self.inner.lookup(table, key)
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        self.inner.insert(table, key, value, None)
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.inner.insert(table, key, value, Some(expire_at(ttl)))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.inner.location(table, key).is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.inner.locks.write(table, key);
+        let old = self.inner.lookup(table, key)?;
+        self.inner.remove(table, key)?;
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        self.inner.scan(table, all, false, 0, |_| true)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        // the values are read upfront, the files may be merged away while iterating
+        Ok(Box::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        if !end.is_empty() && end <= start {
+            return Ok(Vec::new());
+        }
+        let upper = match end {
+            "" => Bound::Unbounded,
+            end => Bound::Excluded(end),
+        };
+        let range = (Bound::Included(start), upper);
+        self.inner.scan(table, range, reverse, limit, |_| true)
+    }
+
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError> {
+        let range = (Bound::Included(prefix), Bound::Unbounded);
+        self.inner
+            .scan(table, range, false, 0, |key| key.starts_with(prefix))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let _guard = self.inner.locks.write(table, key);
+        self.inner.set_expire_at(table, key, Some(expire_at(ttl)))
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let loc = self
+            .inner
+            .location(table, key)
+            .ok_or_else(|| KvError::NotFound(table.into(), key.into()))?;
+        let now = now_millis();
+        Ok(loc
+            .expire_at
+            .map(|t| Duration::from_millis(t.saturating_sub(now))))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _guard = self.inner.locks.write(table, key);
+        match self.inner.location(table, key) {
+            Some(loc) if loc.expire_at.is_some() => self.inner.set_expire_at(table, key, None),
+            _ => Ok(false),
+        }
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        // collect the expired keys first, so no table is iterated while waiting for a key lock
+        let now = now_millis();
+        let mut expired = Vec::new();
+        for table in self.inner.tables.iter() {
+            for e in table.value().iter() {
+                if !e.value().is_live(now) {
+                    expired.push((table.key().clone(), e.key().clone()));
+                }
+            }
+        }
+
+        let mut purged = 0;
+        for (table, key) in expired {
+            let _guard = self.inner.locks.write(&table, &key);
+            let loc = self
+                .inner
+                .get_table(&table)
+                .and_then(|keydir| keydir.get(&key).map(|e| *e.value()));
+            if matches!(loc, Some(loc) if !loc.is_live(now)) {
+                self.inner.write(&[Entry::delete(&table, &key)])?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.inner.update(table, key, |old| incr_value(old, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.inner
+            .update(table, key, |old| incr_float_value(old, delta))
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError> {
+        let _guard = self.inner.locks.write(table, key);
+        let current = self.inner.lookup(table, key)?;
+        if current != expected {
+            return Ok(Err(CompareAndSwapError { current }));
+        }
+
+        match new {
+            Some(v) => {
+                let data: Vec<u8> = v.try_into()?;
+                self.inner.write(&[Entry::put(table, key, &data, None)])?;
+            }
+            None => self.inner.remove(table, key)?,
+        }
+        Ok(Ok(()))
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<_> = self.inner.tables.iter().map(|t| t.key().clone()).collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let mut active = self.inner.active();
+        if !self.inner.tables.contains_key(table) {
+            return Ok(false);
+        }
+        self.inner
+            .write_locked(&mut active, &[Entry::drop_table(table)])?;
+        Ok(true)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        // holding the active file keeps every writer out until the rename is in the log
+        let mut active = self.inner.active();
+        let keydir = self
+            .inner
+            .get_table(from)
+            .ok_or_else(|| KvError::TableNotFound(from.into()))?;
+        if from == to {
+            return Ok(());
+        }
+        if self.inner.tables.contains_key(to) {
+            return Err(KvError::TableExists(to.into()));
+        }
+
+        // the copies and the drop of the old table are a single record
+        let now = now_millis();
+        let mut pairs = Vec::new();
+        {
+            let files = self
+                .inner
+                .files
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            for e in keydir.iter().filter(|e| e.value().is_live(now)) {
+                let loc = *e.value();
+                pairs.push((e.key().clone(), read_value(&files, &loc)?, loc.expire_at));
+            }
+        }
+        let mut entries: Vec<_> = pairs
+            .iter()
+            .map(|(key, value, expire_at)| Entry::put(to, key, value, *expire_at))
+            .collect();
+        entries.push(Entry::drop_table(from));
+        self.inner.write_locked(&mut active, &entries)?;
+        // an empty table still exists after the rename
+        self.inner.tables.entry(to.into()).or_default();
+        Ok(())
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let now = now_millis();
+        Ok(self.inner.get_table(table).map_or(0, |keydir| {
+            keydir.iter().filter(|e| e.value().is_live(now)).count()
+        }))
+    }
+
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>,
+    {
+        run_transaction(&*self.inner, f)
+    }
+}
+
+impl LockingStore for Inner {
+    fn locks(&self) -> &LockSet {
+        &self.locks
+    }
+
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        Inner::lookup(self, table, key)
+    }
+
+    /// write all changes as a single record
+    fn apply(&self, writes: TxnWrites) -> Result<(), KvError> {
+        let mut puts = Vec::new();
+        let mut deletes = Vec::new();
+        for ((table, key), value) in writes {
+            match value {
+                Some(value) => puts.push((table, key, Vec::<u8>::try_from(value)?)),
+                None => deletes.push((table, key)),
+            }
+        }
+
+        let mut entries: Vec<_> = puts
+            .iter()
+            .map(|(table, key, data)| Entry::put(table, key, data, None))
+            .collect();
+        entries.extend(deletes.iter().map(|(table, key)| Entry::delete(table, key)));
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.write(&entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::storage::{
+        test_basi_interface, test_compare_and_swap, test_compare_and_swap_expired, test_get_all,
+        test_incr, test_incr_concurrently, test_incr_keeps_ttl, test_prefix, test_purge_expired,
+        test_range, test_tables, test_transaction, test_transaction_concurrently, test_ttl,
+    };
+
+    use super::*;
+    use crate::storage::record::decode_record;
+
+    #[test]
+    fn bitcask_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn bitcask_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_get_all(store);
+    }
+
+    #[test]
+    fn bitcask_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_ttl(store);
+    }
+
+    #[test]
+    fn bitcask_purge_expired_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn bitcask_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_incr(store);
+    }
+
+    #[test]
+    fn bitcask_incr_should_be_atomic() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_incr_concurrently(store);
+    }
+
+    #[test]
+    fn bitcask_incr_should_keep_ttl() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_incr_keeps_ttl(store);
+    }
+
+    #[test]
+    fn bitcask_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn bitcask_compare_and_swap_should_ignore_expired_value() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_compare_and_swap_expired(store);
+    }
+
+    #[test]
+    fn bitcask_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_transaction(store);
+    }
+
+    #[test]
+    fn bitcask_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_transaction_concurrently(store);
+    }
+
+    #[test]
+    fn bitcask_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_range(store);
+    }
+
+    #[test]
+    fn bitcask_prefix_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_prefix(store);
+    }
+
+    #[test]
+    fn bitcask_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(dir);
+        test_tables(store);
+    }
+
+    #[test]
+    fn bitcask_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.del("t1", "k2").unwrap();
+            store
+                .set_ex("t2", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            store.set("t3", "k1", "v1".into()).unwrap();
+            store.drop_table("t3").unwrap();
+            store.set("t4", "k1", "v1".into()).unwrap();
+            store.rename_table("t4", "t5").unwrap();
+        }
+
+        let store = Bitcask::new(&dir);
+        assert_eq!(
+            store.list_tables(),
+            Ok(vec!["t1".into(), "t2".into(), "t5".into()])
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert!(store.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.get("t5", "k1"), Ok(Some("v1".into())));
+
+        // new writes never reuse the seq of an old one
+        store.set("t3", "k1", "v2".into()).unwrap();
+        drop(store);
+        let store = Bitcask::new(&dir);
+        assert_eq!(store.get("t3", "k1"), Ok(Some("v2".into())));
+    }
+
+    #[test]
+    fn bitcask_should_truncate_torn_tail() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.set("t1", "k3", "v3".into()).unwrap();
+        }
+
+        // a process killed in the middle of a write leaves part of the last record behind
+        let ids = data_file_ids(dir.path()).unwrap();
+        let path = data_path(dir.path(), ids[0]);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let store = Bitcask::new(&dir);
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+
+        store.set("t1", "k4", "v4".into()).unwrap();
+        drop(store);
+        let store = Bitcask::new(&dir);
+        assert_eq!(store.len("t1"), Ok(3));
+        assert_eq!(store.get("t1", "k4"), Ok(Some("v4".into())));
+    }
+
+    #[test]
+    fn bitcask_should_reject_corrupt_record() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+
+        // flip the last byte of the value of k1, truncating there would lose k2
+        let path = data_path(dir.path(), data_file_ids(dir.path()).unwrap()[0]);
+        let mut data = fs::read(&path).unwrap();
+        let first = decode_record(&data, 0).unwrap().1;
+        data[first - 1] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        let res = Bitcask::try_new(&dir);
+        assert!(matches!(res, Err(KvError::Internal(_))));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn bitcask_should_reject_torn_tail_of_older_file() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+        }
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+
+        // only the file written last can be torn by a crash
+        let ids = data_file_ids(dir.path()).unwrap();
+        assert_eq!(ids.len(), 2);
+        let path = data_path(dir.path(), ids[0]);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let res = Bitcask::try_new(&dir);
+        assert!(matches!(res, Err(KvError::Internal(_))));
+    }
+
+    #[test]
+    fn bitcask_should_lock_its_directory() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(&dir);
+        let res = Bitcask::try_new(&dir);
+        assert!(matches!(res, Err(KvError::Internal(_))));
+
+        drop(store);
+        assert!(Bitcask::try_new(&dir).is_ok());
+    }
+
+    #[test]
+    fn bitcask_merge_should_reclaim_garbage() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_max_file_size(&dir, 512);
+            for i in 0..100 {
+                store.set("t1", "k1", i.into()).unwrap();
+                store.set("t1", &format!("k{}", i % 10), i.into()).unwrap();
+            }
+            store.set("t2", "k1", "v1".into()).unwrap();
+            store.del("t1", "k2").unwrap();
+            store
+                .set_ex("t1", "k3", "v3".into(), Duration::from_millis(1))
+                .unwrap();
+            thread::sleep(Duration::from_millis(10));
+
+            // background merges may have run already, either way only the live values are left
+            store.merge().unwrap();
+            let size: u64 = data_file_ids(dir.path())
+                .unwrap()
+                .into_iter()
+                .map(|id| fs::metadata(data_path(dir.path(), id)).unwrap().len())
+                .sum();
+            assert!(size < 9 * 64, "{} bytes left after merge", size);
+            assert!(!dir.path().join(MERGE_MANIFEST).exists());
+
+            assert_eq!(store.get("t1", "k1"), Ok(Some(99.into())));
+            assert_eq!(store.get("t1", "k9"), Ok(Some(99.into())));
+            assert_eq!(store.get("t1", "k2"), Ok(None));
+            assert_eq!(store.get("t1", "k3"), Ok(None));
+            assert_eq!(store.len("t1"), Ok(8));
+        }
+
+        // the merged files are loaded from their hints
+        let ids = data_file_ids(dir.path()).unwrap();
+        assert!(ids.iter().any(|&id| hint_path(dir.path(), id).exists()));
+        let store = Bitcask::new(&dir);
+        assert_eq!(store.get("t1", "k1"), Ok(Some(99.into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.len("t1"), Ok(8));
+    }
+
+    #[test]
+    fn bitcask_should_merge_in_background() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::with_max_file_size(&dir, 256);
+        for i in 0..500 {
+            store.set("t1", "k1", i.into()).unwrap();
+        }
+
+        // overwriting one key makes every file but the latest garbage
+        let start = std::time::Instant::now();
+        while data_file_ids(dir.path()).unwrap().len() > 4 {
+            assert!(start.elapsed() < Duration::from_secs(5), "no merge ran");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(store.get("t1", "k1"), Ok(Some(499.into())));
+    }
+
+    #[test]
+    fn bitcask_should_finish_interrupted_merge() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+        }
+        // the merge wrote its manifest but died before deleting the old file
+        let id = data_file_ids(dir.path()).unwrap()[0];
+        fs::write(dir.path().join(MERGE_MANIFEST), format!("{}\n", id)).unwrap();
+
+        let store = Bitcask::new(&dir);
+        assert!(!dir.path().join(MERGE_MANIFEST).exists());
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn bitcask_should_discard_unfinished_merge_output() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+        }
+        // a merge died while writing its output, the torn tail of the active file stays the
+        // only one which may be truncated
+        let id = data_file_ids(dir.path()).unwrap()[0];
+        fs::write(merging_path(dir.path(), id + 1), b"garbage").unwrap();
+        fs::write(hint_path(dir.path(), id + 2), b"garbage").unwrap();
+
+        let store = Bitcask::new(&dir);
+        assert!(!merging_path(dir.path(), id + 1).exists());
+        assert!(!hint_path(dir.path(), id + 2).exists());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use crate::{KvError, Storage};
+
+/// run a synchronous storage on the blocking thread pool of tokio,
+/// so a backend doing disk I/O never stalls the threads driving the connections
+#[derive(Debug)]
+pub struct BlockingStorage<S> {
+    store: Arc<S>,
+}
+
+impl<S> Clone for BlockingStorage<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+        }
+    }
+}
+
+impl<S: Storage> BlockingStorage<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// the wrapped storage, calling it directly blocks the current thread
+    pub fn get_ref(&self) -> &S {
+        &self.store
+    }
+
+    /// run f with the storage on the blocking thread pool, a panic in f is returned as an error
+    pub async fn run<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: FnOnce(&S) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let store = Arc::clone(&self.store);
+        tokio::task::spawn_blocking(move || f(&store))
+            .await
+            .map_err(|e| KvError::Internal(format!("storage task failed: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemTable;
+
+    #[tokio::test]
+    async fn blocking_storage_should_work() {
+        let store = BlockingStorage::new(MemTable::new());
+        let res = store.run(|s| s.set("t1", "k1", "v1".into())).await;
+        assert_eq!(res, Ok(Ok(None)));
+        let res = store.run(|s| s.get("t1", "k1")).await;
+        assert_eq!(res, Ok(Ok(Some("v1".into()))));
+        assert_eq!(store.get_ref().get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[tokio::test]
+    async fn blocking_storage_should_return_panic_as_error() {
+        let store = BlockingStorage::new(MemTable::new());
+        let res = store.run(|_| panic!("boom")).await;
+        assert!(matches!(res, Err(KvError::Internal(_))));
+    }
+}
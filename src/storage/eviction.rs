@@ -0,0 +1,265 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use crossbeam_skiplist::{map::Entry, SkipMap};
+
+/// which keys a memtable over its memory budget evicts first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// the least recently read or written keys
+    Lru,
+    /// the least frequently read or written keys, the least recently used among equally
+    /// frequent ones. A key keeps its count when it is overwritten.
+    Lfu,
+}
+
+/// how much memory the keys and values of a memtable may take, as the length of the key
+/// plus the encoded length of the value, and which keys it evicts to stay within it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub max_bytes: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl MemoryBudget {
+    pub fn new(max_bytes: usize, policy: EvictionPolicy) -> Self {
+        Self { max_bytes, policy }
+    }
+}
+
+/// the memory a memtable with a budget uses and what it evicted to stay within it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    pub used_bytes: usize,
+    pub max_bytes: usize,
+    pub evicted_keys: u64,
+    pub evicted_bytes: u64,
+}
+
+/// when a key was last used, as a tick of the clock of its evictor, and how often.
+/// It is locked while the rank of the key changes, so concurrent reads of the key move
+/// its rank one after the other instead of leaving stale ranks behind.
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    state: Mutex<UsageState>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct UsageState {
+    tick: u64,
+    hits: u64,
+}
+
+impl Clone for Usage {
+    fn clone(&self) -> Self {
+        Self {
+            state: Mutex::new(*self.lock()),
+        }
+    }
+}
+
+impl Usage {
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    fn lock(&self) -> MutexGuard<'_, UsageState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// the position of a key in the eviction order, `(hits, tick)` for lfu and `(0, tick)` for lru.
+/// Every use of a key gets a new tick, so no two keys share a rank.
+pub(crate) type Rank = (u64, u64);
+
+/// tracks the memory used by the entries of a memtable and the order to evict them in
+pub(crate) struct Evictor {
+    budget: MemoryBudget,
+    used: AtomicUsize,
+    clock: AtomicU64,
+    /// rank -> (table, key), the first key is evicted first. A rank whose entry is gone
+    /// is dropped once eviction comes across it.
+    order: SkipMap<Rank, (String, String)>,
+    evicted_keys: AtomicU64,
+    evicted_bytes: AtomicU64,
+}
+
+impl fmt::Debug for Evictor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Evictor")
+            .field("budget", &self.budget)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl Evictor {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            used: AtomicUsize::new(0),
+            clock: AtomicU64::new(0),
+            order: SkipMap::new(),
+            evicted_keys: AtomicU64::new(0),
+            evicted_bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn rank(&self, tick: u64, hits: u64) -> Rank {
+        match self.budget.policy {
+            EvictionPolicy::Lru => (0, tick),
+            EvictionPolicy::Lfu => (hits, tick),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// an entry of size bytes with usage was written to the key,
+    /// it counts as a use on top of the hits of the entry it replaced
+    pub fn written(&self, table: &str, key: &str, size: usize, usage: &Usage, old_hits: u64) {
+        let mut state = usage.lock();
+        *state = UsageState {
+            tick: self.tick(),
+            hits: old_hits + 1,
+        };
+        self.order.insert(
+            self.rank(state.tick, state.hits),
+            (table.into(), key.into()),
+        );
+        self.used.fetch_add(size, Ordering::SeqCst);
+    }
+
+    /// the entry of the key was read
+    pub fn accessed(&self, table: &str, key: &str, usage: &Usage) {
+        let mut state = usage.lock();
+        self.order.remove(&self.rank(state.tick, state.hits));
+        *state = UsageState {
+            tick: self.tick(),
+            hits: state.hits + 1,
+        };
+        self.order.insert(
+            self.rank(state.tick, state.hits),
+            (table.into(), key.into()),
+        );
+    }
+
+    /// an entry of size bytes with usage was removed
+    pub fn removed(&self, size: usize, usage: &Usage) {
+        let state = usage.lock();
+        self.order.remove(&self.rank(state.tick, state.hits));
+        self.used.fetch_sub(size, Ordering::SeqCst);
+    }
+
+    /// the table with the entry with usage was renamed to table
+    pub fn renamed(&self, table: &str, key: &str, usage: &Usage) {
+        let state = usage.lock();
+        self.order.insert(
+            self.rank(state.tick, state.hits),
+            (table.into(), key.into()),
+        );
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.used.load(Ordering::SeqCst) > self.budget.max_bytes
+    }
+
+    /// the keys to evict in order
+    pub fn candidates(&self) -> impl Iterator<Item = Entry<'_, Rank, (String, String)>> {
+        self.order.iter()
+    }
+
+    /// whether rank is the current rank of the entry with usage
+    pub fn is_current(rank: Rank, usage: &Usage) -> bool {
+        usage.lock().tick == rank.1
+    }
+
+    /// drop a rank whose entry was used again or removed since
+    pub fn forget(&self, rank: Rank) {
+        self.order.remove(&rank);
+    }
+
+    /// an entry of size bytes was evicted, after it was removed
+    pub fn evicted(&self, size: usize) {
+        self.evicted_keys.fetch_add(1, Ordering::SeqCst);
+        self.evicted_bytes.fetch_add(size as u64, Ordering::SeqCst);
+    }
+
+    pub fn stats(&self) -> EvictionStats {
+        EvictionStats {
+            used_bytes: self.used.load(Ordering::SeqCst),
+            max_bytes: self.budget.max_bytes,
+            evicted_keys: self.evicted_keys.load(Ordering::SeqCst),
+            evicted_bytes: self.evicted_bytes.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(evictor: &Evictor) -> Vec<String> {
+        evictor.candidates().map(|e| e.value().1.clone()).collect()
+    }
+
+    #[test]
+    fn lru_should_order_by_last_use() {
+        let evictor = Evictor::new(MemoryBudget::new(10, EvictionPolicy::Lru));
+        let (u1, u2) = (Usage::default(), Usage::default());
+        evictor.written("t1", "k1", 6, &u1, 0);
+        evictor.written("t1", "k2", 6, &u2, 0);
+        assert!(evictor.over_budget());
+        assert_eq!(order(&evictor), vec!["k1", "k2"]);
+
+        evictor.accessed("t1", "k1", &u1);
+        assert_eq!(order(&evictor), vec!["k2", "k1"]);
+
+        evictor.removed(6, &u2);
+        assert_eq!(order(&evictor), vec!["k1"]);
+        assert!(!evictor.over_budget());
+    }
+
+    #[test]
+    fn lfu_should_order_by_hits() {
+        let evictor = Evictor::new(MemoryBudget::new(10, EvictionPolicy::Lfu));
+        let (u1, u2) = (Usage::default(), Usage::default());
+        evictor.written("t1", "k1", 1, &u1, 0);
+        evictor.accessed("t1", "k1", &u1);
+        evictor.written("t1", "k2", 1, &u2, 0);
+        assert_eq!(order(&evictor), vec!["k2", "k1"]);
+
+        // overwriting k2 keeps its count
+        evictor.removed(1, &u2);
+        let u3 = Usage::default();
+        evictor.written("t1", "k2", 1, &u3, u2.hits());
+        evictor.accessed("t1", "k2", &u3);
+        assert_eq!(order(&evictor), vec!["k1", "k2"]);
+    }
+
+    #[test]
+    fn concurrent_reads_should_leave_one_rank() {
+        for policy in [EvictionPolicy::Lru, EvictionPolicy::Lfu] {
+            let evictor = Evictor::new(MemoryBudget::new(10, policy));
+            let usage = Usage::default();
+            evictor.written("t1", "k1", 1, &usage, 0);
+            std::thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        for _ in 0..1000 {
+                            evictor.accessed("t1", "k1", &usage);
+                        }
+                    });
+                }
+            });
+            assert_eq!(order(&evictor), vec!["k1"]);
+            assert_eq!(usage.hits(), 8001);
+        }
+    }
+}
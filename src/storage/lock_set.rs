@@ -0,0 +1,208 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
+    thread,
+};
+
+use crate::{KvError, Transaction, Value};
+
+/// how many stripes the keys are spread over
+const STRIPES: usize = 256;
+
+/// striped read/write locks over (table, key), a key always maps to the same stripe,
+/// so a writer of the key excludes every other reader and writer of it
+pub(crate) struct LockSet {
+    stripes: Vec<RwLock<()>>,
+}
+
+impl Default for LockSet {
+    fn default() -> Self {
+        Self {
+            stripes: (0..STRIPES).map(|_| RwLock::new(())).collect(),
+        }
+    }
+}
+
+impl fmt::Debug for LockSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockSet")
+            .field("stripes", &self.stripes.len())
+            .finish()
+    }
+}
+
+impl LockSet {
+    /// the stripe guarding the key
+    pub fn stripe(&self, table: &str, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        table.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % self.stripes.len() as u64) as usize
+    }
+
+    pub fn read(&self, table: &str, key: &str) -> RwLockReadGuard<'_, ()> {
+        // the locks guard no data, so a poisoned lock is still usable
+        self.stripes[self.stripe(table, key)]
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self, table: &str, key: &str) -> RwLockWriteGuard<'_, ()> {
+        self.stripes[self.stripe(table, key)]
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// lock every stripe for reading, so a scan sees a transaction either fully or not at all.
+    /// Stripes are locked in order and writers never wait for a second stripe, so it cannot
+    /// deadlock, it has to be taken before any lock on the data itself.
+    pub fn read_all(&self) -> Vec<RwLockReadGuard<'_, ()>> {
+        self.stripes
+            .iter()
+            .map(|stripe| stripe.read().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    /// lock every stripe for writing, so no key is read or written while a whole table changes,
+    /// in the same order and under the same rule as `read_all`
+    pub fn write_all(&self) -> Vec<RwLockWriteGuard<'_, ()>> {
+        self.stripes
+            .iter()
+            .map(|stripe| stripe.write().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    /// lock the stripe for writing without blocking, `None` if it is held by someone else
+    pub fn try_write(&self, stripe: usize) -> Option<RwLockWriteGuard<'_, ()>> {
+        match self.stripes[stripe].try_write() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+/// the writes of a transaction, (table, key) -> the new value, `None` if the key is deleted
+pub(crate) type TxnWrites = HashMap<(String, String), Option<Value>>;
+
+/// a store whose single key operations lock their key in a LockSet,
+/// so its transactions can lock every key they touch in the same set
+pub(crate) trait LockingStore {
+    fn locks(&self) -> &LockSet;
+
+    /// the live value of the key, the caller holds the lock of the key
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+
+    /// apply the writes of a transaction, the caller holds the locks of all of their keys
+    fn apply(&self, writes: TxnWrites) -> Result<(), KvError>;
+}
+
+/// run f in a transaction over the store, it is retried from scratch while it conflicts
+pub(crate) fn run_transaction<S, R, F>(store: &S, f: F) -> Result<R, KvError>
+where
+    S: LockingStore,
+    F: Fn(&dyn Transaction) -> Result<R, KvError>,
+{
+    loop {
+        let txn = LockingTxn::new(store);
+        let result = f(&txn);
+        if txn.conflict.get() {
+            // release every lock so the other transaction can finish, then start over
+            drop(txn);
+            thread::yield_now();
+            continue;
+        }
+
+        if result.is_ok() {
+            txn.commit()?;
+        }
+        return result;
+    }
+}
+
+/// a transaction which locks every key it touches and buffers its writes until it commits.
+/// Locks are taken without blocking: a key locked by someone else marks the attempt
+/// as conflicted, and it is retried from scratch, so transactions never deadlock.
+struct LockingTxn<'a, S> {
+    store: &'a S,
+    guards: RefCell<HashMap<usize, RwLockWriteGuard<'a, ()>>>,
+    writes: RefCell<TxnWrites>,
+    conflict: Cell<bool>,
+}
+
+impl<'a, S: LockingStore> LockingTxn<'a, S> {
+    fn new(store: &'a S) -> Self {
+        Self {
+            store,
+            guards: Default::default(),
+            writes: Default::default(),
+            conflict: Cell::new(false),
+        }
+    }
+
+    fn lock(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let locks = self.store.locks();
+        let stripe = locks.stripe(table, key);
+        let mut guards = self.guards.borrow_mut();
+        if guards.contains_key(&stripe) {
+            return Ok(());
+        }
+
+        match locks.try_write(stripe) {
+            Some(guard) => {
+                guards.insert(stripe, guard);
+                Ok(())
+            }
+            None => {
+                self.conflict.set(true);
+                Err(KvError::Internal("transaction conflict".into()))
+            }
+        }
+    }
+
+    /// apply the writes, the locks are released once all of them are visible
+    fn commit(self) -> Result<(), KvError> {
+        let LockingTxn {
+            store,
+            guards,
+            writes,
+            ..
+        } = self;
+        let result = store.apply(writes.into_inner());
+        drop(guards);
+        result
+    }
+}
+
+impl<S: LockingStore> Transaction for LockingTxn<'_, S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.lock(table, key)?;
+        match self.writes.borrow().get(&(table.into(), key.into())) {
+            Some(value) => Ok(value.clone()),
+            None => self.store.lookup(table, key),
+        }
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.writes
+            .borrow_mut()
+            .insert((table.into(), key.into()), Some(value));
+        Ok(old)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get(table, key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let old = self.get(table, key)?;
+        self.writes
+            .borrow_mut()
+            .insert((table.into(), key.into()), None);
+        Ok(old)
+    }
+}
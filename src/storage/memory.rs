@@ -1,10 +1,137 @@
-use dashmap::{mapref::one::Ref, DashMap};
+use std::{
+    ops::Bound,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock, RwLockWriteGuard},
+    thread,
+    time::Duration,
+};
 
-use crate::{KvError, Kvpair, Storage, Value};
+use crossbeam_skiplist::SkipSet;
+use dashmap::{
+    mapref::{entry::Entry as MapEntry, one::Ref},
+    DashMap,
+};
+use prost::Message;
+use tracing::warn;
 
-#[derive(Clone, Debug, Default)]
+use crate::{
+    storage::{
+        eviction::{EvictionStats, Evictor, MemoryBudget, Usage},
+        expire_at, incr_float_value, incr_value,
+        lock_set::{run_transaction, LockSet, LockingStore, TxnWrites},
+        now_millis,
+        wal::{Op, Wal, WalConfig},
+    },
+    CompareAndSwapError, KvError, Kvpair, Storage, Transaction, Value,
+};
+
+#[derive(Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    tables: Arc<DashMap<String, Table>>,
+    /// single key operations lock their key, transactions lock every key they touch,
+    /// scans lock every key for reading
+    locks: Arc<LockSet>,
+    /// a snapshot holds it for reading while it copies the tables, dropping and renaming
+    /// a table hold it for writing, so no table moves under a snapshot
+    table_lock: Arc<RwLock<()>>,
+    /// the log of a persistent memtable, every write to the tables is applied while holding it
+    wal: Option<Arc<Wal>>,
+    /// the memory used by a memtable with a budget and the order to evict its keys in
+    evictor: Option<Arc<Evictor>>,
+}
+
+impl Clone for MemTable {
+    /// a clone is a copy in memory only, its writes are not logged and it has no budget
+    fn clone(&self) -> Self {
+        Self {
+            tables: Arc::new((*self.tables).clone()),
+            locks: Arc::clone(&self.locks),
+            table_lock: Arc::default(),
+            wal: None,
+            evictor: None,
+        }
+    }
+}
+
+/// the entries of a table and an ordered index of their keys for range queries
+#[derive(Debug, Default)]
+struct Table {
+    entries: DashMap<String, Entry>,
+    /// every key of entries, a key is indexed before its entry is inserted
+    /// and removed from the index after its entry is removed
+    keys: SkipSet<String>,
+}
+
+impl Clone for Table {
+    fn clone(&self) -> Self {
+        let entries = self.entries.clone();
+        let keys = entries.iter().map(|e| e.key().clone()).collect();
+        Self { entries, keys }
+    }
+}
+
+impl Table {
+    fn insert(&self, key: &str, entry: Entry) -> Option<Entry> {
+        self.keys.insert(key.to_string());
+        self.entries.insert(key.to_string(), entry)
+    }
+
+    fn remove(&self, key: &str) -> Option<Entry> {
+        let (_k, entry) = self.entries.remove(key)?;
+        self.keys.remove(key);
+        Some(entry)
+    }
+
+    /// lazily remove the key if it is expired
+    fn remove_expired(&self, key: &str, now: u64) -> Option<Entry> {
+        let (_k, entry) = self.entries.remove_if(key, |_, e| e.is_expired(now))?;
+        self.keys.remove(key);
+        Some(entry)
+    }
+
+    /// the live pairs whose key is within the bounds, in key order
+    fn range<'a>(
+        &'a self,
+        bounds: (Bound<&'a str>, Bound<&'a str>),
+        reverse: bool,
+        now: u64,
+    ) -> Box<dyn Iterator<Item = Kvpair> + 'a> {
+        let keys = self.keys.range::<str, _>(bounds);
+        let keys: Box<dyn Iterator<Item = _>> = match reverse {
+            true => Box::new(keys.rev()),
+            false => Box::new(keys),
+        };
+        // a key may be indexed before its entry is visible, or its entry may have expired
+        Box::new(keys.filter_map(move |key| {
+            let entry = self.entries.get(key.value())?;
+            if entry.is_expired(now) {
+                return None;
+            }
+            Some(Kvpair::new(key.value(), entry.value.clone()))
+        }))
+    }
+}
+
+/// a value, the unix timestamp in milliseconds at which it expires and how it is used
+#[derive(Clone, Debug)]
+struct Entry {
+    value: Value,
+    expire_at: Option<u64>,
+    usage: Usage,
+}
+
+impl Entry {
+    fn new(value: Value, expire_at: Option<u64>) -> Self {
+        Self {
+            value,
+            expire_at,
+            usage: Usage::default(),
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expire_at, Some(t) if t <= now)
+    }
 }
 
 impl MemTable {
@@ -12,7 +139,286 @@ impl MemTable {
         Self::default()
     }
 
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    /// a memtable persisted to dir, every write is logged before it returns and all tables
+    /// are snapshotted periodically. It is recovered from the latest snapshot and the log after it.
+    pub fn with_wal(dir: impl AsRef<Path>, config: WalConfig) -> Self {
+        Self::try_with_wal(dir, config).unwrap()
+    }
+
+    /// recover a persistent memtable from dir, it fails if the snapshot or the log cannot be
+    /// read or hold a corrupt record
+    pub fn try_with_wal(dir: impl AsRef<Path>, config: WalConfig) -> Result<Self, KvError> {
+        let store = Self::new();
+        let wal = Wal::open(dir.as_ref(), config, |op| store.replay(op))?;
+        Ok(Self {
+            wal: Some(Arc::new(wal)),
+            ..store
+        })
+    }
+
+    /// evict keys by the policy of the budget whenever the keys and values take more memory
+    /// than it allows. Only the writes evict, so a memtable may exceed its budget by the key
+    /// written last. An evicted key is gone for good, so a persistent memtable cannot have
+    /// a budget, it panics.
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        assert!(
+            self.wal.is_none(),
+            "a persistent memtable cannot evict keys without losing them"
+        );
+        let evictor = Arc::new(Evictor::new(budget));
+        for table in self.tables.iter() {
+            for e in table.entries.iter() {
+                let size = entry_size(e.key(), &e);
+                evictor.written(table.key(), e.key(), size, &e.usage, 0);
+            }
+        }
+        self.evictor = Some(evictor);
+        self.evict();
+        self
+    }
+
+    /// the memory used and the keys evicted, `None` if the memtable has no budget
+    pub fn eviction_stats(&self) -> Option<EvictionStats> {
+        self.evictor.as_ref().map(|evictor| evictor.stats())
+    }
+
+    /// write a snapshot of all tables and delete the log before it, does nothing if the
+    /// memtable is not persistent
+    pub fn snapshot(&self) -> Result<(), KvError> {
+        let wal = match &self.wal {
+            Some(wal) => wal,
+            None => return Ok(()),
+        };
+        let segment = wal.lock().start_snapshot()?;
+        self.snapshot_source().write(wal, segment)
+    }
+
+    fn snapshot_source(&self) -> SnapshotSource {
+        SnapshotSource {
+            tables: Arc::clone(&self.tables),
+            locks: Arc::clone(&self.locks),
+            table_lock: Arc::clone(&self.table_lock),
+        }
+    }
+
+    fn replay(&self, op: Op) {
+        match op {
+            Op::Put {
+                table,
+                key,
+                value,
+                expire_at,
+            } => {
+                self.insert(&table, &key, value, expire_at);
+            }
+            Op::Delete { table, key } => {
+                self.remove(&table, &key);
+            }
+            Op::DropTable(table) => {
+                self.remove_table(&table);
+            }
+            Op::RenameTable { from, to } => {
+                if let Some((_, table)) = self.tables.remove(&from) {
+                    self.track_rename(&to, &table);
+                    self.tables.insert(to, table);
+                }
+            }
+        }
+    }
+
+    /// apply a write to the tables with f and log it, then evict keys if the memtable is
+    /// over its budget
+    fn logged<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, KvError>,
+        ops: impl FnOnce(&T) -> Vec<Op>,
+        undo: impl FnOnce(T),
+    ) -> Result<T, KvError> {
+        let result = self.log_write(f, ops, undo)?;
+        self.evict();
+        Ok(result)
+    }
+
+    /// apply a write to the tables with f, a persistent memtable then logs the ops describing it.
+    /// If they cannot be logged the write is reverted with undo, the caller holds the locks of
+    /// the keys it writes, so no one sees it meanwhile. Once the snapshot interval has passed,
+    /// a snapshot is written in the background.
+    fn log_write<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, KvError>,
+        ops: impl FnOnce(&T) -> Vec<Op>,
+        undo: impl FnOnce(T),
+    ) -> Result<T, KvError> {
+        let wal = match &self.wal {
+            Some(wal) => wal,
+            None => return f(),
+        };
+        let mut log = wal.lock();
+        let result = f()?;
+        if let Err(e) = log.append(&ops(&result)) {
+            undo(result);
+            return Err(e);
+        }
+        if !log.snapshot_due() {
+            return Ok(result);
+        }
+
+        // the write succeeded, a failing snapshot is retried on a later write
+        match log.start_snapshot() {
+            Ok(segment) => {
+                drop(log);
+                let (wal, source) = (Arc::clone(wal), self.snapshot_source());
+                thread::spawn(move || {
+                    if let Err(e) = source.write(&wal, segment) {
+                        warn!("failed to write snapshot: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to start snapshot: {}", e),
+        }
+        Ok(result)
+    }
+
+    /// apply a write to the key with f, a persistent memtable then logs the state of the key
+    fn write_key<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce() -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let saved = self.save_entry(table, key);
+        self.logged(
+            f,
+            |_| vec![self.key_op(table, key)],
+            |_| self.restore_entry(table, key, saved),
+        )
+    }
+
+    /// the entry of the key before a write, so it can be restored if logging the write fails,
+    /// only a persistent memtable keeps it
+    fn save_entry(&self, table: &str, key: &str) -> Option<Entry> {
+        self.wal.as_ref()?;
+        let entry = self.get_table(table)?.entries.get(key)?.clone();
+        Some(entry)
+    }
+
+    fn restore_entry(&self, table: &str, key: &str, entry: Option<Entry>) {
+        match entry {
+            Some(entry) => self.insert(table, key, entry.value, entry.expire_at),
+            None => self.remove(table, key),
+        };
+    }
+
+    /// the op setting the key to its current state
+    fn key_op(&self, table: &str, key: &str) -> Op {
+        let entry = self
+            .get_table(table)
+            .and_then(|t| t.entries.get(key).map(|e| e.value().clone()));
+        match entry {
+            Some(entry) => Op::Put {
+                table: table.into(),
+                key: key.into(),
+                value: entry.value,
+                expire_at: entry.expire_at,
+            },
+            None => Op::Delete {
+                table: table.into(),
+                key: key.into(),
+            },
+        }
+    }
+
+    /// evict keys until the memtable is within its budget, keys locked by someone else are
+    /// skipped, among them the one the caller just wrote
+    fn evict(&self) {
+        let evictor = match &self.evictor {
+            Some(evictor) if evictor.over_budget() => evictor,
+            _ => return,
+        };
+        for candidate in evictor.candidates() {
+            if !evictor.over_budget() {
+                break;
+            }
+            let rank = *candidate.key();
+            let (table, key) = candidate.value();
+            let _guard = match self.locks.try_write(self.locks.stripe(table, key)) {
+                Some(guard) => guard,
+                None => continue,
+            };
+            let size = self.get_table(table).and_then(|t| {
+                let entry = t.entries.get(key)?;
+                Evictor::is_current(rank, &entry.usage).then(|| entry_size(key, &entry))
+            });
+            let size = match size {
+                Some(size) => size,
+                None => {
+                    evictor.forget(rank);
+                    continue;
+                }
+            };
+            self.remove(table, key);
+            evictor.evicted(size);
+        }
+    }
+
+    /// the entry was removed from the key
+    fn untrack(&self, key: &str, entry: &Entry) {
+        if let Some(evictor) = &self.evictor {
+            evictor.removed(entry_size(key, entry), &entry.usage);
+        }
+    }
+
+    /// count the entries of a table put back against the budget
+    fn track_table(&self, name: &str, table: &Table) {
+        if let Some(evictor) = &self.evictor {
+            for e in table.entries.iter() {
+                evictor.written(name, e.key(), entry_size(e.key(), &e), &e.usage, 0);
+            }
+        }
+    }
+
+    /// the entries of table now belong to the table named to
+    fn track_rename(&self, to: &str, table: &Table) {
+        if let Some(evictor) = &self.evictor {
+            for e in table.entries.iter() {
+                evictor.renamed(to, e.key(), &e.usage);
+            }
+        }
+    }
+
+    /// lazily remove the key if it is expired
+    fn remove_expired(&self, table: &Table, key: &str, now: u64) -> bool {
+        match table.remove_expired(key, now) {
+            Some(entry) => {
+                self.untrack(key, &entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// remove the table with all of its keys, returns it if it existed
+    fn remove_table(&self, name: &str) -> Option<Table> {
+        let (_, table) = self.tables.remove(name)?;
+        for e in table.entries.iter() {
+            self.untrack(e.key(), &e);
+        }
+        Some(table)
+    }
+
+    /// taken before the locks of the keys
+    fn lock_tables(&self) -> RwLockWriteGuard<'_, ()> {
+        self.table_lock
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// the table if it exists, reads never create a table
+    fn get_table(&self, name: &str) -> Option<Ref<'_, String, Table>> {
+        self.tables.get(name)
+    }
+
+    fn get_or_create_table(&self, name: &str) -> Ref<'_, String, Table> {
         match self.tables.get(name) {
             Some(table) => table,
             None => {
@@ -21,50 +427,482 @@ impl MemTable {
             }
         }
     }
+
+    /// the live entry of the key, reading it counts as a use of the key
+    fn lookup_entry<R>(&self, table: &str, key: &str, f: impl FnOnce(&Entry) -> R) -> Option<R> {
+        let name = table;
+        let table = self.get_table(table)?;
+        self.remove_expired(&table, key, now_millis());
+        let entry = table.entries.get(key)?;
+        if let Some(evictor) = &self.evictor {
+            evictor.accessed(name, key, &entry.usage);
+        }
+        Some(f(&entry))
+    }
+
+    fn lookup(&self, table: &str, key: &str) -> Option<Value> {
+        self.lookup_entry(table, key, |e| e.value.clone())
+    }
+
+    fn insert(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Option<Value> {
+        let name = table;
+        let table = self.get_or_create_table(table);
+        let now = now_millis();
+        let old = table.insert(key, Entry::new(value, expire_at));
+        if let Some(evictor) = &self.evictor {
+            if let Some(old) = &old {
+                self.untrack(key, old);
+            }
+            if let Some(e) = table.entries.get(key) {
+                let old_hits = old.as_ref().map_or(0, |e| e.usage.hits());
+                evictor.written(name, key, entry_size(key, &e), &e.usage, old_hits);
+            }
+        }
+        old.filter(|e| !e.is_expired(now)).map(|e| e.value)
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Option<Value> {
+        let table = self.get_table(table)?;
+        let now = now_millis();
+        let entry = table.remove(key)?;
+        self.untrack(key, &entry);
+        Some(entry).filter(|e| !e.is_expired(now)).map(|e| e.value)
+    }
+
+    /// replace the value of the key with f(old value) while holding the lock of the key,
+    /// the key keeps its time to live
+    fn update<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError>
+    where
+        T: Into<Value> + Copy,
+    {
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || {
+            let old = self.lookup_entry(table, key, |e| (e.value.clone(), e.expire_at));
+            let new = f(old.as_ref().map(|(value, _)| value))?;
+            self.insert(table, key, new.into(), old.and_then(|(_, t)| t));
+            Ok(new)
+        })
+    }
+}
+
+/// the memory taken by the entry of the key, as counted against a budget
+fn entry_size(key: &str, entry: &Entry) -> usize {
+    key.len() + entry.value.encoded_len()
+}
+
+/// what a snapshot reads the tables from, apart from the memtable so it can be written in
+/// the background
+struct SnapshotSource {
+    tables: Arc<DashMap<String, Table>>,
+    locks: Arc<LockSet>,
+    table_lock: Arc<RwLock<()>>,
+}
+
+impl SnapshotSource {
+    /// write the live pairs as the snapshot the log continues from at segment. Writes go on
+    /// meanwhile, so it may hold some which are logged after it starts, replaying them again
+    /// is harmless. Every key is read under its lock, so it only holds writes which are logged.
+    fn write(&self, wal: &Wal, segment: u64) -> Result<(), KvError> {
+        let _tables = self
+            .table_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let names: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        let now = now_millis();
+        let pairs = names.into_iter().flat_map(move |name| {
+            // no table is borrowed while waiting for the lock of a key
+            let keys: Vec<String> = match self.tables.get(&name) {
+                Some(table) => table.keys.iter().map(|k| k.value().clone()).collect(),
+                None => Vec::new(),
+            };
+            keys.into_iter().filter_map(move |key| {
+                let _guard = self.locks.read(&name, &key);
+                let entry = self.tables.get(&name)?.entries.get(&key)?.clone();
+                (!entry.is_expired(now)).then(|| Op::Put {
+                    table: name.clone(),
+                    key,
+                    value: entry.value,
+                    expire_at: entry.expire_at,
+                })
+            })
+        });
+        wal.write_snapshot(segment, pairs)
+    }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let _guard = self.locks.read(table, key);
+        Ok(self.lookup(table, key))
     }
 
     fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key.to_string(), value))
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || Ok(self.insert(table, key, value, None)))
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = self.locks.write(table, key);
+        let expire_at = Some(expire_at(ttl));
+        self.write_key(table, key, || Ok(self.insert(table, key, value, expire_at)))
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        let _guard = self.locks.read(table, key);
+        Ok(self.lookup_entry(table, key, |_| ()).is_some())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || Ok(self.remove(table, key)))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
+        let _guards = self.locks.read_all();
+        let table = match self.get_table(table) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+        let now = now_millis();
         Ok(table
+            .entries
             .iter()
-            .map(|v| Kvpair::new(v.key(), v.value().clone()))
+            .filter(|v| !v.value().is_expired(now))
+            .map(|v| Kvpair::new(v.key(), v.value().value.clone()))
             .collect())
     }
 
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let table = self.get_or_create_table(table).clone();
-        let iter = table.into_iter().map(|data| data.into());
+        let _guards = self.locks.read_all();
+        let table = match self.get_table(table) {
+            Some(table) => table.entries.clone(),
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let now = now_millis();
+        let iter = table
+            .into_iter()
+            .filter(move |(_k, e)| !e.is_expired(now))
+            .map(|(k, e)| (k, e.value).into());
         Ok(Box::new(iter))
     }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let end = match end {
+            "" => Bound::Unbounded,
+            end if end <= start => return Ok(Vec::new()),
+            end => Bound::Excluded(end),
+        };
+        let _guards = self.locks.read_all();
+        let table = match self.get_table(table) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+        let iter = table.range((Bound::Included(start), end), reverse, now_millis());
+        Ok(match limit {
+            0 => iter.collect(),
+            n => iter.take(n).collect(),
+        })
+    }
+
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError> {
+        let _guards = self.locks.read_all();
+        let table = match self.get_table(table) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+        let bounds = (Bound::Included(prefix), Bound::Unbounded);
+        Ok(table
+            .range(bounds, false, now_millis())
+            .take_while(|pair| pair.key.starts_with(prefix))
+            .collect())
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || {
+            let table = match self.get_table(table) {
+                Some(table) => table,
+                None => return Ok(false),
+            };
+            self.remove_expired(&table, key, now_millis());
+            Ok(table
+                .entries
+                .get_mut(key)
+                .map(|mut e| e.expire_at = Some(expire_at(ttl)))
+                .is_some())
+        })
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _guard = self.locks.read(table, key);
+        let name = table;
+        let table = match self.get_table(table) {
+            Some(table) => table,
+            None => return Err(KvError::NotFound(name.into(), key.into())),
+        };
+        let now = now_millis();
+        self.remove_expired(&table, key, now);
+        let result = match table.entries.get(key) {
+            Some(e) => Ok(e
+                .expire_at
+                .map(|t| Duration::from_millis(t.saturating_sub(now)))),
+            None => Err(KvError::NotFound(name.into(), key.into())),
+        };
+        result
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || {
+            let table = match self.get_table(table) {
+                Some(table) => table,
+                None => return Ok(false),
+            };
+            self.remove_expired(&table, key, now_millis());
+            Ok(table
+                .entries
+                .get_mut(key)
+                .map(|mut e| e.expire_at.take().is_some())
+                .unwrap_or(false))
+        })
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        let now = now_millis();
+        // collected first, so no table is borrowed while waiting for the lock of a key
+        let mut expired = Vec::new();
+        for table in self.tables.iter() {
+            for e in table.entries.iter().filter(|e| e.is_expired(now)) {
+                expired.push((table.key().clone(), e.key().clone()));
+            }
+        }
+
+        let mut purged = 0;
+        for (table, key) in expired {
+            let _guard = self.locks.write(&table, &key);
+            let removed = self.write_key(&table, &key, || {
+                Ok(self
+                    .get_table(&table)
+                    .map(|table| self.remove_expired(&table, &key, now))
+                    .unwrap_or(false))
+            })?;
+            if removed {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError> {
+        let _guard = self.locks.write(table, key);
+        self.write_key(table, key, || {
+            let current = self.lookup(table, key);
+            if current != expected {
+                return Ok(Err(CompareAndSwapError { current }));
+            }
+            match new {
+                Some(value) => self.insert(table, key, value, None),
+                None => self.remove(table, key),
+            };
+            Ok(Ok(()))
+        })
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update(table, key, |old| incr_value(old, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update(table, key, |old| incr_float_value(old, delta))
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _tables = self.lock_tables();
+        // no key of the table is written while it goes away
+        let _guards = self.locks.write_all();
+        let dropped = self.logged(
+            || Ok(self.remove_table(table)),
+            |dropped| match dropped {
+                Some(_) => vec![Op::DropTable(table.into())],
+                None => vec![],
+            },
+            |dropped| {
+                if let Some(dropped) = dropped {
+                    self.track_table(table, &dropped);
+                    self.tables.insert(table.into(), dropped);
+                }
+            },
+        )?;
+        Ok(dropped.is_some())
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        let _tables = self.lock_tables();
+        let _guards = self.locks.write_all();
+        self.logged(
+            || {
+                if from == to {
+                    return match self.tables.contains_key(from) {
+                        true => Ok(()),
+                        false => Err(KvError::TableNotFound(from.into())),
+                    };
+                }
+
+                // the target is reserved first,
+                // so a concurrent rename to it fails instead of overwriting
+                match self.tables.entry(to.into()) {
+                    MapEntry::Occupied(_) => return Err(KvError::TableExists(to.into())),
+                    MapEntry::Vacant(e) => {
+                        e.insert(Table::default());
+                    }
+                }
+                match self.tables.remove(from) {
+                    Some((_, table)) => {
+                        self.track_rename(to, &table);
+                        self.tables.insert(to.into(), table);
+                        Ok(())
+                    }
+                    None => {
+                        self.tables.remove(to);
+                        Err(KvError::TableNotFound(from.into()))
+                    }
+                }
+            },
+            |_| match from == to {
+                true => vec![],
+                false => vec![Op::RenameTable {
+                    from: from.into(),
+                    to: to.into(),
+                }],
+            },
+            |_| {
+                if let Some((_, table)) = self.tables.remove(to) {
+                    self.track_rename(from, &table);
+                    self.tables.insert(from.into(), table);
+                }
+            },
+        )
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let _guards = self.locks.read_all();
+        let now = now_millis();
+        Ok(self
+            .get_table(table)
+            .map(|t| t.entries.iter().filter(|e| !e.is_expired(now)).count())
+            .unwrap_or(0))
+    }
+
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>,
+    {
+        run_transaction(self, f)
+    }
+}
+
+impl LockingStore for MemTable {
+    fn locks(&self) -> &LockSet {
+        &self.locks
+    }
+
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        Ok(MemTable::lookup(self, table, key))
+    }
+
+    /// a persistent memtable logs all changes as a single record
+    fn apply(&self, writes: TxnWrites) -> Result<(), KvError> {
+        let keys: Vec<_> = writes.keys().cloned().collect();
+        let saved: Vec<_> = keys
+            .iter()
+            .map(|(table, key)| self.save_entry(table, key))
+            .collect();
+        self.logged(
+            || {
+                for ((table, key), value) in writes {
+                    match value {
+                        Some(value) => self.insert(&table, &key, value, None),
+                        None => self.remove(&table, &key),
+                    };
+                }
+                Ok(())
+            },
+            |_| {
+                keys.iter()
+                    .map(|(table, key)| self.key_op(table, key))
+                    .collect()
+            },
+            |_| {
+                for ((table, key), entry) in keys.iter().zip(saved) {
+                    self.restore_entry(table, key, entry);
+                }
+            },
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::{test_basi_interface, test_get_all};
+    use std::{fs, thread};
+
+    use tempfile::tempdir;
+
+    use crate::storage::{
+        record::decode_record, test_basi_interface, test_compare_and_swap,
+        test_compare_and_swap_expired, test_get_all, test_incr, test_incr_concurrently,
+        test_incr_keeps_ttl, test_prefix, test_purge_expired, test_range, test_tables,
+        test_transaction, test_transaction_concurrently, test_ttl, EvictionPolicy, SyncPolicy,
+    };
 
     use super::*;
 
+    /// how many log segments are in dir
+    fn segments(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| {
+                let path = e.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "wal")
+            })
+            .count()
+    }
+
     #[test]
     fn memtable_basic_interface_should_work() {
         let store = MemTable::new();
@@ -76,4 +914,383 @@ mod tests {
         let store = MemTable::new();
         test_get_all(store)
     }
+
+    #[test]
+    fn memtable_ttl_should_work() {
+        let store = MemTable::new();
+        test_ttl(store);
+    }
+
+    #[test]
+    fn memtable_purge_expired_should_work() {
+        let store = MemTable::new();
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn memtable_incr_should_work() {
+        let store = MemTable::new();
+        test_incr(store);
+    }
+
+    #[test]
+    fn memtable_incr_should_be_atomic() {
+        let store = MemTable::new();
+        test_incr_concurrently(store);
+    }
+
+    #[test]
+    fn memtable_incr_should_keep_ttl() {
+        let store = MemTable::new();
+        test_incr_keeps_ttl(store);
+    }
+
+    #[test]
+    fn memtable_compare_and_swap_should_work() {
+        let store = MemTable::new();
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn memtable_compare_and_swap_should_ignore_expired_value() {
+        let store = MemTable::new();
+        test_compare_and_swap_expired(store);
+    }
+
+    #[test]
+    fn memtable_transaction_should_work() {
+        let store = MemTable::new();
+        test_transaction(store);
+    }
+
+    #[test]
+    fn memtable_transaction_should_be_isolated() {
+        let store = MemTable::new();
+        test_transaction_concurrently(store);
+    }
+
+    #[test]
+    fn memtable_scans_should_see_whole_transactions() {
+        // every transaction sets all keys to the same new value
+        let store = Arc::new(MemTable::new());
+        let keys: Vec<String> = (0..64).map(|i| format!("k{:02}", i)).collect();
+        for key in &keys {
+            store.set("t1", key, 0.into()).unwrap();
+        }
+
+        let writer = {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                for i in 1..=200 {
+                    store
+                        .transaction(|txn| {
+                            for key in &keys {
+                                txn.set("t1", key, i.into())?;
+                            }
+                            Ok(())
+                        })
+                        .unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let pairs = store.get_all("t1").unwrap();
+            assert!(pairs.windows(2).all(|w| w[0].value == w[1].value));
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn memtable_reads_should_not_create_tables() {
+        let store = MemTable::new();
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert_eq!(store.get_all("t1"), Ok(vec![]));
+        assert_eq!(store.get_iter("t1").unwrap().count(), 0);
+        assert_eq!(store.del("t1", "k1"), Ok(None));
+        assert!(store.tables.is_empty());
+    }
+
+    #[test]
+    fn memtable_tables_should_work() {
+        let store = MemTable::new();
+        test_tables(store);
+    }
+
+    #[test]
+    fn memtable_range_should_work() {
+        let store = MemTable::new();
+        test_range(store);
+    }
+
+    #[test]
+    fn memtable_prefix_should_work() {
+        let store = MemTable::new();
+        test_prefix(store);
+    }
+
+    #[test]
+    fn memtable_with_wal_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn memtable_with_wal_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        test_transaction_concurrently(store);
+    }
+
+    #[test]
+    fn memtable_with_wal_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        test_tables(store);
+    }
+
+    #[test]
+    fn memtable_should_revert_writes_it_cannot_log() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.wal.as_ref().unwrap().lock().fail_appends();
+
+        assert!(store.set("t1", "k1", "v2".into()).is_err());
+        assert!(store.set("t1", "k2", "v2".into()).is_err());
+        assert!(store.incr("t1", "k3", 1).is_err());
+        let res = store.transaction(|txn| {
+            txn.del("t1", "k1")?;
+            txn.set("t2", "k1", "v1".into())
+        });
+        assert!(res.is_err());
+        assert!(store.drop_table("t1").is_err());
+        assert!(store.rename_table("t1", "t3").is_err());
+
+        let pairs = store.get_all("t1").unwrap();
+        assert_eq!(pairs, vec![Kvpair::new("k1", "v1".into())]);
+        assert_eq!(store.get("t2", "k1"), Ok(None));
+        assert_eq!(store.get("t3", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn memtable_should_recover_from_wal() {
+        let dir = tempdir().unwrap();
+        let config = WalConfig::new(SyncPolicy::Always, Duration::from_secs(300));
+        {
+            let store = MemTable::with_wal(&dir, config);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.del("t1", "k2").unwrap();
+            store
+                .set_ex("t1", "k3", "v3".into(), Duration::from_secs(60))
+                .unwrap();
+            store.incr("t1", "counter", 2).unwrap();
+            store.incr("t1", "counter", 3).unwrap();
+            store
+                .compare_and_swap("t1", "k1", Some("v1".into()), Some("v4".into()))
+                .unwrap()
+                .unwrap();
+            store.set("t2", "k1", "v1".into()).unwrap();
+            store.drop_table("t2").unwrap();
+            store.set("t3", "k1", "v1".into()).unwrap();
+            store.rename_table("t3", "t4").unwrap();
+            store
+                .transaction(|txn| {
+                    txn.set("t4", "k2", "v2".into())?;
+                    txn.del("t4", "k1")
+                })
+                .unwrap();
+        }
+
+        let store = MemTable::with_wal(&dir, config);
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into(), "t4".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v4".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert!(store.ttl("t1", "k3").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.get("t1", "counter"), Ok(Some(5.into())));
+        assert_eq!(
+            store.get_all("t4"),
+            Ok(vec![Kvpair::new("k2", "v2".into())])
+        );
+    }
+
+    #[test]
+    fn memtable_should_recover_from_snapshot_and_wal() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::with_wal(&dir, WalConfig::default());
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.snapshot().unwrap();
+            assert_eq!(segments(dir.path()), 1);
+            store.del("t1", "k1").unwrap();
+            store.set("t1", "k3", "v3".into()).unwrap();
+        }
+
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(Some("v3".into())));
+    }
+
+    #[test]
+    fn memtable_should_snapshot_periodically() {
+        let dir = tempdir().unwrap();
+        let config = WalConfig::new(SyncPolicy::Never, Duration::ZERO);
+        {
+            let store = MemTable::with_wal(&dir, config);
+            for i in 0..10 {
+                store.set("t1", &format!("k{}", i), i.into()).unwrap();
+            }
+            // every write started a snapshot, the last one deletes every segment before it
+            let start = std::time::Instant::now();
+            while segments(dir.path()) > 1 {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "no snapshot written"
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let store = MemTable::with_wal(&dir, config);
+        assert_eq!(store.len("t1"), Ok(10));
+        assert_eq!(store.get("t1", "k9"), Ok(Some(9.into())));
+    }
+
+    #[test]
+    fn memtable_clone_should_not_be_logged() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::with_wal(&dir, WalConfig::default());
+            store.set("t1", "k1", "v1".into()).unwrap();
+            let copy = store.clone();
+            copy.set("t1", "k2", "v2".into()).unwrap();
+        }
+
+        let store = MemTable::with_wal(&dir, WalConfig::default());
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn memtable_with_budget_basic_interface_should_work() {
+        let store = MemTable::new().with_budget(MemoryBudget::new(1 << 20, EvictionPolicy::Lru));
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn memtable_with_budget_should_evict_least_recently_used() {
+        // "kN" with the value "vN" takes 2 + 4 bytes
+        let store = MemTable::new().with_budget(MemoryBudget::new(18, EvictionPolicy::Lru));
+        for i in 1..=3 {
+            store
+                .set("t1", &format!("k{}", i), format!("v{}", i).as_str().into())
+                .unwrap();
+        }
+        store.get("t1", "k1").unwrap();
+        store.set("t1", "k4", "v4".into()).unwrap();
+
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        for key in ["k1", "k3", "k4"] {
+            assert!(store.contains("t1", key).unwrap());
+        }
+        assert_eq!(
+            store.eviction_stats(),
+            Some(EvictionStats {
+                used_bytes: 18,
+                max_bytes: 18,
+                evicted_keys: 1,
+                evicted_bytes: 6,
+            })
+        );
+    }
+
+    #[test]
+    fn memtable_with_budget_should_evict_least_frequently_used() {
+        let store = MemTable::new().with_budget(MemoryBudget::new(18, EvictionPolicy::Lfu));
+        for i in 1..=3 {
+            store
+                .set("t1", &format!("k{}", i), format!("v{}", i).as_str().into())
+                .unwrap();
+        }
+        for _ in 0..2 {
+            store.get("t1", "k1").unwrap();
+            store.get("t1", "k2").unwrap();
+        }
+        store.set("t1", "k4", "v4".into()).unwrap();
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+
+        // the new key is the least frequently used one once another key is written
+        store.set("t1", "k5", "v5".into()).unwrap();
+        assert_eq!(store.get("t1", "k4"), Ok(None));
+        for key in ["k1", "k2", "k5"] {
+            assert!(store.contains("t1", key).unwrap());
+        }
+        assert_eq!(store.eviction_stats().unwrap().evicted_keys, 2);
+    }
+
+    #[test]
+    fn memtable_with_budget_should_track_used_bytes() {
+        let store = MemTable::new().with_budget(MemoryBudget::new(1 << 20, EvictionPolicy::Lfu));
+        let used = || store.eviction_stats().unwrap().used_bytes;
+        store.set("t1", "k1", "v1".into()).unwrap();
+        assert_eq!(used(), 6);
+        store.set("t1", "k1", "value1".into()).unwrap();
+        assert_eq!(used(), 10);
+        store.incr("t1", "k2", 1).unwrap();
+        store.del("t1", "k1").unwrap();
+        store.rename_table("t1", "t2").unwrap();
+        store.set("t3", "k1", "v1".into()).unwrap();
+        store.drop_table("t3").unwrap();
+        store.del("t2", "k2").unwrap();
+        assert_eq!(used(), 0);
+        assert_eq!(store.eviction_stats().unwrap().evicted_keys, 0);
+    }
+
+    #[test]
+    fn memtable_with_budget_should_evict_existing_keys() {
+        let store = MemTable::new();
+        for i in 1..=4 {
+            store
+                .set("t1", &format!("k{}", i), format!("v{}", i).as_str().into())
+                .unwrap();
+        }
+        let store = store.with_budget(MemoryBudget::new(12, EvictionPolicy::Lru));
+        assert_eq!(store.len("t1"), Ok(2));
+        assert_eq!(store.eviction_stats().unwrap().evicted_keys, 2);
+    }
+
+    #[test]
+    fn memtable_should_refuse_corrupt_wal() {
+        let dir = tempdir().unwrap();
+        {
+            let store = MemTable::with_wal(&dir, WalConfig::default());
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+        }
+        // flip the last byte of the first record, only a torn tail may be dropped
+        let path = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "wal"))
+            .unwrap();
+        let mut data = fs::read(&path).unwrap();
+        let first = decode_record(&data, 0).unwrap().1;
+        data[first - 1] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let res = MemTable::try_with_wal(&dir, WalConfig::default());
+        assert!(matches!(res, Err(KvError::Internal(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "persistent memtable")]
+    fn memtable_with_wal_should_refuse_budget() {
+        let dir = tempdir().unwrap();
+        let _ = MemTable::with_wal(&dir, WalConfig::default())
+            .with_budget(MemoryBudget::new(12, EvictionPolicy::Lru));
+    }
 }
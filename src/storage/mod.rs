@@ -1,13 +1,39 @@
+pub mod bitcask;
+mod blocking;
+mod eviction;
+mod lock_set;
 pub mod memory;
+mod record;
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb;
 pub mod sleddb;
+pub mod tiered;
+mod wal;
+
+pub use blocking::BlockingStorage;
+pub use eviction::{EvictionPolicy, EvictionStats, MemoryBudget};
+pub use tiered::{TieredStorage, WriteMode};
+pub use wal::{SyncPolicy, WalConfig};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::{KvError, Kvpair, Value};
 
-pub trait Storage {
+pub trait Storage: Send + Sync + 'static {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
 
+    /// set the value, the key no longer expires
     fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError>;
 
+    /// set the value, the key expires after ttl
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError>;
+
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
@@ -15,6 +41,149 @@ pub trait Storage {
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
 
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+
+    /// the pairs with start <= key < end in key order, an empty end is unbounded,
+    /// reverse returns them from the last key, a limit of 0 returns all of them
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError>;
+
+    /// the pairs whose key starts with prefix in key order
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError>;
+
+    /// let an existing key expire after ttl, returns false if the key does not exist
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError>;
+
+    /// remaining time to live of the key, `None` if it never expires,
+    /// `KvError::NotFound` if the key does not exist
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError>;
+
+    /// remove the time to live of the key, returns false if it had none
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError>;
+
+    /// remove every expired key, returns how many were removed
+    fn purge_expired(&self) -> Result<usize, KvError>;
+
+    /// atomically add delta to the integer value of the key, a missing key starts from 0,
+    /// returns the new value
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError>;
+
+    /// atomically add delta to the float value of the key, a missing key starts from 0,
+    /// an integer value is promoted to float, returns the new value
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError>;
+
+    /// atomically replace the value of the key with new if the current value equals expected,
+    /// `None` as expected means the key must not exist, `None` as new deletes the key.
+    /// A successful swap removes the time to live of the key.
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError>;
+
+    /// names of all tables in order
+    fn list_tables(&self) -> Result<Vec<String>, KvError>;
+
+    /// remove the table with all of its keys, returns false if it does not exist
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+
+    /// rename the table from to to, `KvError::TableNotFound` if from does not exist,
+    /// `KvError::TableExists` if to already exists
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError>;
+
+    /// how many live keys the table has
+    fn len(&self, table: &str) -> Result<usize, KvError>;
+
+    /// run f atomically, either all of its writes are applied or, if it fails, none is.
+    /// f may be run more than once when it conflicts with a concurrent writer,
+    /// so it should have no side effects other than on the transaction.
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>;
+}
+
+/// the view of the storage inside a transaction, writes are only visible to
+/// other clients once the transaction commits. Writes remove the time to live of the key.
+pub trait Transaction {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError>;
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+}
+
+/// a compare and swap failed because the current value differs from the expected one
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareAndSwapError {
+    /// the current value, `None` if the key does not exist
+    pub current: Option<Value>,
+}
+
+/// add delta to an integer value
+pub(crate) fn incr_value(old: Option<&Value>, delta: i64) -> Result<i64, KvError> {
+    let old = match old {
+        Some(v) => i64::try_from(v)?,
+        None => 0,
+    };
+    old.checked_add(delta)
+        .ok_or_else(|| KvError::InvalidCommand("increment would overflow".into()))
+}
+
+/// add delta to a float value, integers are promoted to float
+pub(crate) fn incr_float_value(old: Option<&Value>, delta: f64) -> Result<f64, KvError> {
+    let old = match old {
+        Some(v) => match i64::try_from(v) {
+            Ok(i) => i as f64,
+            Err(_) => f64::try_from(v)?,
+        },
+        None => 0.0,
+    };
+    let new = old + delta;
+    if !new.is_finite() {
+        return Err(KvError::InvalidCommand(
+            "increment would produce NaN or Infinity".into(),
+        ));
+    }
+    Ok(new)
+}
+
+/// milliseconds since the unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// the unix timestamp in milliseconds at which a key with the ttl expires
+fn expire_at(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// the key of the expiration of (table, key) for backends keeping all expirations together,
+/// the table is length delimited from the key, so no pair of table and key collides with another
+pub(crate) fn expiration_key(table: &str, key: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + table.len() + key.len());
+    buf.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    buf.extend_from_slice(table.as_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf
+}
+
+pub(crate) fn split_expiration_key(buf: &[u8]) -> Option<(&str, &str)> {
+    let len = u32::from_be_bytes(buf.get(..4)?.try_into().ok()?) as usize;
+    let table = std::str::from_utf8(buf.get(4..4 + len)?).ok()?;
+    let key = std::str::from_utf8(&buf[4 + len..]).ok()?;
+    Some((table, key))
 }
 
 #[cfg(test)]
@@ -59,3 +228,371 @@ fn test_get_all(store: impl Storage) {
         ]
     )
 }
+
+#[cfg(test)]
+fn test_ttl(store: impl Storage) {
+    use std::thread::sleep;
+
+    let ttl = Duration::from_millis(100);
+    assert_eq!(store.set_ex("t3", "k1", "v1".into(), ttl), Ok(None));
+    assert_eq!(store.get("t3", "k1"), Ok(Some("v1".into())));
+    let remaining = store.ttl("t3", "k1").unwrap().unwrap();
+    assert!(remaining <= ttl && remaining > Duration::ZERO);
+
+    // a key without ttl never expires
+    store.set("t3", "k2", "v2".into()).unwrap();
+    assert_eq!(store.ttl("t3", "k2"), Ok(None));
+    assert_eq!(store.expire("t3", "k2", ttl), Ok(true));
+    assert_eq!(store.persist("t3", "k2"), Ok(true));
+    assert_eq!(store.persist("t3", "k2"), Ok(false));
+
+    // set removes the ttl
+    store.set_ex("t3", "k3", "v3".into(), ttl).unwrap();
+    assert_eq!(store.set("t3", "k3", "v33".into()), Ok(Some("v3".into())));
+    assert_eq!(store.ttl("t3", "k3"), Ok(None));
+
+    assert_eq!(store.expire("t3", "unknown", ttl), Ok(false));
+    assert!(matches!(
+        store.ttl("t3", "unknown"),
+        Err(KvError::NotFound(_, _))
+    ));
+
+    sleep(ttl * 2);
+    assert_eq!(store.get("t3", "k1"), Ok(None));
+    assert_eq!(store.contains("t3", "k1"), Ok(false));
+    assert_eq!(store.del("t3", "k1"), Ok(None));
+    assert!(matches!(
+        store.ttl("t3", "k1"),
+        Err(KvError::NotFound(_, _))
+    ));
+    assert_eq!(store.get("t3", "k2"), Ok(Some("v2".into())));
+
+    // an expired value is not returned when it is overwritten
+    store.set_ex("t3", "k4", "v4".into(), ttl).unwrap();
+    sleep(ttl * 2);
+    assert_eq!(store.set("t3", "k4", "v44".into()), Ok(None));
+}
+
+#[cfg(test)]
+fn test_purge_expired(store: impl Storage) {
+    use std::thread::sleep;
+
+    let ttl = Duration::from_millis(50);
+    store.set_ex("t4", "k1", "v1".into(), ttl).unwrap();
+    store.set_ex("t4", "k2", "v2".into(), ttl).unwrap();
+    store
+        .set_ex("t4", "k3", "v3".into(), Duration::from_secs(60))
+        .unwrap();
+    store.set("t4", "k4", "v4".into()).unwrap();
+
+    assert_eq!(store.purge_expired(), Ok(0));
+    sleep(ttl * 2);
+    assert_eq!(store.get_all("t4").unwrap().len(), 2);
+    assert_eq!(store.purge_expired(), Ok(2));
+
+    let mut data = store.get_all("t4").unwrap();
+    data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        data,
+        vec![
+            Kvpair::new("k3", "v3".into()),
+            Kvpair::new("k4", "v4".into())
+        ]
+    );
+}
+
+#[cfg(test)]
+fn test_incr(store: impl Storage) {
+    assert_eq!(store.incr("t5", "k1", 10), Ok(10));
+    assert_eq!(store.incr("t5", "k1", -3), Ok(7));
+    assert_eq!(store.get("t5", "k1"), Ok(Some(7.into())));
+
+    assert_eq!(store.incr_float("t5", "k2", 1.5), Ok(1.5));
+    assert_eq!(store.incr_float("t5", "k2", 0.25), Ok(1.75));
+    // an integer is promoted to float, but a float is never truncated to integer
+    assert_eq!(store.incr_float("t5", "k1", 0.5), Ok(7.5));
+    assert!(matches!(
+        store.incr("t5", "k1", 1),
+        Err(KvError::ConvertError(_, "Integer"))
+    ));
+
+    store.set("t5", "k3", "v3".into()).unwrap();
+    assert!(matches!(
+        store.incr("t5", "k3", 1),
+        Err(KvError::ConvertError(_, _))
+    ));
+    assert!(matches!(
+        store.incr_float("t5", "k3", 1.0),
+        Err(KvError::ConvertError(_, _))
+    ));
+    assert_eq!(store.get("t5", "k3"), Ok(Some("v3".into())));
+
+    store.set("t5", "k4", i64::MAX.into()).unwrap();
+    assert!(matches!(
+        store.incr("t5", "k4", 1),
+        Err(KvError::InvalidCommand(_))
+    ));
+    assert_eq!(store.get("t5", "k4"), Ok(Some(i64::MAX.into())));
+}
+
+#[cfg(test)]
+fn test_incr_concurrently(store: impl Storage) {
+    use std::{sync::Arc, thread};
+
+    let store = Arc::new(store);
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    store.incr("t6", "counter", 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get("t6", "counter"), Ok(Some(800.into())));
+}
+
+#[cfg(test)]
+fn test_incr_keeps_ttl(store: impl Storage) {
+    use std::thread::sleep;
+
+    let ttl = Duration::from_millis(50);
+    store.set_ex("t7", "k1", 1.into(), ttl).unwrap();
+    assert_eq!(store.incr("t7", "k1", 1), Ok(2));
+    assert!(store.ttl("t7", "k1").unwrap().is_some());
+
+    // an expired counter starts over from 0
+    sleep(ttl * 2);
+    assert_eq!(store.incr("t7", "k1", 1), Ok(1));
+    assert_eq!(store.ttl("t7", "k1"), Ok(None));
+}
+
+#[cfg(test)]
+fn test_compare_and_swap(store: impl Storage) {
+    let v1: Value = "v1".into();
+    let v2: Value = "v2".into();
+
+    // set if absent
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", None, Some(v1.clone())),
+        Ok(Ok(()))
+    );
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", None, Some(v2.clone())),
+        Ok(Err(CompareAndSwapError {
+            current: Some(v1.clone())
+        }))
+    );
+
+    // swap
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", Some(v2.clone()), Some(v1.clone())),
+        Ok(Err(CompareAndSwapError {
+            current: Some(v1.clone())
+        }))
+    );
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", Some(v1.clone()), Some(v2.clone())),
+        Ok(Ok(()))
+    );
+    assert_eq!(store.get("t8", "k1"), Ok(Some(v2.clone())));
+
+    // delete
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", Some(v2), None),
+        Ok(Ok(()))
+    );
+    assert_eq!(store.contains("t8", "k1"), Ok(false));
+    assert_eq!(
+        store.compare_and_swap("t8", "k1", Some(v1), None),
+        Ok(Err(CompareAndSwapError { current: None }))
+    );
+}
+
+#[cfg(test)]
+fn test_compare_and_swap_expired(store: impl Storage) {
+    use std::thread::sleep;
+
+    let ttl = Duration::from_millis(50);
+    store.set_ex("t9", "lease", "node1".into(), ttl).unwrap();
+    assert_eq!(
+        store.compare_and_swap("t9", "lease", None, Some("node2".into())),
+        Ok(Err(CompareAndSwapError {
+            current: Some("node1".into())
+        }))
+    );
+
+    // an expired key is taken over as if it did not exist
+    sleep(ttl * 2);
+    assert_eq!(
+        store.compare_and_swap("t9", "lease", None, Some("node2".into())),
+        Ok(Ok(()))
+    );
+    assert_eq!(store.ttl("t9", "lease"), Ok(None));
+
+    // a swap drops the time to live of the old value
+    assert_eq!(store.expire("t9", "lease", ttl * 100), Ok(true));
+    assert_eq!(
+        store.compare_and_swap("t9", "lease", Some("node2".into()), Some("node3".into())),
+        Ok(Ok(()))
+    );
+    assert_eq!(store.ttl("t9", "lease"), Ok(None));
+}
+
+#[cfg(test)]
+fn test_transaction(store: impl Storage) {
+    store.set("t10", "k1", 1.into()).unwrap();
+
+    let res = store.transaction(|txn| {
+        let old = txn.set("t10", "k1", 2.into())?;
+        txn.set("t10", "k2", 3.into())?;
+        // writes are visible inside the transaction
+        assert_eq!(txn.get("t10", "k1")?, Some(2.into()));
+        assert!(txn.contains("t10", "k2")?);
+        txn.del("t10", "k2")?;
+        Ok(old)
+    });
+    assert_eq!(res, Ok(Some(1.into())));
+    assert_eq!(store.get("t10", "k1"), Ok(Some(2.into())));
+    assert_eq!(store.contains("t10", "k2"), Ok(false));
+
+    // a failed transaction leaves no writes behind
+    let res: Result<(), _> = store.transaction(|txn| {
+        txn.set("t10", "k1", 10.into())?;
+        txn.set("t10", "k3", 30.into())?;
+        Err(KvError::InvalidCommand("abort".into()))
+    });
+    assert_eq!(res, Err(KvError::InvalidCommand("abort".into())));
+    assert_eq!(store.get("t10", "k1"), Ok(Some(2.into())));
+    assert_eq!(store.get("t10", "k3"), Ok(None));
+}
+
+#[cfg(test)]
+fn test_transaction_concurrently(store: impl Storage) {
+    use std::{sync::Arc, thread};
+
+    // move one unit from k1 to k2 at a time, the sum never changes
+    let store = Arc::new(store);
+    store.set("t11", "k1", 800.into()).unwrap();
+    store.set("t11", "k2", 0.into()).unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    store
+                        .transaction(|txn| {
+                            let k1: i64 = (&txn.get("t11", "k1")?.unwrap()).try_into()?;
+                            let k2: i64 = (&txn.get("t11", "k2")?.unwrap()).try_into()?;
+                            txn.set("t11", "k1", (k1 - 1).into())?;
+                            txn.set("t11", "k2", (k2 + 1).into())?;
+                            Ok(())
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get("t11", "k1"), Ok(Some(0.into())));
+    assert_eq!(store.get("t11", "k2"), Ok(Some(800.into())));
+}
+
+#[cfg(test)]
+fn test_range(store: impl Storage) {
+    for i in [3, 1, 4, 5, 9, 2, 6] {
+        store.set("t12", &format!("k{}", i), i.into()).unwrap();
+    }
+    // another table whose name shares the prefix
+    store.set("t120", "k0", 0.into()).unwrap();
+    let keys = |pairs: Vec<Kvpair>| -> Vec<String> { pairs.into_iter().map(|p| p.key).collect() };
+
+    let res = store.range("t12", "k2", "k6", false, 0).unwrap();
+    assert_eq!(keys(res), ["k2", "k3", "k4", "k5"]);
+    let res = store.range("t12", "k2", "k6", true, 0).unwrap();
+    assert_eq!(keys(res), ["k5", "k4", "k3", "k2"]);
+    let res = store.range("t12", "k2", "", false, 3).unwrap();
+    assert_eq!(keys(res), ["k2", "k3", "k4"]);
+    let res = store.range("t12", "", "", true, 2).unwrap();
+    assert_eq!(keys(res), ["k9", "k6"]);
+    assert_eq!(
+        store.range("t12", "k3", "k4", false, 0),
+        Ok(vec![Kvpair::new("k3", 3.into())])
+    );
+    assert_eq!(store.range("t12", "k7", "k8", false, 0), Ok(vec![]));
+
+    // deleted and expired keys are skipped
+    store.del("t12", "k3").unwrap();
+    store
+        .set_ex("t12", "k4", 4.into(), Duration::from_millis(20))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let res = store.range("t12", "k2", "k6", false, 0).unwrap();
+    assert_eq!(keys(res), ["k2", "k5"]);
+}
+
+#[cfg(test)]
+fn test_prefix(store: impl Storage) {
+    for key in ["2024-01-02", "2024-01-01", "2024-02-01", "2023-12-31"] {
+        store.set("t13", key, key.into()).unwrap();
+    }
+
+    let res = store.prefix("t13", "2024-01").unwrap();
+    assert_eq!(
+        res,
+        vec![
+            Kvpair::new("2024-01-01", "2024-01-01".into()),
+            Kvpair::new("2024-01-02", "2024-01-02".into()),
+        ]
+    );
+    assert_eq!(store.prefix("t13", "2024").unwrap().len(), 3);
+    assert_eq!(store.prefix("t13", "").unwrap().len(), 4);
+    assert_eq!(store.prefix("t13", "2025"), Ok(vec![]));
+}
+
+#[cfg(test)]
+fn test_tables(store: impl Storage) {
+    store.set("t14", "k1", 1.into()).unwrap();
+    store.set("t14", "k2", 2.into()).unwrap();
+    store
+        .set_ex("t14", "k3", 3.into(), Duration::from_millis(20))
+        .unwrap();
+    store.set("t15", "k1", 1.into()).unwrap();
+    assert_eq!(store.list_tables(), Ok(vec!["t14".into(), "t15".into()]));
+    assert_eq!(store.len("t14"), Ok(3));
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(store.len("t14"), Ok(2));
+    assert_eq!(store.len("t16"), Ok(0));
+
+    // reads of a missing table do not create it
+    assert_eq!(store.get("t16", "k1"), Ok(None));
+    assert_eq!(store.get_all("t16"), Ok(vec![]));
+    assert_eq!(store.list_tables().unwrap().len(), 2);
+
+    assert_eq!(
+        store.rename_table("t14", "t15"),
+        Err(KvError::TableExists("t15".into()))
+    );
+    assert_eq!(
+        store.rename_table("t16", "t17"),
+        Err(KvError::TableNotFound("t16".into()))
+    );
+    store.rename_table("t14", "t16").unwrap();
+    assert_eq!(store.list_tables(), Ok(vec!["t15".into(), "t16".into()]));
+    assert_eq!(store.get("t14", "k1"), Ok(None));
+    assert_eq!(store.get("t16", "k1"), Ok(Some(1.into())));
+    assert_eq!(store.len("t16"), Ok(2));
+
+    assert_eq!(store.drop_table("t16"), Ok(true));
+    assert_eq!(store.drop_table("t16"), Ok(false));
+    assert_eq!(store.list_tables(), Ok(vec!["t15".into()]));
+    assert_eq!(store.get("t16", "k1"), Ok(None));
+    assert_eq!(store.len("t16"), Ok(0));
+}
@@ -0,0 +1,144 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Read,
+    path::Path,
+};
+
+use flate2::Crc;
+use tracing::warn;
+
+use crate::KvError;
+
+/// crc32 and length of the payload in front of every record
+pub(crate) const RECORD_HEADER_LEN: usize = 4 + 4;
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// frame the payload as a record, `crc32 | payload length | payload`
+pub(crate) fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&crc32(payload).to_be_bytes());
+    record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// the payload of the record at offset and the offset of the next record,
+/// `None` if the record is incomplete or does not match its crc
+pub(crate) fn decode_record(data: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let mut header = Cursor::new(data.get(offset..offset + RECORD_HEADER_LEN)?);
+    let (crc, len) = (header.u32()?, header.u32()? as usize);
+    let start = offset + RECORD_HEADER_LEN;
+    let payload = data.get(start..start + len)?;
+    (crc32(payload) == crc).then_some((payload, start + len))
+}
+
+/// decode every record of the log file with f, which gets the payload and its offset in the
+/// file. If torn_tail is true the last record may be torn or corrupt, the remains of a crash in
+/// the middle of a write, and it is truncated. Any other corrupt record is an error, since
+/// truncating there would silently drop the records after it.
+pub(crate) fn read_log<T>(
+    path: &Path,
+    torn_tail: bool,
+    mut f: impl FnMut(&[u8], usize) -> Option<T>,
+) -> Result<Vec<T>, KvError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if let Some((payload, next)) = decode_record(&data, offset) {
+            match f(payload, offset + RECORD_HEADER_LEN) {
+                Some(record) => {
+                    records.push(record);
+                    offset = next;
+                    continue;
+                }
+                None => {
+                    return Err(KvError::Internal(format!(
+                        "malformed record in {:?} at {}",
+                        path, offset
+                    )))
+                }
+            }
+        }
+
+        if !torn_tail || !is_last_record(&data, offset) {
+            return Err(KvError::Internal(format!(
+                "corrupt record in {:?} at {} of {} bytes",
+                path,
+                offset,
+                data.len()
+            )));
+        }
+        warn!(
+            "truncating {:?} from {} of {} bytes, its tail is torn",
+            path,
+            offset,
+            data.len()
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(offset as u64)?;
+        break;
+    }
+    Ok(records)
+}
+
+/// whether the record at offset reaches to the end of the data, going by its length
+fn is_last_record(data: &[u8], offset: usize) -> bool {
+    let len = match data.get(offset + 4..offset + RECORD_HEADER_LEN) {
+        Some(len) => u32::from_be_bytes(len.try_into().unwrap()) as usize,
+        None => return true,
+    };
+    offset + RECORD_HEADER_LEN + len >= data.len()
+}
+
+/// reads big endian numbers and strings from a buffer, `None` once it runs out
+pub(crate) struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// how many bytes were read
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn string(&mut self, n: usize) -> Option<String> {
+        String::from_utf8(self.take(n)?.to_vec()).ok()
+    }
+}
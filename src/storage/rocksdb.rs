@@ -0,0 +1,799 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use ::rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, Options,
+    ReadOptions, WriteBatch,
+};
+
+use crate::{
+    storage::{
+        expiration_key, expire_at, incr_float_value, incr_value,
+        lock_set::{run_transaction, LockSet, LockingStore, TxnWrites},
+        now_millis, split_expiration_key,
+    },
+    CompareAndSwapError, KvError, Kvpair, Storage, Transaction, Value,
+};
+
+type Db = DBWithThreadMode<MultiThreaded>;
+
+/// name of the column family holding the unix timestamp in milliseconds at which a key expires,
+/// keyed by `expiration_key(table, key)`
+const EXPIRATIONS_CF: &str = "__expirations__";
+
+/// name of the column family holding the names of the tables being dropped,
+/// a drop interrupted by a crash is finished when the db is opened again
+const DROPPED_CF: &str = "__dropped__";
+
+/// number of writes after which renaming a table writes its batch,
+/// so a large table is not copied in one batch held in memory
+const RENAME_BATCH: usize = 1024;
+
+/// every table is stored in its own column family, named after the table with this prefix
+const TABLE_CF_PREFIX: &str = "table:";
+
+#[derive(Debug)]
+pub struct RocksDb {
+    db: Db,
+    /// names of the existing tables, the lock also serialises creating and dropping
+    /// their column families
+    tables: RwLock<BTreeSet<String>>,
+    /// single key operations lock their key, transactions lock every key they touch
+    locks: LockSet,
+}
+
+impl RocksDb {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::try_new(path).unwrap()
+    }
+
+    /// open the db, it fails if it cannot be read or is opened by another RocksDb
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        // rocksdb has to open every existing column family, a new db has none
+        let mut cfs = Db::list_cf(&opts, path).unwrap_or_default();
+        for name in [EXPIRATIONS_CF, DROPPED_CF] {
+            if !cfs.iter().any(|cf| cf == name) {
+                cfs.push(name.into());
+            }
+        }
+        let db = Db::open_cf(&opts, path, &cfs)?;
+        let tables = cfs
+            .iter()
+            .filter_map(|cf| cf.strip_prefix(TABLE_CF_PREFIX))
+            .map(String::from)
+            .collect();
+
+        let store = Self {
+            db,
+            tables: RwLock::new(tables),
+            locks: LockSet::default(),
+        };
+        store.finish_drops()?;
+        Ok(store)
+    }
+
+    fn expirations(&self) -> Arc<BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(EXPIRATIONS_CF)
+            .expect("the expirations column family is created on open")
+    }
+
+    fn dropped(&self) -> Arc<BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(DROPPED_CF)
+            .expect("the dropped column family is created on open")
+    }
+
+    /// drop the tables whose drop was interrupted
+    fn finish_drops(&self) -> Result<(), KvError> {
+        // the marks are read upfront, so no iterator is held while dropping a column family
+        let mut marks = Vec::new();
+        for item in self.db.iterator_cf(&self.dropped(), IteratorMode::Start) {
+            marks.push(item?.0);
+        }
+
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        for name in marks {
+            match std::str::from_utf8(&name) {
+                Ok(table) => self.finish_drop(&mut tables, table)?,
+                Err(_) => self.db.delete_cf(&self.dropped(), &name)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// drop the column family and the expirations of a table marked as dropped,
+    /// then the mark itself. The caller holds the write lock of the tables.
+    fn finish_drop(&self, tables: &mut BTreeSet<String>, table: &str) -> Result<(), KvError> {
+        tables.remove(table);
+        // rocksdb drops the whole column family and the range of expirations at once
+        let cf_name = table_cf_name(table);
+        if self.db.cf_handle(&cf_name).is_some() {
+            self.db.drop_cf(&cf_name)?;
+        }
+        let prefix = expiration_key(table, "");
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(&self.expirations(), prefix.clone(), prefix_end(&prefix));
+        batch.delete_cf(&self.dropped(), table);
+        Ok(self.db.write(batch)?)
+    }
+
+    /// the column family of the table if it exists, reads never create a table
+    fn get_table(&self, name: &str) -> Option<Arc<BoundColumnFamily<'_>>> {
+        self.db.cf_handle(&table_cf_name(name))
+    }
+
+    /// the column family of the table, it is created if it does not exist
+    fn table(&self, name: &str) -> Result<Arc<BoundColumnFamily<'_>>, KvError> {
+        if let Some(cf) = self.get_table(name) {
+            return Ok(cf);
+        }
+
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        let cf_name = table_cf_name(name);
+        if self.db.cf_handle(&cf_name).is_none() {
+            self.db.create_cf(&cf_name, &Options::default())?;
+            tables.insert(name.to_string());
+        }
+        self.db
+            .cf_handle(&cf_name)
+            .ok_or_else(|| KvError::TableNotFound(name.into()))
+    }
+
+    fn expire_at_of(&self, table: &str, key: &str) -> Result<Option<u64>, KvError> {
+        let expire_at = self
+            .db
+            .get_cf(&self.expirations(), expiration_key(table, key))?;
+        Ok(expire_at.map(|t| bytes_to_millis(&t)))
+    }
+
+    /// the live value of the key, the caller holds the lock of the key
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let cf = match self.get_table(table) {
+            Some(cf) => cf,
+            None => return Ok(None),
+        };
+        let value = match self.db.get_cf(&cf, key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if is_expired(self.expire_at_of(table, key)?, now_millis()) {
+            return Ok(None);
+        }
+        Ok(Some(value.as_slice().try_into()?))
+    }
+
+    /// add writing the value of the key with its expiration to the batch
+    fn put(
+        &self,
+        batch: &mut WriteBatch,
+        table: &str,
+        key: &str,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Result<(), KvError> {
+        let data: Vec<u8> = value.try_into()?;
+        batch.put_cf(&self.table(table)?, key, data);
+        let exp_key = expiration_key(table, key);
+        match expire_at {
+            Some(t) => batch.put_cf(&self.expirations(), exp_key, t.to_be_bytes()),
+            None => batch.delete_cf(&self.expirations(), exp_key),
+        }
+        Ok(())
+    }
+
+    /// add removing the key with its expiration to the batch
+    fn delete(&self, batch: &mut WriteBatch, table: &str, key: &str) {
+        if let Some(cf) = self.get_table(table) {
+            batch.delete_cf(&cf, key);
+        }
+        batch.delete_cf(&self.expirations(), expiration_key(table, key));
+    }
+
+    fn insert(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let _guard = self.locks.write(table, key);
+        let old = self.lookup(table, key)?;
+        let mut batch = WriteBatch::default();
+        self.put(&mut batch, table, key, value, expire_at)?;
+        self.db.write(batch)?;
+        Ok(old)
+    }
+
+    /// replace the value of the key with f(old value) while holding the lock of the key,
+    /// the key keeps its time to live
+    fn update<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError>
+    where
+        T: Into<Value> + Copy,
+    {
+        let _guard = self.locks.write(table, key);
+        let old = self.lookup(table, key)?;
+        let new = f(old.as_ref())?;
+        let data: Vec<u8> = new.into().try_into()?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&self.table(table)?, key, data);
+        // the expiration of an expired key must not apply to the new value
+        if old.is_none() {
+            batch.delete_cf(&self.expirations(), expiration_key(table, key));
+        }
+        self.db.write(batch)?;
+        Ok(new)
+    }
+
+    /// write the batch once it holds RENAME_BATCH writes and start a new one
+    fn write_full_batch(&self, batch: &mut WriteBatch) -> Result<(), KvError> {
+        if batch.len() >= RENAME_BATCH {
+            self.db.write(std::mem::take(batch))?;
+        }
+        Ok(())
+    }
+
+    /// copy the pairs and the expirations of a table into the new column family of another one.
+    /// The last batch moves the dropped mark from the new table to the old one.
+    fn copy_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        let (source, target) = (table_cf_name(from), table_cf_name(to));
+        self.db.create_cf(&target, &Options::default())?;
+        let (source, target) = match (self.db.cf_handle(&source), self.db.cf_handle(&target)) {
+            (Some(source), Some(target)) => (source, target),
+            _ => return Err(KvError::TableNotFound(from.into())),
+        };
+        let expirations = self.expirations();
+        let prefix = expiration_key(from, "");
+
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(&source, IteratorMode::Start) {
+            let (key, value) = item?;
+            batch.put_cf(&target, key, value);
+            self.write_full_batch(&mut batch)?;
+        }
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(prefix_end(&prefix));
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        for item in self.db.iterator_cf_opt(&expirations, opts, mode) {
+            let (exp_key, t) = item?;
+            if let Some((_, key)) = split_expiration_key(&exp_key) {
+                batch.put_cf(&expirations, expiration_key(to, key), t);
+            }
+            self.write_full_batch(&mut batch)?;
+        }
+
+        let dropped = self.dropped();
+        batch.delete_cf(&dropped, to);
+        batch.put_cf(&dropped, from, b"");
+        Ok(self.db.write(batch)?)
+    }
+
+    /// the live pairs of the table with lower <= key < upper from a consistent snapshot,
+    /// an empty bound is unbounded, a limit of 0 returns all of them
+    fn scan(
+        &self,
+        table: &str,
+        lower: &[u8],
+        upper: &[u8],
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let cf = match self.get_table(table) {
+            Some(cf) => cf,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut opts = ReadOptions::default();
+        if !lower.is_empty() {
+            opts.set_iterate_lower_bound(lower);
+        }
+        if !upper.is_empty() {
+            opts.set_iterate_upper_bound(upper);
+        }
+        let mode = match reverse {
+            true => IteratorMode::End,
+            false => IteratorMode::Start,
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+
+        let snapshot = self.db.snapshot();
+        let expirations = self.expirations();
+        let now = now_millis();
+        let mut pairs = Vec::new();
+        for item in snapshot.iterator_cf_opt(&cf, opts, mode) {
+            if pairs.len() == limit {
+                break;
+            }
+            let (key, value) = item?;
+            let key = String::from_utf8_lossy(&key);
+            let expire_at = snapshot.get_cf(&expirations, expiration_key(table, &key))?;
+            if is_expired(expire_at.map(|t| bytes_to_millis(&t)), now) {
+                continue;
+            }
+            pairs.push(Kvpair::new(key, value.as_ref().try_into()?));
+        }
+        Ok(pairs)
+    }
+}
+
+fn table_cf_name(table: &str) -> String {
+    format!("{}{}", TABLE_CF_PREFIX, table)
+}
+
+/// the smallest key greater than every key starting with prefix, empty if there is none
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            break;
+        }
+    }
+    end
+}
+
+fn is_expired(expire_at: Option<u64>, now: u64) -> bool {
+    matches!(expire_at, Some(t) if t <= now)
+}
+
+fn bytes_to_millis(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+
+impl Storage for RocksDb {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.locks.read(table, key);
+        self.lookup(table, key)
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        self.insert(table, key, value, None)
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.insert(table, key, value, Some(expire_at(ttl)))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get(table, key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _guard = self.locks.write(table, key);
+        if self.get_table(table).is_none() {
+            return Ok(None);
+        }
+        let old = self.lookup(table, key)?;
+        let mut batch = WriteBatch::default();
+        self.delete(&mut batch, table, key);
+        self.db.write(batch)?;
+        Ok(old)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.scan(table, b"", b"", false, 0)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        // rocksdb iterators borrow the db, so the pairs are read upfront
+        let pairs = self.scan(table, b"", b"", false, 0)?;
+        Ok(Box::new(pairs.into_iter()))
+    }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        if !end.is_empty() && end <= start {
+            return Ok(Vec::new());
+        }
+        self.scan(table, start.as_bytes(), end.as_bytes(), reverse, limit)
+    }
+
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError> {
+        let end = prefix_end(prefix.as_bytes());
+        self.scan(table, prefix.as_bytes(), &end, false, 0)
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let _guard = self.locks.write(table, key);
+        if self.lookup(table, key)?.is_none() {
+            return Ok(false);
+        }
+        let exp_key = expiration_key(table, key);
+        self.db
+            .put_cf(&self.expirations(), exp_key, expire_at(ttl).to_be_bytes())?;
+        Ok(true)
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _guard = self.locks.read(table, key);
+        if self.lookup(table, key)?.is_none() {
+            return Err(KvError::NotFound(table.into(), key.into()));
+        }
+
+        let now = now_millis();
+        Ok(self
+            .expire_at_of(table, key)?
+            .map(|t| Duration::from_millis(t.saturating_sub(now))))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _guard = self.locks.write(table, key);
+        if self.lookup(table, key)?.is_none() {
+            return Ok(false);
+        }
+        let had_ttl = self.expire_at_of(table, key)?.is_some();
+        self.db
+            .delete_cf(&self.expirations(), expiration_key(table, key))?;
+        Ok(had_ttl)
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        // collect the expired keys first, so no iterator is held while waiting for a key lock
+        let now = now_millis();
+        let mut expired = Vec::new();
+        for item in self
+            .db
+            .iterator_cf(&self.expirations(), IteratorMode::Start)
+        {
+            let (exp_key, t) = item?;
+            if bytes_to_millis(&t) > now {
+                continue;
+            }
+            if let Some((table, key)) = split_expiration_key(&exp_key) {
+                expired.push((table.to_string(), key.to_string()));
+            }
+        }
+
+        let mut purged = 0;
+        for (table, key) in expired {
+            let _guard = self.locks.write(&table, &key);
+            if !is_expired(self.expire_at_of(&table, &key)?, now) {
+                continue;
+            }
+            if let Some(cf) = self.get_table(&table) {
+                if self.db.get_pinned_cf(&cf, &key)?.is_some() {
+                    purged += 1;
+                }
+            }
+            let mut batch = WriteBatch::default();
+            self.delete(&mut batch, &table, &key);
+            self.db.write(batch)?;
+        }
+        Ok(purged)
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update(table, key, |old| incr_value(old, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update(table, key, |old| incr_float_value(old, delta))
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError> {
+        let _guard = self.locks.write(table, key);
+        let current = self.lookup(table, key)?;
+        if current != expected {
+            return Ok(Err(CompareAndSwapError { current }));
+        }
+
+        let mut batch = WriteBatch::default();
+        match new {
+            Some(v) => self.put(&mut batch, table, key, v, None)?,
+            None => self.delete(&mut batch, table, key),
+        }
+        self.db.write(batch)?;
+        Ok(Ok(()))
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let tables = self.tables.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(tables.iter().cloned().collect())
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        // writers lock their key before creating a table, so the keys are locked first
+        let _guards = self.locks.write_all();
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        if !tables.contains(table) {
+            return Ok(false);
+        }
+        self.db.put_cf(&self.dropped(), table, b"")?;
+        self.finish_drop(&mut tables, table)?;
+        Ok(true)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        let _guards = self.locks.write_all();
+        let mut tables = self.tables.write().unwrap_or_else(PoisonError::into_inner);
+        if !tables.contains(from) {
+            return Err(KvError::TableNotFound(from.into()));
+        }
+        if from == to {
+            return Ok(());
+        }
+        if tables.contains(to) {
+            return Err(KvError::TableExists(to.into()));
+        }
+
+        // rocksdb cannot rename a column family, so the pairs and their expirations are copied
+        // into a new one in batches of RENAME_BATCH writes, while no key can be written.
+        // The new table is marked as dropped until the copy is done and the old one from then
+        // on, so a crash leaves one table.
+        self.db.put_cf(&self.dropped(), to, b"")?;
+        let result = self.copy_table(from, to);
+        let table = if result.is_ok() { from } else { to };
+        self.finish_drop(&mut tables, table)?;
+        result?;
+        tables.insert(to.to_string());
+        Ok(())
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        Ok(self.scan(table, b"", b"", false, 0)?.len())
+    }
+
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>,
+    {
+        run_transaction(self, f)
+    }
+}
+
+impl LockingStore for RocksDb {
+    fn locks(&self) -> &LockSet {
+        &self.locks
+    }
+
+    fn lookup(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        RocksDb::lookup(self, table, key)
+    }
+
+    /// write all changes in a single batch
+    fn apply(&self, writes: TxnWrites) -> Result<(), KvError> {
+        let mut batch = WriteBatch::default();
+        for ((table, key), value) in writes {
+            match value {
+                Some(value) => self.put(&mut batch, &table, &key, value, None)?,
+                None => self.delete(&mut batch, &table, &key),
+            }
+        }
+        Ok(self.db.write(batch)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::storage::{
+        test_basi_interface, test_compare_and_swap, test_compare_and_swap_expired, test_get_all,
+        test_incr, test_incr_concurrently, test_incr_keeps_ttl, test_prefix, test_purge_expired,
+        test_range, test_tables, test_transaction, test_transaction_concurrently, test_ttl,
+    };
+
+    use super::*;
+
+    #[test]
+    fn rocksdb_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn rocksdb_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_get_all(store);
+    }
+
+    #[test]
+    fn rocksdb_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_ttl(store);
+    }
+
+    #[test]
+    fn rocksdb_purge_expired_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn rocksdb_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_incr(store);
+    }
+
+    #[test]
+    fn rocksdb_incr_should_be_atomic() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_incr_concurrently(store);
+    }
+
+    #[test]
+    fn rocksdb_incr_should_keep_ttl() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_incr_keeps_ttl(store);
+    }
+
+    #[test]
+    fn rocksdb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn rocksdb_compare_and_swap_should_ignore_expired_value() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_compare_and_swap_expired(store);
+    }
+
+    #[test]
+    fn rocksdb_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_transaction(store);
+    }
+
+    #[test]
+    fn rocksdb_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_transaction_concurrently(store);
+    }
+
+    #[test]
+    fn rocksdb_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_range(store);
+    }
+
+    #[test]
+    fn rocksdb_prefix_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_prefix(store);
+    }
+
+    #[test]
+    fn rocksdb_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        test_tables(store);
+    }
+
+    #[test]
+    fn rocksdb_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = RocksDb::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store
+                .set_ex("t2", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+        }
+
+        let store = RocksDb::new(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into(), "t2".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert!(store.ttl("t2", "k1").unwrap().unwrap() > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn rocksdb_rename_should_copy_table_in_batches() {
+        let dir = tempdir().unwrap();
+        let store = RocksDb::new(dir);
+        let n = RENAME_BATCH * 2 + 1;
+        for i in 0..n {
+            let key = format!("k{}", i);
+            store
+                .set_ex("t1", &key, (i as i64).into(), Duration::from_secs(60))
+                .unwrap();
+        }
+
+        store.rename_table("t1", "t2").unwrap();
+        assert_eq!(store.list_tables(), Ok(vec!["t2".into()]));
+        assert_eq!(store.len("t2"), Ok(n));
+        assert_eq!(store.get("t2", "k0"), Ok(Some(0.into())));
+        assert!(store.ttl("t2", "k1").unwrap().is_some());
+    }
+
+    #[test]
+    fn rocksdb_should_finish_interrupted_drop() {
+        let dir = tempdir().unwrap();
+        {
+            let store = RocksDb::new(&dir);
+            store
+                .set_ex("t1", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            store.set("t2", "k1", "v1".into()).unwrap();
+            // crash after marking t1 as dropped
+            store.db.put_cf(&store.dropped(), "t1", b"").unwrap();
+        }
+
+        let store = RocksDb::new(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t2".into()]));
+        let mut iter = store
+            .db
+            .iterator_cf(&store.expirations(), IteratorMode::Start);
+        assert!(iter.next().is_none());
+        let mut iter = store.db.iterator_cf(&store.dropped(), IteratorMode::Start);
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn rocksdb_should_drop_target_of_interrupted_rename() {
+        let dir = tempdir().unwrap();
+        {
+            let store = RocksDb::new(&dir);
+            store
+                .set_ex("t1", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            // crash in the middle of copying the pairs
+            store.db.put_cf(&store.dropped(), "t2", b"").unwrap();
+            store
+                .set_ex("t2", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+        }
+
+        let store = RocksDb::new(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+        assert!(store.ttl("t2", "k1").is_err());
+    }
+
+    #[test]
+    fn prefix_end_should_work() {
+        assert_eq!(prefix_end(b"ab"), b"ac");
+        assert_eq!(prefix_end(b"a\xff"), b"b");
+        assert_eq!(prefix_end(b"\xff\xff"), b"");
+        assert_eq!(prefix_end(b""), b"");
+    }
+}
@@ -1,65 +1,838 @@
-use crate::{KvError, Kvpair, Storage, Value};
-use sled::{Db, IVec};
+use crate::{
+    storage::{
+        expiration_key, expire_at, incr_float_value, incr_value, now_millis, split_expiration_key,
+    },
+    CompareAndSwapError, KvError, Kvpair, Storage, Transaction, Value,
+};
+use dashmap::DashMap;
+use sled::{
+    transaction::{
+        ConflictableTransactionError, TransactionError, TransactionalTree,
+        UnabortableTransactionError,
+    },
+    Batch, Db, IVec, Transactional, Tree,
+};
+use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::str;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// name of the tree holding the unix timestamp in milliseconds at which a key expires,
+/// keyed by `expiration_key(table, key)`
+const EXPIRATIONS_TREE: &str = "__expirations__";
+
+/// name of the tree holding the names of the tables being dropped,
+/// a drop interrupted by a crash is finished when the db is opened again
+const DROPPED_TREE: &str = "__dropped__";
+
+/// every table is stored in its own tree, named after the table with this prefix,
+/// so a table can never collide with another table or with the trees of SledDb itself
+const TABLE_TREE_PREFIX: &str = "table:";
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    expirations: Tree,
+    dropped: Tree,
+    /// the trees of the existing tables, so a read can tell a missing table
+    /// without creating its tree
+    tables: DashMap<String, Tree>,
+    /// operations on keys hold it for reading, dropping and renaming a table for writing,
+    /// so no key is written to a table while it is dropped or renamed
+    tables_lock: RwLock<()>,
+}
+
+/// why a transaction over a SledDb was aborted
+enum Abort {
+    Failed(KvError),
+    /// the transaction touched tables which were not part of it, it is retried with them.
+    /// The flag is set for the tables it writes, which are created if they do not exist.
+    MissingTables(Vec<(String, bool)>),
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::try_new(path).unwrap()
+    }
+
+    /// sled releases the lock of a dropped db from its background threads,
+    /// so opening it again right away may have to wait for them
+    #[cfg(test)]
+    pub(crate) fn reopen(path: impl AsRef<Path>) -> Self {
+        for _ in 0..100 {
+            if let Ok(store) = Self::try_new(&path) {
+                return store;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        Self::new(path)
     }
 
-    pub fn get_full_key(prefix: &str, key: &str) -> String {
-        format!("{}:{}", prefix, key)
+    /// open the db, it fails if it cannot be read or is opened by another SledDb
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let db = sled::open(path)?;
+        let expirations = db.open_tree(EXPIRATIONS_TREE)?;
+        let dropped = db.open_tree(DROPPED_TREE)?;
+        let store = Self {
+            db,
+            expirations,
+            dropped,
+            tables: DashMap::new(),
+            tables_lock: RwLock::new(()),
+        };
+        store.finish_drops()?;
+        store.load_tables()?;
+        store.migrate()?;
+        Ok(store)
     }
 
-    fn get_table_prefix(table: &str) -> String {
-        table.to_string()
+    fn lock_keys(&self) -> RwLockReadGuard<'_, ()> {
+        self.tables_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_tables(&self) -> RwLockWriteGuard<'_, ()> {
+        self.tables_lock
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// drop the tables whose drop was interrupted
+    fn finish_drops(&self) -> Result<(), KvError> {
+        for item in self.dropped.iter() {
+            let (name, _) = item?;
+            match str::from_utf8(&name) {
+                Ok(table) => self.finish_drop(table)?,
+                Err(_) => {
+                    self.dropped.remove(&name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// drop the tree and the expirations of a table marked as dropped, then the mark itself
+    fn finish_drop(&self, table: &str) -> Result<(), KvError> {
+        self.tables.remove(table);
+        // sled drops the whole tree at once instead of removing key by key
+        self.db.drop_tree(table_tree_name(table))?;
+        self.drop_expirations(table)?;
+        self.dropped.remove(table)?;
+        Ok(())
+    }
+
+    fn load_tables(&self) -> Result<(), KvError> {
+        for name in self.db.tree_names() {
+            let table = match str::from_utf8(&name)
+                .ok()
+                .and_then(|n| n.strip_prefix(TABLE_TREE_PREFIX))
+            {
+                Some(table) => table,
+                None => continue,
+            };
+            self.tables
+                .insert(table.to_string(), self.db.open_tree(&name)?);
+        }
+        Ok(())
+    }
+
+    /// the tree of the table if it exists, reads never create a table
+    fn get_table(&self, name: &str) -> Option<Tree> {
+        self.tables.get(name).map(|t| t.value().clone())
+    }
+
+    /// the tree of the table, it is created if it does not exist
+    fn table(&self, name: &str) -> Result<Tree, KvError> {
+        if let Some(tree) = self.get_table(name) {
+            return Ok(tree);
+        }
+        let tree = self.db.open_tree(table_tree_name(name))?;
+        Ok(self
+            .tables
+            .entry(name.to_string())
+            .or_insert(tree)
+            .value()
+            .clone())
+    }
+
+    /// remove every expiration of the table
+    fn drop_expirations(&self, table: &str) -> Result<(), KvError> {
+        let mut batch = Batch::default();
+        for item in self.expirations.scan_prefix(expiration_key(table, "")) {
+            batch.remove(item?.0);
+        }
+        Ok(self.expirations.apply_batch(batch)?)
+    }
+
+    /// move the data of the old layout, where all tables shared the default tree with
+    /// `table:key` keys, into the tree of each table. Every key is copied before it is
+    /// removed, so an interrupted migration just continues the next time the db is opened.
+    fn migrate(&self) -> Result<(), KvError> {
+        let legacy: &Tree = &self.db;
+        if legacy.is_empty() {
+            return Ok(());
+        }
+
+        info!("Migrating {} keys to one tree per table", legacy.len());
+        for item in legacy.iter() {
+            let (full_key, value) = item?;
+            let (table, key) = match str::from_utf8(&full_key)
+                .ok()
+                .and_then(|k| k.split_once(':'))
+            {
+                Some(v) => v,
+                None => {
+                    warn!("Skip migrating the invalid key {:?}", full_key);
+                    continue;
+                }
+            };
+
+            self.table(table)?.insert(key, value)?;
+            if let Some(t) = self.expirations.get(&full_key)? {
+                self.expirations.insert(expiration_key(table, key), t)?;
+                self.expirations.remove(&full_key)?;
+            }
+            legacy.remove(&full_key)?;
+        }
+        self.db.flush()?;
+        Ok(())
     }
+
+    /// run f atomically over the tree of a table and the expirations
+    fn run_transaction<F, R>(&self, tree: &Tree, f: F) -> Result<R, KvError>
+    where
+        F: Fn(
+            &TransactionalTree,
+            &TransactionalTree,
+        ) -> Result<R, ConflictableTransactionError<KvError>>,
+    {
+        (tree, &self.expirations)
+            .transaction(|(data, expirations)| f(data, expirations))
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => e.into(),
+                TransactionError::Abort(e) => e,
+            })
+    }
+
+    fn insert(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let _keys = self.lock_keys();
+        let tree = self.table(table)?;
+        let exp_key = expiration_key(table, key);
+        let data: Vec<u8> = value.try_into()?;
+        let now = now_millis();
+
+        let result = self.run_transaction(&tree, |db, expirations| {
+            let old_expire_at = match expire_at {
+                Some(t) => expirations.insert(exp_key.as_slice(), &t.to_be_bytes())?,
+                None => expirations.remove(exp_key.as_slice())?,
+            };
+            let old = db.insert(key, data.as_slice())?;
+            Ok(old.filter(|_| !is_expired(old_expire_at.as_ref(), now)))
+        })?;
+        result.map(|v| v.as_ref().try_into()).transpose()
+    }
+
+    /// lazily remove the key if it is expired
+    fn remove_expired(&self, table: &str, tree: &Tree, key: &str) -> Result<(), KvError> {
+        let exp_key = expiration_key(table, key);
+        let now = now_millis();
+        // checked outside of a transaction first, so keys without ttl stay cheap to read
+        if !is_expired(self.expirations.get(&exp_key)?.as_ref(), now) {
+            return Ok(());
+        }
+
+        self.run_transaction(tree, |db, expirations| {
+            if is_expired(expirations.get(exp_key.as_slice())?.as_ref(), now) {
+                expirations.remove(exp_key.as_slice())?;
+                db.remove(key)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// atomically replace the value of the key with f(old value), the key keeps its time to live
+    fn update<T>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl Fn(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError>
+    where
+        T: Into<Value> + Copy,
+    {
+        let _keys = self.lock_keys();
+        let tree = self.table(table)?;
+        self.remove_expired(table, &tree, key)?;
+
+        // the closure may be retried on conflicts, so only the outcome of the last run counts
+        let mut result = None;
+        tree.update_and_fetch(key, |old| {
+            let new = old
+                .map(Value::try_from)
+                .transpose()
+                .and_then(|old| f(old.as_ref()))
+                .and_then(|new| Ok((new, Vec::<u8>::try_from(new.into())?)));
+            match new {
+                Ok((new, data)) => {
+                    result = Some(Ok(new));
+                    Some(data)
+                }
+                Err(e) => {
+                    result = Some(Err(e));
+                    old.map(|v| v.to_vec())
+                }
+            }
+        })?;
+
+        result.unwrap_or_else(|| Err(KvError::Internal("update was not applied".into())))
+    }
+
+    /// move the pairs and the expirations of a table into the empty tree of another one, and
+    /// the drop mark from the new table to the old one
+    fn move_table(&self, from: &str, to: &str, source: Tree, target: Tree) -> Result<(), KvError> {
+        let pairs = source.iter().collect::<Result<Vec<_>, _>>()?;
+        let expirations = self
+            .expirations
+            .scan_prefix(expiration_key(from, ""))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let trees = [
+            source,
+            target,
+            self.expirations.clone(),
+            self.dropped.clone(),
+        ];
+        trees[..]
+            .transaction(|trees| {
+                let (source, target, exps, dropped) = (&trees[0], &trees[1], &trees[2], &trees[3]);
+                for (key, value) in &pairs {
+                    target.insert(key, value)?;
+                    source.remove(key)?;
+                }
+                for (exp_key, t) in &expirations {
+                    if let Some((_, key)) = split_expiration_key(exp_key) {
+                        exps.insert(expiration_key(to, key), t)?;
+                    }
+                    exps.remove(exp_key)?;
+                }
+                dropped.remove(to.as_bytes())?;
+                dropped.insert(from.as_bytes(), &[])?;
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => e.into(),
+                TransactionError::Abort(()) => KvError::Internal("rename was aborted".into()),
+            })
+    }
+
+    /// filter out the expired pairs of a scan over the table
+    fn live_pairs(
+        &self,
+        table: &str,
+        iter: impl Iterator<Item = Result<(IVec, IVec), sled::Error>>,
+    ) -> impl Iterator<Item = Result<(IVec, IVec), sled::Error>> {
+        let expirations = self.expirations.clone();
+        let table = table.to_string();
+        let now = now_millis();
+        iter.filter(move |v| match v {
+            Ok((k, _)) => {
+                let exp_key = expiration_key(&table, ivec_to_key(k));
+                !matches!(expirations.get(exp_key), Ok(t) if is_expired(t.as_ref(), now))
+            }
+            Err(_) => true,
+        })
+    }
+}
+
+fn table_tree_name(table: &str) -> String {
+    format!("{}{}", TABLE_TREE_PREFIX, table)
+}
+
+fn is_expired(expire_at: Option<&IVec>, now: u64) -> bool {
+    match expire_at {
+        Some(t) => ivec_to_millis(t) <= now,
+        None => false,
+    }
+}
+
+fn ivec_to_millis(ivec: &IVec) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&ivec[..8]);
+    u64::from_be_bytes(buf)
 }
 
 impl Storage for SledDb {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        let result = self.0.get(full_key)?;
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(None),
+        };
+        self.remove_expired(table, &tree, key)?;
+        let result = tree.get(key)?;
         result.map(|v| v.as_ref().try_into()).transpose()
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let result = self.0.scan_prefix(prefix).map(|v| v.into()).collect();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(Vec::new()),
+        };
+        let result = self
+            .live_pairs(table, tree.iter())
+            .map(|v| v.into())
+            .collect();
 
         Ok(result)
     }
 
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let prefix = SledDb::get_table_prefix(table);
-        let iter = self.0.scan_prefix(prefix).into_iter().map(|v| v.into());
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let iter = self.live_pairs(table, tree.iter()).map(|v| v.into());
         Ok(Box::new(iter))
     }
 
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(Vec::new()),
+        };
+        let iter = match end {
+            "" => tree.range(start..),
+            end if end <= start => return Ok(Vec::new()),
+            end => tree.range(start..end),
+        };
+        let iter: Box<dyn Iterator<Item = _>> = match reverse {
+            true => Box::new(self.live_pairs(table, iter.rev())),
+            false => Box::new(self.live_pairs(table, iter)),
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        iter.take(limit).map(ivec_pair_to_kvpair).collect()
+    }
+
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError> {
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(Vec::new()),
+        };
+        self.live_pairs(table, tree.scan_prefix(prefix))
+            .map(ivec_pair_to_kvpair)
+            .collect()
+    }
+
     fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        let data: Vec<u8> = value.try_into()?;
+        self.insert(table, key, value, None)
+    }
 
-        let result = self
-            .0
-            .insert(full_key, data)?
-            .map(|v| v.as_ref().try_into());
-        result.transpose()
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.insert(table, key, value, Some(expire_at(ttl)))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(false),
+        };
+        self.remove_expired(table, &tree, key)?;
+        Ok(tree.contains_key(key)?)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(None),
+        };
+        let exp_key = expiration_key(table, key);
+        let now = now_millis();
+        let result = self.run_transaction(&tree, |db, expirations| {
+            let expire_at = expirations.remove(exp_key.as_slice())?;
+            let old = db.remove(key)?;
+            Ok(old.filter(|_| !is_expired(expire_at.as_ref(), now)))
+        })?;
+        result.map(|v| v.as_ref().try_into()).transpose()
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(false),
+        };
+        self.remove_expired(table, &tree, key)?;
+        let exp_key = expiration_key(table, key);
+        let expire_at = expire_at(ttl);
+        self.run_transaction(&tree, |db, expirations| {
+            if db.get(key)?.is_none() {
+                return Ok(false);
+            }
+            expirations.insert(exp_key.as_slice(), &expire_at.to_be_bytes())?;
+            Ok(true)
+        })
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Err(KvError::NotFound(table.into(), key.into())),
+        };
+        self.remove_expired(table, &tree, key)?;
+        if !tree.contains_key(key)? {
+            return Err(KvError::NotFound(table.into(), key.into()));
+        }
+
+        let now = now_millis();
+        Ok(self
+            .expirations
+            .get(expiration_key(table, key))?
+            .map(|t| Duration::from_millis(ivec_to_millis(&t).saturating_sub(now))))
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let _keys = self.lock_keys();
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(false),
+        };
+        self.remove_expired(table, &tree, key)?;
+        Ok(self
+            .expirations
+            .remove(expiration_key(table, key))?
+            .is_some())
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        let now = now_millis();
+        let mut purged = 0;
+        for item in self.expirations.iter() {
+            let (exp_key, t) = item?;
+            if ivec_to_millis(&t) > now {
+                continue;
+            }
+            let (table, key) = match split_expiration_key(&exp_key) {
+                Some(v) => v,
+                None => continue,
+            };
+            let _keys = self.lock_keys();
+
+            // the expiration of a dropped table is stale
+            let tree = match self.get_table(table) {
+                Some(tree) => tree,
+                None => {
+                    self.expirations.remove(&exp_key)?;
+                    continue;
+                }
+            };
+            let removed = self.run_transaction(&tree, |db, expirations| {
+                match expirations.get(&exp_key)? {
+                    Some(t) if ivec_to_millis(&t) <= now => {
+                        expirations.remove(&exp_key)?;
+                        Ok(db.remove(key)?.is_some())
+                    }
+                    _ => Ok(false),
+                }
+            })?;
+            if removed {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError> {
+        let _keys = self.lock_keys();
+        let tree = self.table(table)?;
+        let exp_key = expiration_key(table, key);
+        let expected: Option<Vec<u8>> = expected.map(|v| v.try_into()).transpose()?;
+        let new: Option<Vec<u8>> = new.map(|v| v.try_into()).transpose()?;
+        let now = now_millis();
+
+        // the value and its expiration change together, an expired value counts as missing
+        let result = self.run_transaction(&tree, |db, expirations| {
+            let expire_at = expirations.get(exp_key.as_slice())?;
+            let current = db
+                .get(key)?
+                .filter(|_| !is_expired(expire_at.as_ref(), now));
+            if current.as_deref() != expected.as_deref() {
+                return Ok(Err(current));
+            }
+            match &new {
+                Some(data) => db.insert(key, data.as_slice())?,
+                None => db.remove(key)?,
+            };
+            expirations.remove(exp_key.as_slice())?;
+            Ok(Ok(()))
+        })?;
+
+        match result {
+            Ok(()) => Ok(Ok(())),
+            Err(current) => {
+                let current = current.map(|v| v.as_ref().try_into()).transpose()?;
+                Ok(Err(CompareAndSwapError { current }))
+            }
+        }
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update(table, key, |old| incr_value(old, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update(table, key, |old| incr_float_value(old, delta))
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<String> = self.tables.iter().map(|t| t.key().clone()).collect();
+        tables.sort();
+        Ok(tables)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _tables = self.lock_tables();
+        if !self.tables.contains_key(table) {
+            return Ok(false);
+        }
+        self.dropped.insert(table, &[])?;
+        self.finish_drop(table)?;
+        Ok(true)
+    }
+
+    /// sled cannot rename a tree, so the pairs and their expirations are moved into a new tree in
+    /// a single transaction, which holds the whole table in memory. The new tree is marked as
+    /// dropped until the move commits and the old one from then on, so a crash leaves one table.
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        let _tables = self.lock_tables();
+        let source = match self.get_table(from) {
+            Some(tree) => tree,
+            None => return Err(KvError::TableNotFound(from.into())),
+        };
+        if from == to {
+            return Ok(());
+        }
+        if self.tables.contains_key(to) {
+            return Err(KvError::TableExists(to.into()));
+        }
+
+        self.dropped.insert(to, &[])?;
+        let target = self.table(to)?;
+        let result = self.move_table(from, to, source, target);
+        let table = if result.is_ok() { from } else { to };
+        self.finish_drop(table)?;
+        result
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        let tree = match self.get_table(table) {
+            Some(tree) => tree,
+            None => return Ok(0),
+        };
+        let mut len = 0;
+        for item in self.live_pairs(table, tree.iter()) {
+            item?;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>,
+    {
+        // sled needs the trees of a transaction upfront, so it starts without any table
+        // and is retried with the tables it turned out to touch. Only the tables it writes
+        // are created, the ones it just reads and which do not exist are read as empty.
+        let _keys = self.lock_keys();
+        let mut tables: Vec<String> = Vec::new();
+        let mut trees = vec![self.expirations.clone()];
+        let mut absent: Vec<String> = Vec::new();
+        loop {
+            let result = trees[..].transaction(|trees| {
+                let txn = SledTxn::new(&tables, &absent, &trees[1..], &trees[0]);
+                let result = f(&txn);
+                // a conflict must reach sled so it retries the transaction
+                if let Some(e) = txn.error.take() {
+                    return Err(e.into());
+                }
+                let missing = txn.missing.into_inner();
+                if !missing.is_empty() {
+                    return Err(ConflictableTransactionError::Abort(Abort::MissingTables(
+                        missing,
+                    )));
+                }
+                result.map_err(|e| ConflictableTransactionError::Abort(Abort::Failed(e)))
+            });
+
+            match result {
+                Ok(result) => return Ok(result),
+                Err(TransactionError::Abort(Abort::MissingTables(missing))) => {
+                    for (table, write) in missing {
+                        let tree = match write {
+                            true => Some(self.table(&table)?),
+                            false => self.get_table(&table),
+                        };
+                        absent.retain(|t| t != &table);
+                        match tree {
+                            Some(tree) => {
+                                tables.push(table);
+                                trees.push(tree);
+                            }
+                            None => absent.push(table),
+                        }
+                    }
+                }
+                Err(TransactionError::Abort(Abort::Failed(e))) => return Err(e),
+                Err(TransactionError::Storage(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// a transaction over some tables of a SledDb, sled buffers the writes and retries it on conflicts
+struct SledTxn<'a> {
+    tables: &'a [String],
+    /// the tables f reads which do not exist
+    absent: &'a [String],
+    /// the trees of the tables, in the same order
+    data: &'a [TransactionalTree],
+    expirations: &'a TransactionalTree,
+    now: u64,
+    /// the first sled error, which f only sees as a KvError
+    error: Cell<Option<UnabortableTransactionError>>,
+    /// the tables f touched which are not part of the transaction, and whether it writes them
+    missing: RefCell<Vec<(String, bool)>>,
+}
+
+impl<'a> SledTxn<'a> {
+    fn new(
+        tables: &'a [String],
+        absent: &'a [String],
+        data: &'a [TransactionalTree],
+        expirations: &'a TransactionalTree,
+    ) -> Self {
+        Self {
+            tables,
+            absent,
+            data,
+            expirations,
+            now: now_millis(),
+            error: Cell::new(None),
+            missing: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// the tree of a table f reads, `None` if the table does not exist
+    fn read_tree(&self, table: &str) -> Result<Option<&TransactionalTree>, KvError> {
+        if self.absent.iter().any(|t| t == table) {
+            return Ok(None);
+        }
+        self.tree(table, false).map(Some)
+    }
+
+    fn tree(&self, table: &str, write: bool) -> Result<&TransactionalTree, KvError> {
+        if let Some(i) = self.tables.iter().position(|t| t == table) {
+            return Ok(&self.data[i]);
+        }
+
+        let mut missing = self.missing.borrow_mut();
+        match missing.iter_mut().find(|(t, _)| t == table) {
+            Some((_, w)) => *w |= write,
+            None => missing.push((table.to_string(), write)),
+        }
+        Err(KvError::Internal(format!(
+            "table {} is not part of the transaction",
+            table
+        )))
+    }
+
+    fn check<T>(&self, result: Result<T, UnabortableTransactionError>) -> Result<T, KvError> {
+        result.map_err(|e| {
+            let err = match &e {
+                UnabortableTransactionError::Conflict => {
+                    KvError::Internal("transaction conflict".into())
+                }
+                UnabortableTransactionError::Storage(e) => e.clone().into(),
+            };
+            let first = self.error.take().unwrap_or(e);
+            self.error.set(Some(first));
+            err
+        })
+    }
+
+    /// remove the value and the expiration of the key, returns the old value if it was alive
+    fn take(&self, table: &str, key: &str) -> Result<Option<IVec>, KvError> {
+        let tree = match self.read_tree(table)? {
+            Some(tree) => tree,
+            None => return Ok(None),
+        };
+        let exp_key = expiration_key(table, key);
+        let expire_at = self.check(self.expirations.remove(exp_key))?;
+        let old = self.check(tree.remove(key))?;
+        Ok(old.filter(|_| !is_expired(expire_at.as_ref(), self.now)))
+    }
+}
+
+impl Transaction for SledTxn<'_> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let tree = match self.read_tree(table)? {
+            Some(tree) => tree,
+            None => return Ok(None),
+        };
+        let expire_at = self.check(self.expirations.get(expiration_key(table, key)))?;
+        if is_expired(expire_at.as_ref(), self.now) {
+            return Ok(None);
+        }
+        let result = self.check(tree.get(key))?;
+        result.map(|v| v.as_ref().try_into()).transpose()
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        let data: Vec<u8> = value.try_into()?;
+        let tree = self.tree(table, true)?;
+        let old = self.take(table, key)?;
+        self.check(tree.insert(key, data))?;
+        old.map(|v| v.as_ref().try_into()).transpose()
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        Ok(self.0.contains_key(full_key)?)
+        Ok(self.get(table, key)?.is_some())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let full_key = SledDb::get_full_key(table, key);
-        let result = self.0.remove(full_key)?.map(|v| v.as_ref().try_into());
-        result.transpose()
+        let old = self.take(table, key)?;
+        old.map(|v| v.as_ref().try_into()).transpose()
     }
 }
 
@@ -75,18 +848,24 @@ impl From<Result<(IVec, IVec), sled::Error>> for Kvpair {
     }
 }
 
+fn ivec_pair_to_kvpair(v: Result<(IVec, IVec), sled::Error>) -> Result<Kvpair, KvError> {
+    let (k, v) = v?;
+    Ok(Kvpair::new(ivec_to_key(k.as_ref()), v.as_ref().try_into()?))
+}
+
 fn ivec_to_key(ivec: &[u8]) -> &str {
-    let s = str::from_utf8(ivec).unwrap();
-    let mut iter = s.split(':');
-    iter.next();
-    iter.next().unwrap()
+    str::from_utf8(ivec).unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use tempfile::tempdir;
 
-    use crate::storage::{test_basi_interface, test_get_all};
+    use crate::storage::{
+        test_basi_interface, test_compare_and_swap, test_compare_and_swap_expired, test_get_all,
+        test_incr, test_incr_concurrently, test_incr_keeps_ttl, test_prefix, test_purge_expired,
+        test_range, test_tables, test_transaction, test_transaction_concurrently, test_ttl,
+    };
 
     use super::*;
 
@@ -103,4 +882,290 @@ mod tests {
         let store = SledDb::new(dir);
         test_get_all(store);
     }
+
+    #[test]
+    fn sleddb_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_ttl(store);
+    }
+
+    #[test]
+    fn sleddb_purge_expired_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_purge_expired(store);
+    }
+
+    #[test]
+    fn sleddb_incr_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_incr(store);
+    }
+
+    #[test]
+    fn sleddb_incr_should_be_atomic() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_incr_concurrently(store);
+    }
+
+    #[test]
+    fn sleddb_incr_should_keep_ttl() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_incr_keeps_ttl(store);
+    }
+
+    #[test]
+    fn sleddb_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_compare_and_swap(store);
+    }
+
+    #[test]
+    fn sleddb_compare_and_swap_should_ignore_expired_value() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_compare_and_swap_expired(store);
+    }
+
+    #[test]
+    fn sleddb_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_transaction(store);
+    }
+
+    #[test]
+    fn sleddb_transaction_should_be_isolated() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_transaction_concurrently(store);
+    }
+
+    #[test]
+    fn sleddb_range_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_range(store);
+    }
+
+    #[test]
+    fn sleddb_prefix_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_prefix(store);
+    }
+
+    #[test]
+    fn sleddb_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        test_tables(store);
+    }
+
+    #[test]
+    fn sleddb_tables_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store
+                .set_ex("t2", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            store.set("t3", "k1", "v1".into()).unwrap();
+            store.rename_table("t2", "t4").unwrap();
+            store.drop_table("t3").unwrap();
+            assert_eq!(store.get("t5", "k1"), Ok(None));
+            store.db.flush().unwrap();
+        }
+
+        let store = SledDb::reopen(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into(), "t4".into()]));
+        assert!(store.ttl("t4", "k1").unwrap().is_some());
+        // the expirations of the renamed and the dropped tables are gone
+        assert_eq!(store.expirations.len(), 1);
+    }
+
+    #[test]
+    fn sleddb_ttl_should_survive_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(&dir);
+            store
+                .set_ex("t1", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            store
+                .set_ex("t1", "k2", "v2".into(), Duration::from_millis(50))
+                .unwrap();
+            store.db.flush().unwrap();
+        }
+
+        // let k2 expire
+        std::thread::sleep(Duration::from_millis(100));
+        let store = SledDb::reopen(&dir);
+        assert!(store.ttl("t1", "k1").unwrap().unwrap() > Duration::from_secs(50));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn sleddb_tables_should_not_overlap() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.set("t10", "k1", "v10".into()).unwrap();
+        store.set("t1x", "k1", "v1x".into()).unwrap();
+        // a table and key which concatenate to the same string as another pair
+        store.set("t1:k", "2", "a".into()).unwrap();
+        store.set("t1", "k:k2", "b".into()).unwrap();
+
+        let mut pairs = store.get_all("t1").unwrap();
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("k1", "v1".into()),
+                Kvpair::new("k:k2", "b".into()),
+            ]
+        );
+        assert_eq!(store.get("t1:k", "2"), Ok(Some("a".into())));
+        assert_eq!(store.get_all("t10").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sleddb_keys_with_colon_should_work() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        store.set("t1", "user:1:name", "alice".into()).unwrap();
+        store
+            .set_ex("t1", "session:a:b", "s".into(), Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(store.get("t1", "user:1:name"), Ok(Some("alice".into())));
+        let keys: Vec<_> = store.get_iter("t1").unwrap().map(|p| p.key).collect();
+        assert_eq!(keys, ["session:a:b", "user:1:name"]);
+        assert!(store.ttl("t1", "session:a:b").unwrap().is_some());
+        assert_eq!(store.ttl("t1", "user:1:name"), Ok(None));
+    }
+
+    #[test]
+    fn sleddb_transaction_should_span_tables() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        store.set("from", "k1", 10.into()).unwrap();
+
+        let res = store.transaction(|txn| {
+            let v = txn.del("from", "k1")?.unwrap();
+            txn.set("to", "k1", v)?;
+            txn.set("log", "k1", "moved".into())
+        });
+        assert_eq!(res, Ok(None));
+        assert_eq!(store.get("from", "k1"), Ok(None));
+        assert_eq!(store.get("to", "k1"), Ok(Some(10.into())));
+        assert_eq!(store.get("log", "k1"), Ok(Some("moved".into())));
+    }
+
+    #[test]
+    fn sleddb_transaction_should_only_create_written_tables() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir);
+        store.set("t1", "k1", "v1".into()).unwrap();
+
+        let res = store.transaction(|txn| {
+            assert!(!txn.contains("missing", "k1")?);
+            txn.del("missing", "k1")?;
+            txn.get("missing", "k1")
+        });
+        assert_eq!(res, Ok(None));
+        assert_eq!(store.list_tables(), Ok(vec!["t1".to_string()]));
+
+        // a table it first reads and then writes is created
+        let res = store.transaction(|txn| {
+            let v = txn.get("t2", "k1")?;
+            txn.set("t2", "k1", "v2".into())?;
+            Ok(v)
+        });
+        assert_eq!(res, Ok(None));
+        assert_eq!(store.get("t2", "k1"), Ok(Some("v2".into())));
+        assert_eq!(
+            store.list_tables(),
+            Ok(vec!["t1".to_string(), "t2".to_string()])
+        );
+    }
+
+    #[test]
+    fn sleddb_should_finish_interrupted_drop() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(&dir);
+            store
+                .set_ex("t1", "k1", "v1".into(), Duration::from_secs(60))
+                .unwrap();
+            store.set("t2", "k1", "v1".into()).unwrap();
+            // crash after marking t1 as dropped
+            store.dropped.insert("t1", &[]).unwrap();
+            store.db.flush().unwrap();
+        }
+
+        let store = SledDb::reopen(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t2".into()]));
+        assert!(store.expirations.is_empty());
+        assert!(store.dropped.is_empty());
+    }
+
+    #[test]
+    fn sleddb_should_drop_target_of_interrupted_rename() {
+        let dir = tempdir().unwrap();
+        {
+            let store = SledDb::new(&dir);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            // crash after creating the target but before moving the pairs
+            store.dropped.insert("t2", &[]).unwrap();
+            store.set("t2", "k1", "v1".into()).unwrap();
+            store.db.flush().unwrap();
+        }
+
+        let store = SledDb::reopen(&dir);
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[test]
+    fn sleddb_should_migrate_shared_tree_layout() {
+        let dir = tempdir().unwrap();
+        {
+            // the layout before every table got its own tree
+            let db = sled::open(&dir).unwrap();
+            let expirations = db.open_tree(EXPIRATIONS_TREE).unwrap();
+            let value = |v: Value| -> Vec<u8> { v.try_into().unwrap() };
+            db.insert("t1:k1", value("v1".into())).unwrap();
+            db.insert("t1:k:2", value("v2".into())).unwrap();
+            db.insert("t10:k1", value("v10".into())).unwrap();
+            db.insert("t1:k3", value("v3".into())).unwrap();
+            let expire_at = now_millis() + 60_000;
+            expirations
+                .insert("t1:k3", &expire_at.to_be_bytes())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = SledDb::new(&dir);
+        assert!(store.db.is_empty());
+        let mut pairs = store.get_all("t1").unwrap();
+        pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("k1", "v1".into()),
+                Kvpair::new("k3", "v3".into()),
+                Kvpair::new("k:2", "v2".into()),
+            ]
+        );
+        assert_eq!(store.get("t10", "k1"), Ok(Some("v10".into())));
+        assert!(store.ttl("t1", "k3").unwrap().is_some());
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+    }
 }
@@ -0,0 +1,714 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use tracing::warn;
+
+use crate::{
+    memory::MemTable,
+    sleddb::SledDb,
+    storage::{expire_at, lock_set::LockSet, now_millis},
+    CompareAndSwapError, KvError, Kvpair, Storage, Transaction, Value,
+};
+
+/// when the writes to a tiered storage reach its backend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// every write reaches the backend before it returns
+    WriteThrough,
+    /// set and del only write to the cache, the backend gets them in the background once per
+    /// interval, from the write which makes them more than the max pending writes, before any
+    /// other operation on the key or a scan of its table and when the storage is dropped
+    WriteBack(Duration),
+}
+
+/// how many writes a write back storage holds back by default before flushing them
+const DEFAULT_MAX_PENDING: usize = 10_000;
+
+/// a storage serving reads of keys from a MemTable in front of a durable backend.
+///
+/// A key missing in the cache is read from the backend and kept in the cache with its time to
+/// live. A del removes the key from the cache, writes other than set and del go straight to
+/// the backend and remove the key from the cache, so it is read again the next time. Scans and
+/// operations on whole tables are always served by the backend, a scan after flushing the
+/// pending writes of its table. The cache may have a memory budget, in write back mode a key
+/// it evicts is still written to the backend.
+#[derive(Debug)]
+pub struct TieredStorage<S: Storage = SledDb> {
+    /// dropped first, so the backend is closed once the storage is dropped
+    _flusher: Option<Flusher>,
+    inner: Arc<Inner<S>>,
+}
+
+/// the thread flushing the pending writes in write back mode, stopped and joined on drop
+#[derive(Debug)]
+struct Flusher {
+    stop: mpsc::Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner<S: Storage> {
+    cache: MemTable,
+    backend: S,
+    mode: WriteMode,
+    /// (table, key) -> the write of the key which has not reached the backend yet
+    pending: DashMap<(String, String), Pending>,
+    /// a write making the pending writes more than this flushes them
+    max_pending: AtomicUsize,
+    /// single key operations lock their key, so loading it into the cache does not race
+    /// with a write of it
+    locks: LockSet,
+    /// single key operations hold it for reading, operations which write to several keys
+    /// for writing, so no key is loaded into the cache while they run
+    tables: RwLock<()>,
+}
+
+#[derive(Debug)]
+enum Pending {
+    /// the value and the unix timestamp in milliseconds at which it expires
+    Set(Value, Option<u64>),
+    Del,
+}
+
+/// held by a single key operation
+struct KeyGuard<'a> {
+    _tables: RwLockReadGuard<'a, ()>,
+    _key: RwLockWriteGuard<'a, ()>,
+}
+
+impl<S: Storage> TieredStorage<S> {
+    pub fn new(cache: MemTable, backend: S, mode: WriteMode) -> Self {
+        let inner = Arc::new(Inner {
+            cache,
+            backend,
+            mode,
+            pending: DashMap::new(),
+            max_pending: AtomicUsize::new(DEFAULT_MAX_PENDING),
+            locks: LockSet::default(),
+            tables: RwLock::new(()),
+        });
+        let flusher = match mode {
+            WriteMode::WriteBack(interval) => {
+                let (stop, stopped) = mpsc::channel();
+                let inner = Arc::clone(&inner);
+                let handle = thread::spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                        if let Err(e) = inner.flush() {
+                            warn!("failed to flush writes to the backend: {}", e);
+                        }
+                    }
+                });
+                Some(Flusher {
+                    stop,
+                    handle: Some(handle),
+                })
+            }
+            WriteMode::WriteThrough => None,
+        };
+        Self {
+            _flusher: flusher,
+            inner,
+        }
+    }
+
+    /// flush the pending writes once a write makes them more than max_pending,
+    /// so a write back storage does not buffer an unbounded number of them
+    pub fn with_max_pending(self, max_pending: usize) -> Self {
+        self.inner.max_pending.store(max_pending, Ordering::Relaxed);
+        self
+    }
+
+    /// the backend, in write back mode reading it directly misses the writes not flushed yet
+    pub fn backend(&self) -> &S {
+        &self.inner.backend
+    }
+
+    /// write every pending write to the backend, a no-op in write through mode
+    pub fn flush(&self) -> Result<(), KvError> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Storage> Inner<S> {
+    fn lock_key(&self, table: &str, key: &str) -> KeyGuard<'_> {
+        KeyGuard {
+            _tables: self.tables.read().unwrap_or_else(PoisonError::into_inner),
+            _key: self.locks.write(table, key),
+        }
+    }
+
+    fn lock_tables(&self) -> RwLockWriteGuard<'_, ()> {
+        self.tables.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// write the pending write of the key to the backend, the caller holds the lock of the key
+    fn flush_key(&self, table: &str, key: &str) -> Result<(), KvError> {
+        let id = (table.to_string(), key.to_string());
+        let (id, pending) = match self.pending.remove(&id) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let now = now_millis();
+        let result = match &pending {
+            Pending::Set(value, None) => self.backend.set(table, key, value.clone()),
+            Pending::Set(value, Some(at)) if *at > now => {
+                let ttl = Duration::from_millis(at - now);
+                self.backend.set_ex(table, key, value.clone(), ttl)
+            }
+            Pending::Set(_, Some(_)) | Pending::Del => self.backend.del(table, key),
+        };
+        if result.is_err() {
+            // keep it for the next flush
+            self.pending.insert(id, pending);
+        }
+        result.map(|_| ())
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        let ids: Vec<_> = self.pending.iter().map(|e| e.key().clone()).collect();
+        for (table, key) in ids {
+            let _guard = self.lock_key(&table, &key);
+            self.flush_key(&table, &key)?;
+        }
+        Ok(())
+    }
+
+    /// write the pending writes of the table to the backend
+    fn flush_table(&self, table: &str) -> Result<(), KvError> {
+        let keys: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|e| e.key().0 == table)
+            .map(|e| e.key().1.clone())
+            .collect();
+        for key in keys {
+            let _guard = self.lock_key(table, &key);
+            self.flush_key(table, &key)?;
+        }
+        Ok(())
+    }
+
+    /// write every pending write to the backend, the caller holds the tables lock
+    fn flush_locked(&self) -> Result<(), KvError> {
+        let ids: Vec<_> = self.pending.iter().map(|e| e.key().clone()).collect();
+        ids.iter()
+            .try_for_each(|(table, key)| self.flush_key(table, key))
+    }
+
+    /// the value of the key, it is loaded into the cache if it is missing there.
+    /// The caller holds the lock of the key.
+    fn load(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        if let Some(value) = self.cache.get(table, key)? {
+            return Ok(Some(value));
+        }
+        self.flush_key(table, key)?;
+        let value = match self.backend.get(table, key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match self.backend.ttl(table, key) {
+            Ok(None) => self.cache.set(table, key, value.clone())?,
+            Ok(Some(ttl)) => self.cache.set_ex(table, key, value.clone(), ttl)?,
+            // it expired in the meantime
+            Err(KvError::NotFound(..)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(value))
+    }
+
+    /// set the key to value, or delete it if it is `None`, returns the old value
+    fn write(
+        &self,
+        table: &str,
+        key: &str,
+        value: Option<Value>,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Value>, KvError> {
+        let guard = self.lock_key(table, key);
+        let old = match self.mode {
+            WriteMode::WriteThrough => {
+                // a get reading the cache without the lock must not see the old value once
+                // the backend has the new one, it waits for the lock instead
+                self.cache.del(table, key)?;
+                match (&value, ttl) {
+                    (Some(value), None) => self.backend.set(table, key, value.clone())?,
+                    (Some(value), Some(ttl)) => {
+                        self.backend.set_ex(table, key, value.clone(), ttl)?
+                    }
+                    (None, _) => self.backend.del(table, key)?,
+                }
+            }
+            WriteMode::WriteBack(_) => {
+                let old = self.load(table, key)?;
+                let pending = match &value {
+                    Some(value) => Pending::Set(value.clone(), ttl.map(expire_at)),
+                    None => Pending::Del,
+                };
+                self.pending.insert((table.into(), key.into()), pending);
+                old
+            }
+        };
+        match (value, ttl) {
+            (Some(value), None) => self.cache.set(table, key, value)?,
+            (Some(value), Some(ttl)) => self.cache.set_ex(table, key, value, ttl)?,
+            (None, _) => self.cache.del(table, key)?,
+        };
+
+        // flushing locks every key, so the lock of this one is released first
+        drop(guard);
+        if self.pending.len() > self.max_pending.load(Ordering::Relaxed) {
+            // the write itself succeeded, the flusher retries what is left
+            if let Err(e) = self.flush() {
+                warn!("failed to flush writes to the backend: {}", e);
+            }
+        }
+        Ok(old)
+    }
+
+    /// run f on the backend after flushing the key and removing it from the cache,
+    /// so it is read again the next time
+    fn write_backend<R>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(&S) -> Result<R, KvError>,
+    ) -> Result<R, KvError> {
+        let _guard = self.lock_key(table, key);
+        self.flush_key(table, key)?;
+        // a get reading the cache without the lock waits for the lock instead
+        self.cache.del(table, key)?;
+        f(&self.backend)
+    }
+}
+
+impl<S: Storage> Drop for Inner<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_locked() {
+            warn!("failed to flush writes to the backend: {}", e);
+        }
+    }
+}
+
+impl<S: Storage> Storage for TieredStorage<S> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        if let Some(value) = self.inner.cache.get(table, key)? {
+            return Ok(Some(value));
+        }
+        let _guard = self.inner.lock_key(table, key);
+        self.inner.load(table, key)
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        self.inner.write(table, key, Some(value), None)
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.inner.write(table, key, Some(value), Some(ttl))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get(table, key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inner.write(table, key, None, None)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.flush_table(table)?;
+        self.inner.backend.get_all(table)
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        self.inner.flush_table(table)?;
+        self.inner.backend.get_iter(table)
+    }
+
+    fn range(
+        &self,
+        table: &str,
+        start: &str,
+        end: &str,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.flush_table(table)?;
+        self.inner.backend.range(table, start, end, reverse, limit)
+    }
+
+    fn prefix(&self, table: &str, prefix: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.flush_table(table)?;
+        self.inner.backend.prefix(table, prefix)
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        self.inner
+            .write_backend(table, key, |backend| backend.expire(table, key, ttl))
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let _guard = self.inner.lock_key(table, key);
+        self.inner.flush_key(table, key)?;
+        self.inner.backend.ttl(table, key)
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.inner
+            .write_backend(table, key, |backend| backend.persist(table, key))
+    }
+
+    fn purge_expired(&self) -> Result<usize, KvError> {
+        let _tables = self.inner.lock_tables();
+        self.inner.flush_locked()?;
+        self.inner.cache.purge_expired()?;
+        self.inner.backend.purge_expired()
+    }
+
+    fn incr(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.inner
+            .write_backend(table, key, |backend| backend.incr(table, key, delta))
+    }
+
+    fn incr_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.inner
+            .write_backend(table, key, |backend| backend.incr_float(table, key, delta))
+    }
+
+    fn compare_and_swap(
+        &self,
+        table: &str,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<Result<(), CompareAndSwapError>, KvError> {
+        self.inner.write_backend(table, key, |backend| {
+            backend.compare_and_swap(table, key, expected, new)
+        })
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        self.flush()?;
+        self.inner.backend.list_tables()
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _tables = self.inner.lock_tables();
+        self.inner.flush_locked()?;
+        self.inner.cache.drop_table(table)?;
+        self.inner.backend.drop_table(table)
+    }
+
+    fn rename_table(&self, from: &str, to: &str) -> Result<(), KvError> {
+        let _tables = self.inner.lock_tables();
+        self.inner.flush_locked()?;
+        self.inner.cache.drop_table(from)?;
+        self.inner.cache.drop_table(to)?;
+        self.inner.backend.rename_table(from, to)
+    }
+
+    fn len(&self, table: &str) -> Result<usize, KvError> {
+        self.inner.flush_table(table)?;
+        self.inner.backend.len(table)
+    }
+
+    fn transaction<R, F>(&self, f: F) -> Result<R, KvError>
+    where
+        F: Fn(&dyn Transaction) -> Result<R, KvError>,
+    {
+        let _tables = self.inner.lock_tables();
+        self.inner.flush_locked()?;
+        // every key the transaction wrote to, in any of its attempts
+        let written = RefCell::new(HashSet::new());
+        let result = self.inner.backend.transaction(|txn| {
+            f(&RecordingTransaction {
+                txn,
+                written: &written,
+            })
+        });
+        for (table, key) in written.into_inner() {
+            self.inner.cache.del(&table, &key)?;
+        }
+        result
+    }
+}
+
+/// a transaction on the backend, remembering the keys written to
+struct RecordingTransaction<'a> {
+    txn: &'a dyn Transaction,
+    written: &'a RefCell<HashSet<(String, String)>>,
+}
+
+impl RecordingTransaction<'_> {
+    fn record(&self, table: &str, key: &str) {
+        self.written.borrow_mut().insert((table.into(), key.into()));
+    }
+}
+
+impl Transaction for RecordingTransaction<'_> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.txn.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: &str, value: Value) -> Result<Option<Value>, KvError> {
+        self.record(table, key);
+        self.txn.set(table, key, value)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.txn.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.record(table, key);
+        self.txn.del(table, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tempfile::tempdir;
+
+    use crate::storage::{
+        test_basi_interface, test_compare_and_swap, test_get_all, test_incr_keeps_ttl, test_prefix,
+        test_purge_expired, test_range, test_tables, test_transaction, test_ttl, EvictionPolicy,
+        MemoryBudget,
+    };
+
+    use super::*;
+
+    const WRITE_BACK: WriteMode = WriteMode::WriteBack(Duration::from_secs(60));
+
+    fn tiered(dir: impl AsRef<std::path::Path>, mode: WriteMode) -> TieredStorage {
+        TieredStorage::new(MemTable::new(), SledDb::new(dir), mode)
+    }
+
+    #[test]
+    fn tiered_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        test_basi_interface(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_basi_interface(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        test_get_all(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_get_all(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        test_ttl(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_ttl(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_purge_expired_should_work() {
+        let dir = tempdir().unwrap();
+        test_purge_expired(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_purge_expired(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_incr_should_keep_ttl() {
+        let dir = tempdir().unwrap();
+        test_incr_keeps_ttl(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_incr_keeps_ttl(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_compare_and_swap_should_work() {
+        let dir = tempdir().unwrap();
+        test_compare_and_swap(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_compare_and_swap(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_transaction_should_work() {
+        let dir = tempdir().unwrap();
+        test_transaction(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_transaction(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_range_should_work() {
+        let dir = tempdir().unwrap();
+        test_range(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_range(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_prefix_should_work() {
+        let dir = tempdir().unwrap();
+        test_prefix(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_prefix(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_tables_should_work() {
+        let dir = tempdir().unwrap();
+        test_tables(tiered(&dir, WriteMode::WriteThrough));
+        let dir = tempdir().unwrap();
+        test_tables(tiered(&dir, WRITE_BACK));
+    }
+
+    #[test]
+    fn tiered_write_through_should_write_to_backend() {
+        let dir = tempdir().unwrap();
+        let store = tiered(&dir, WriteMode::WriteThrough);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        assert_eq!(store.backend().get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.inner.cache.get("t1", "k1"), Ok(Some("v1".into())));
+
+        store.del("t1", "k1").unwrap();
+        assert_eq!(store.backend().get("t1", "k1"), Ok(None));
+        assert_eq!(store.inner.cache.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn tiered_should_drop_cached_key_before_writing_backend() {
+        for mode in [WriteMode::WriteThrough, WRITE_BACK] {
+            let dir = tempdir().unwrap();
+            let store = tiered(&dir, mode);
+            store.set("t1", "k1", 1.into()).unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+
+            let res = store.inner.write_backend("t1", "k1", |backend| {
+                assert_eq!(store.inner.cache.get("t1", "k1"), Ok(None));
+                backend.incr("t1", "k1", 1)
+            });
+            assert_eq!(res, Ok(2));
+            assert_eq!(store.get("t1", "k1"), Ok(Some(2.into())));
+        }
+    }
+
+    #[test]
+    fn tiered_should_load_missing_keys_from_backend() {
+        let dir = tempdir().unwrap();
+        let backend = SledDb::new(&dir);
+        backend
+            .set_ex("t1", "k1", "v1".into(), Duration::from_secs(60))
+            .unwrap();
+        let store = TieredStorage::new(MemTable::new(), backend, WriteMode::WriteThrough);
+
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.inner.cache.get("t1", "k1"), Ok(Some("v1".into())));
+        let ttl = store.inner.cache.ttl("t1", "k1").unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(50));
+
+        // writes other than set and del invalidate the key in the cache
+        store.backend().set("t1", "k1", "v2".into()).unwrap();
+        store.persist("t1", "k1").unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v2".into())));
+    }
+
+    #[test]
+    fn tiered_write_back_should_flush_periodically() {
+        let dir = tempdir().unwrap();
+        let mode = WriteMode::WriteBack(Duration::from_millis(10));
+        let store = tiered(&dir, mode);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+
+        let start = Instant::now();
+        while store.backend().get("t1", "k1").unwrap().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5), "nothing flushed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn tiered_write_back_should_flush_on_drop() {
+        let dir = tempdir().unwrap();
+        {
+            let store = tiered(&dir, WRITE_BACK);
+            store.set("t1", "k1", "v1".into()).unwrap();
+            store.set("t1", "k2", "v2".into()).unwrap();
+            store.del("t1", "k2").unwrap();
+            assert_eq!(store.backend().get("t1", "k1"), Ok(None));
+        }
+
+        let backend = SledDb::reopen(&dir);
+        assert_eq!(backend.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(backend.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn tiered_write_back_should_flush_past_max_pending() {
+        let dir = tempdir().unwrap();
+        let store = tiered(&dir, WRITE_BACK).with_max_pending(2);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.set("t2", "k1", "v1".into()).unwrap();
+        assert_eq!(store.backend().get("t1", "k1"), Ok(None));
+
+        store.del("t1", "k2").unwrap();
+        assert!(store.inner.pending.is_empty());
+        assert_eq!(store.backend().get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.backend().get("t2", "k1"), Ok(Some("v1".into())));
+    }
+
+    #[test]
+    fn tiered_write_back_scan_should_only_flush_its_table() {
+        let dir = tempdir().unwrap();
+        let store = tiered(&dir, WRITE_BACK);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.set("t2", "k1", "v1".into()).unwrap();
+
+        assert_eq!(
+            store.get_all("t1"),
+            Ok(vec![Kvpair::new("k1", "v1".into())])
+        );
+        assert_eq!(store.inner.pending.len(), 1);
+        assert_eq!(store.backend().get("t2", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn tiered_write_back_should_keep_writes_evicted_from_cache() {
+        let dir = tempdir().unwrap();
+        let cache = MemTable::new().with_budget(MemoryBudget::new(1, EvictionPolicy::Lru));
+        let store = TieredStorage::new(cache, SledDb::new(&dir), WRITE_BACK);
+        store.set("t1", "k1", "v1".into()).unwrap();
+        store.set("t1", "k2", "v2".into()).unwrap();
+        assert_eq!(store.inner.cache.get("t1", "k1"), Ok(None));
+
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.backend().get("t1", "k1"), Ok(Some("v1".into())));
+    }
+}
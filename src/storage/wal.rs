@@ -0,0 +1,508 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::{
+    storage::record::{decode_record, encode_record, read_log, Cursor},
+    KvError, Value,
+};
+
+/// the snapshot of all tables, the log segments from the one it names on are replayed after it
+const SNAPSHOT: &str = "snapshot";
+
+/// how many pairs a record of a snapshot holds
+const SNAPSHOT_CHUNK: usize = 1024;
+
+/// when the log is flushed to disk. Writes reach the operating system right away,
+/// so a crash of the process alone loses none of them with any policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// after every write, a write is durable once it returns
+    Always,
+    /// on a write at least this long after the last flush,
+    /// a crash of the machine loses the writes since the last flush
+    Interval(Duration),
+    /// never, the operating system writes the log out at its own pace
+    Never,
+}
+
+/// how a persistent memtable writes its log and snapshots
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalConfig {
+    pub sync: SyncPolicy,
+    /// a snapshot of all tables is taken on a write this long after the last one,
+    /// the log before it is deleted once it is written
+    pub snapshot_interval: Duration,
+}
+
+impl WalConfig {
+    pub fn new(sync: SyncPolicy, snapshot_interval: Duration) -> Self {
+        Self {
+            sync,
+            snapshot_interval,
+        }
+    }
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self::new(
+            SyncPolicy::Interval(Duration::from_secs(1)),
+            Duration::from_secs(300),
+        )
+    }
+}
+
+/// a change recorded in the log
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Op {
+    Put {
+        table: String,
+        key: String,
+        value: Value,
+        expire_at: Option<u64>,
+    },
+    Delete {
+        table: String,
+        key: String,
+    },
+    DropTable(String),
+    RenameTable {
+        from: String,
+        to: String,
+    },
+}
+
+/// a write ahead log split into segments, and the snapshot the log continues from.
+///
+/// Every segment is a sequence of records, every record holds the ops of one write, so a
+/// transaction is replayed either completely or not at all. A snapshot starts a new segment
+/// and is written from a copy of the tables taken at that point, once it is complete the
+/// segments before it are deleted.
+pub(crate) struct Wal {
+    dir: PathBuf,
+    config: WalConfig,
+    writer: Mutex<Writer>,
+    /// the segment the latest snapshot continues from, the lock serialises writing snapshots
+    snapshot: Mutex<u64>,
+}
+
+struct Writer {
+    segment: u64,
+    file: File,
+    size: u64,
+    last_sync: Instant,
+    last_snapshot: Instant,
+}
+
+/// the locked log, writes to the tables happen while holding it,
+/// so the log has them in the order they were applied
+pub(crate) struct WalGuard<'a> {
+    wal: &'a Wal,
+    writer: MutexGuard<'a, Writer>,
+}
+
+impl fmt::Debug for Wal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wal")
+            .field("dir", &self.dir)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl Wal {
+    /// open the log in dir, replaying the latest snapshot and the log after it with f
+    pub fn open(dir: &Path, config: WalConfig, mut f: impl FnMut(Op)) -> Result<Self, KvError> {
+        fs::create_dir_all(dir)?;
+
+        let start = load_snapshot(&dir.join(SNAPSHOT), &mut f)?;
+        let segments = segment_ids(dir)?;
+        for &id in &segments {
+            // left behind by a crash right after writing the snapshot, or by an idle run
+            let path = segment_path(dir, id);
+            if id < start || fs::metadata(&path)?.len() == 0 {
+                fs::remove_file(path)?;
+                continue;
+            }
+            // only the segment written last can end in a torn write
+            let torn_tail = Some(&id) == segments.last();
+            for ops in read_log(&path, torn_tail, |payload, _| decode_ops(payload))? {
+                ops.into_iter().for_each(&mut f);
+            }
+        }
+
+        let segment = segments.last().map_or(start, |id| start.max(id + 1));
+        let now = Instant::now();
+        let writer = Writer {
+            segment,
+            file: create_segment(dir, segment)?,
+            size: 0,
+            last_sync: now,
+            last_snapshot: now,
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            config,
+            writer: Mutex::new(writer),
+            snapshot: Mutex::new(start),
+        })
+    }
+
+    pub fn lock(&self) -> WalGuard<'_> {
+        WalGuard {
+            wal: self,
+            writer: self.writer.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// write the pairs as the snapshot the log continues from at segment, and delete the
+    /// segments before it. A snapshot older than the current one is discarded.
+    pub fn write_snapshot(
+        &self,
+        segment: u64,
+        pairs: impl Iterator<Item = Op>,
+    ) -> Result<(), KvError> {
+        let mut current = self.snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+        if *current >= segment {
+            return Ok(());
+        }
+
+        let path = self.dir.join(SNAPSHOT);
+        let tmp = path.with_extension("tmp");
+        let mut file = io::BufWriter::new(File::create(&tmp)?);
+        file.write_all(&encode_record(&segment.to_be_bytes()))?;
+        let mut chunk = Vec::with_capacity(SNAPSHOT_CHUNK);
+        let mut count = 0;
+        for op in pairs {
+            chunk.push(op);
+            if chunk.len() == SNAPSHOT_CHUNK {
+                file.write_all(&encode_record(&encode_ops(&chunk)?))?;
+                count += chunk.len();
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            file.write_all(&encode_record(&encode_ops(&chunk)?))?;
+            count += chunk.len();
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(tmp, path)?;
+        *current = segment;
+
+        for id in segment_ids(&self.dir)?
+            .into_iter()
+            .filter(|&id| id < segment)
+        {
+            fs::remove_file(segment_path(&self.dir, id))?;
+        }
+        info!("wrote a snapshot of {} pairs to {:?}", count, self.dir);
+        Ok(())
+    }
+}
+
+impl WalGuard<'_> {
+    /// append the ops of a write as a single record
+    pub fn append(&mut self, ops: &[Op]) -> Result<(), KvError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let record = encode_record(&encode_ops(ops)?);
+        let writer = &mut *self.writer;
+        if let Err(e) = writer.file.write_all(&record) {
+            // do not leave a partial record in front of the next one
+            let _ = writer.file.set_len(writer.size);
+            return Err(e.into());
+        }
+        writer.size += record.len() as u64;
+
+        match self.wal.config.sync {
+            SyncPolicy::Always => writer.file.sync_data()?,
+            SyncPolicy::Interval(interval) if writer.last_sync.elapsed() >= interval => {
+                writer.file.sync_data()?;
+                writer.last_sync = Instant::now();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// whether the snapshot interval has passed since the last snapshot was started
+    pub fn snapshot_due(&self) -> bool {
+        self.writer.last_snapshot.elapsed() >= self.wal.config.snapshot_interval
+    }
+
+    /// start a new segment for a snapshot taken now, returns its id
+    pub fn start_snapshot(&mut self) -> Result<u64, KvError> {
+        let writer = &mut *self.writer;
+        writer.file.sync_data()?;
+        let segment = writer.segment + 1;
+        writer.file = create_segment(&self.wal.dir, segment)?;
+        writer.segment = segment;
+        writer.size = 0;
+        writer.last_snapshot = Instant::now();
+        Ok(segment)
+    }
+}
+
+#[cfg(test)]
+impl WalGuard<'_> {
+    /// make every append fail from now on, as if the disk broke
+    pub(crate) fn fail_appends(&mut self) {
+        let path = segment_path(&self.wal.dir, self.writer.segment);
+        self.writer.file = File::open(path).unwrap();
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:010}.wal", id))
+}
+
+fn create_segment(dir: &Path, id: u64) -> Result<File, KvError> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, id))?)
+}
+
+/// ids of the log segments in the directory in order
+fn segment_ids(dir: &Path) -> Result<Vec<u64>, KvError> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let id = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".wal"))
+            .and_then(|id| id.parse().ok());
+        if let Some(id) = id {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// replay the snapshot with f, returns the segment the log continues from, 0 without a snapshot.
+/// A snapshot is renamed into place once complete, so unlike the log a damaged one is an error.
+fn load_snapshot(path: &Path, f: &mut impl FnMut(Op)) -> Result<u64, KvError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let corrupt = || KvError::Internal(format!("corrupt snapshot {:?}", path));
+
+    let (header, mut offset) = decode_record(&data, 0).ok_or_else(corrupt)?;
+    let segment = Cursor::new(header).u64().ok_or_else(corrupt)?;
+    while offset < data.len() {
+        let (payload, next) = decode_record(&data, offset).ok_or_else(corrupt)?;
+        decode_ops(payload)
+            .ok_or_else(corrupt)?
+            .into_iter()
+            .for_each(&mut *f);
+        offset = next;
+    }
+    Ok(segment)
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    buf.extend_from_slice(s);
+}
+
+fn get_str(buf: &mut Cursor<'_>) -> Option<String> {
+    let len = buf.u32()? as usize;
+    buf.string(len)
+}
+
+/// `tag | fields`, every string and value is length delimited, an expiration of 0 is none
+fn encode_ops(ops: &[Op]) -> Result<Vec<u8>, KvError> {
+    let mut buf = Vec::new();
+    for op in ops {
+        match op {
+            Op::Put {
+                table,
+                key,
+                value,
+                expire_at,
+            } => {
+                buf.push(0);
+                put_str(&mut buf, table.as_bytes());
+                put_str(&mut buf, key.as_bytes());
+                put_str(&mut buf, &Vec::<u8>::try_from(value.clone())?);
+                buf.extend_from_slice(&expire_at.unwrap_or(0).to_be_bytes());
+            }
+            Op::Delete { table, key } => {
+                buf.push(1);
+                put_str(&mut buf, table.as_bytes());
+                put_str(&mut buf, key.as_bytes());
+            }
+            Op::DropTable(table) => {
+                buf.push(2);
+                put_str(&mut buf, table.as_bytes());
+            }
+            Op::RenameTable { from, to } => {
+                buf.push(3);
+                put_str(&mut buf, from.as_bytes());
+                put_str(&mut buf, to.as_bytes());
+            }
+        }
+    }
+    Ok(buf)
+}
+
+/// the ops of a record, `None` if it is malformed
+fn decode_ops(payload: &[u8]) -> Option<Vec<Op>> {
+    let mut buf = Cursor::new(payload);
+    let mut ops = Vec::new();
+    while !buf.is_empty() {
+        let op = match buf.u8()? {
+            0 => {
+                let (table, key) = (get_str(&mut buf)?, get_str(&mut buf)?);
+                let len = buf.u32()? as usize;
+                let value = Value::try_from(buf.take(len)?).ok()?;
+                let expire_at = Some(buf.u64()?).filter(|&t| t != 0);
+                Op::Put {
+                    table,
+                    key,
+                    value,
+                    expire_at,
+                }
+            }
+            1 => Op::Delete {
+                table: get_str(&mut buf)?,
+                key: get_str(&mut buf)?,
+            },
+            2 => Op::DropTable(get_str(&mut buf)?),
+            3 => Op::RenameTable {
+                from: get_str(&mut buf)?,
+                to: get_str(&mut buf)?,
+            },
+            _ => return None,
+        };
+        ops.push(op);
+    }
+    Some(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn put(table: &str, key: &str, value: Value) -> Op {
+        Op::Put {
+            table: table.into(),
+            key: key.into(),
+            value,
+            expire_at: None,
+        }
+    }
+
+    fn replay(dir: &Path) -> Vec<Op> {
+        let mut ops = Vec::new();
+        Wal::open(dir, WalConfig::default(), |op| ops.push(op)).unwrap();
+        ops
+    }
+
+    #[test]
+    fn ops_should_round_trip() {
+        let ops = vec![
+            put("t1", "k1", "v1".into()),
+            Op::Put {
+                table: "t1".into(),
+                key: "k2".into(),
+                value: 1.5.into(),
+                expire_at: Some(42),
+            },
+            Op::Delete {
+                table: "t1".into(),
+                key: "k1".into(),
+            },
+            Op::DropTable("t2".into()),
+            Op::RenameTable {
+                from: "t1".into(),
+                to: "t3".into(),
+            },
+        ];
+        assert_eq!(decode_ops(&encode_ops(&ops).unwrap()), Some(ops));
+    }
+
+    #[test]
+    fn wal_should_replay_snapshot_and_log() {
+        let dir = tempdir().unwrap();
+        {
+            let wal = Wal::open(dir.path(), WalConfig::default(), |_| {}).unwrap();
+            wal.lock().append(&[put("t1", "k1", "v1".into())]).unwrap();
+            let segment = wal.lock().start_snapshot().unwrap();
+            wal.lock().append(&[put("t1", "k2", "v2".into())]).unwrap();
+            let pairs = vec![put("t1", "k1", "v1".into())];
+            wal.write_snapshot(segment, pairs.into_iter()).unwrap();
+            // an older snapshot finishing late must not replace the newer one
+            wal.write_snapshot(segment - 1, std::iter::empty()).unwrap();
+            assert_eq!(segment_ids(dir.path()).unwrap(), vec![segment]);
+        }
+
+        assert_eq!(
+            replay(dir.path()),
+            vec![put("t1", "k1", "v1".into()), put("t1", "k2", "v2".into())]
+        );
+    }
+
+    #[test]
+    fn wal_should_truncate_torn_tail() {
+        let dir = tempdir().unwrap();
+        {
+            let config = WalConfig::new(SyncPolicy::Always, Duration::from_secs(300));
+            let wal = Wal::open(dir.path(), config, |_| {}).unwrap();
+            wal.lock().append(&[put("t1", "k1", "v1".into())]).unwrap();
+            wal.lock().append(&[put("t1", "k2", "v2".into())]).unwrap();
+        }
+        let path = segment_path(dir.path(), 0);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        assert_eq!(replay(dir.path()), vec![put("t1", "k1", "v1".into())]);
+    }
+
+    #[test]
+    fn wal_should_reject_corrupt_record_before_tail() {
+        let dir = tempdir().unwrap();
+        {
+            let wal = Wal::open(dir.path(), WalConfig::default(), |_| {}).unwrap();
+            wal.lock().append(&[put("t1", "k1", "v1".into())]).unwrap();
+            wal.lock().append(&[put("t1", "k2", "v2".into())]).unwrap();
+        }
+        // flip the last byte of the first record, truncating there would lose the second
+        let path = segment_path(dir.path(), 0);
+        let mut data = fs::read(&path).unwrap();
+        let first = decode_record(&data, 0).unwrap().1;
+        data[first - 1] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let res = Wal::open(dir.path(), WalConfig::default(), |_| {});
+        assert!(matches!(res, Err(KvError::Internal(_))));
+    }
+
+    #[test]
+    fn wal_should_reject_corrupt_snapshot() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(SNAPSHOT), b"garbage").unwrap();
+        let res = Wal::open(dir.path(), WalConfig::default(), |_| {});
+        assert!(matches!(res, Err(KvError::Internal(_))));
+    }
+}